@@ -4,23 +4,25 @@ import yaml
 
 TEMPLATE = """
 // AUTOMATICALLY GENERATED, DO NOT EDIT!
-use std::mem::transmute;
 
 #[repr(u8)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Instruction {
 $instr
 }
 
-impl Into<u8> for Instruction {
-    fn into(self: Self) -> u8 {
-        self as u8
+impl From<Instruction> for u8 {
+    fn from(instruction: Instruction) -> u8 {
+        instruction as u8
     }
 }
 
 impl From<u8> for Instruction {
     fn from(value: u8) -> Instruction {
-        unsafe {transmute(value)}
+        match value {
+$code_to_instr
+            _ => Instruction::Invalid,
+        }
     }
 }
 """
@@ -31,14 +33,15 @@ if __name__ == "__main__":
         instructions = yaml.safe_load(f)
 
     instr = ""
-    instr_to_code = ""
     code_to_instr = ""
 
     for instruction in instructions:
         name = instruction["name"]
         code = hex(instruction["code"])
 
-        instr += f"    {name},\n"
+        instr += f"    {name} = {code},\n"
+        if name != "Invalid":
+            code_to_instr += f"            {code} => Instruction::{name},\n"
 
     t = Template(TEMPLATE)
 
@@ -1,4 +1,5 @@
 from string import Template
+import re
 import yaml
 
 match_template = """ 
@@ -9,9 +10,106 @@ match_template = """
             $reg3,
             $operand),"""
 
-R1 = "parse_register_name(line.tokens[{}].token.as_str())?"
+R1 = "parse_register(line.tokens[{}].token.as_str())?"
 OP = "parse_operand(line.tokens[{}].token.as_str())?"
 
+KIND_CHECKS = {
+    "reg": "is_register({})",
+    "op": "!is_register({})",
+    "regs": "is_register({})",
+    "args": "true",
+}
+
+PLACEHOLDER = re.compile(r"\{(\d+)(\.index)?\}")
+
+mnemonic_template = """
+    Mnemonic {
+        keyword: "$keyword",
+        operands: &[$operands],
+        opcode: $opcode,
+        description: "$description",
+    },"""
+
+
+def rust_string(text):
+    return text.replace("\\", "\\\\").replace('"', '\\"')
+
+
+def mnemonic(keyword, operands, opcode, description):
+    return Template(mnemonic_template).substitute(
+        keyword=keyword,
+        operands=", ".join('"{}"'.format(kind) for kind in operands),
+        opcode=opcode,
+        description=rust_string(description))
+
+
+# "LoadImmediate" -> "Load immediate"
+def describe_name(name):
+    words = re.findall(r"[A-Z][a-z]*", name)
+    return " ".join([words[0]] + [word.lower() for word in words[1:]])
+
+
+def pseudo_case(pseudo):
+    keyword = pseudo["keyword"]
+    kinds = pseudo["operands"]
+    variadic = len(kinds) - 1 if kinds and kinds[-1] in ("regs", "args") else None
+
+    if variadic is None:
+        guard = ["operands.len() == {}".format(len(kinds))]
+    else:
+        guard = ["operands.len() > {}".format(variadic)]
+
+    for index, kind in enumerate(kinds):
+        if index == variadic:
+            if KIND_CHECKS[kind] != "true":
+                guard.append("operands[{}..].iter().all(|o| {})".format(
+                    index, KIND_CHECKS[kind].format("o")))
+        else:
+            guard.append(KIND_CHECKS[kind].format("operands[{}]".format(index)))
+
+    body = ""
+    if "overwrites" in pseudo:
+        body += """
+            if let Some(register) = overwritten_argument(&operands[{}..]) {{
+                return Some(Err(format!(
+                    "Argument {{}} of {} is overwritten by an earlier argument", register
+                )));
+            }}""".format(pseudo["overwrites"], keyword)
+    for line in pseudo["expansion"]:
+        arguments = []
+
+        def replace(match):
+            index = int(match.group(1))
+            if index == variadic:
+                arguments.append("index" if match.group(2) else "operand")
+            else:
+                arguments.append("operands[{}]".format(index))
+            return "{}"
+
+        text = PLACEHOLDER.sub(replace, line)
+        push = "lines.push(format!(\"{}\", {}));".format(text, ", ".join(arguments)) \
+            if arguments else "lines.push(\"{}\".into());".format(text)
+
+        if variadic is not None and re.search(r"\{%d(\.index)?\}" % variadic, line):
+            loop = "(index, operand) in operands[{}..].iter().enumerate()" if "index" in arguments \
+                else "operand in operands[{}..].iter()"
+            body += """
+            for {} {{
+                {}
+            }}""".format(loop.format(variadic), push)
+        else:
+            body += """
+            {}""".format(push)
+
+    if pseudo.get("reverse", False):
+        body += """
+            lines.reverse();"""
+
+    return """
+        "{}" if {} => {{{}
+        }}""".format(keyword, " && ".join(guard), body)
+
+
 if __name__ == "__main__":
     with open("instructions.yaml") as f:
         instructions = yaml.safe_load(f)
@@ -19,6 +117,7 @@ if __name__ == "__main__":
     with open("src/assembler/matcher.rs.template") as f:
         template = f.read()
     cases = ""
+    mnemonics = ""
 
     for instruction in instructions:
         name = instruction["name"]
@@ -36,6 +135,16 @@ if __name__ == "__main__":
         t = Template(match_template)
         cases += t.substitute(**locals())
 
+        mnemonics += mnemonic(keyword, ["reg"] * regs + ["op"] * op,
+                              "Some(0x{:02X})".format(instruction["code"]), describe_name(name))
+
+    with open("pseudo_instructions.yaml") as f:
+        pseudo_instructions = yaml.safe_load(f)
+
+    pseudo_cases = "".join(pseudo_case(pseudo) for pseudo in pseudo_instructions)
+    mnemonics += "".join(mnemonic(pseudo["keyword"], pseudo["operands"], "None",
+                                  pseudo["description"]) for pseudo in pseudo_instructions)
+
     t = Template(template)
 
     with open("src/assembler/generated/matcher.rs", "w") as f:
@@ -0,0 +1,651 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::assembler::generated::matcher::MNEMONICS;
+use crate::assembler::parser::REGISTER_NAMES;
+use crate::common::encoding::DecodedInstruction;
+use crate::common::generated::instruction::Instruction::*;
+use crate::common::image::{Image, EXECUTE};
+use crate::common::json::Json;
+use crate::emulator::cpu::{registers, Register};
+
+// Control-flow graph of an executable. Code is decoded from the executable
+// segments and split into basic blocks at labels, branch targets and after
+// every instruction that transfers control. Functions are the entry point
+// and every call target, together with the blocks reachable from them
+// without following calls. Code that is not reachable this way forms
+// functions of its own.
+
+const PC: u8 = Register::PC as u8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Branch,
+    Jump,
+}
+
+impl EdgeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Branch => "branch",
+            EdgeKind::Jump => "jump",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub target: u32,
+    pub kind: EdgeKind,
+    // The edge closes a loop, its target is still being visited in a
+    // depth-first search from the start of the function
+    pub back_edge: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: u32,
+    pub instructions: Vec<DecodedInstruction>,
+    pub successors: Vec<Edge>,
+    // Target of the call ending the block
+    pub call: Option<u32>,
+}
+
+impl BasicBlock {
+    pub fn end(&self) -> u32 {
+        self.start + 8 * self.instructions.len() as u32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub start: u32,
+    // Start addresses of the blocks, in address order
+    pub blocks: Vec<u32>,
+    // Start addresses of the called functions
+    pub callees: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlFlowGraph {
+    pub entry: u32,
+    // Sorted by address
+    pub blocks: Vec<BasicBlock>,
+    pub functions: Vec<Function>,
+    labels: HashMap<u32, String>,
+}
+
+fn writes_pc(instruction: &DecodedInstruction) -> bool {
+    let i = instruction;
+    let (_, written) = registers(i.instruction_type, [i.reg_1, i.reg_2, i.reg_3]);
+    written.contains(&PC)
+}
+
+fn ends_block(instruction: &DecodedInstruction) -> bool {
+    match instruction.instruction_type {
+        Jump | Call | Return | BranchEqual | BranchNotEqual | Halt | Invalid => true,
+        // Any other instruction writing to PC, e.g. mov pc, r0
+        _ => writes_pc(instruction),
+    }
+}
+
+fn target(instruction: &DecodedInstruction) -> Option<u32> {
+    match instruction.instruction_type {
+        Jump | Call | BranchEqual | BranchNotEqual => Some(instruction.operand),
+        _ => None,
+    }
+}
+
+fn is_local(name: &str) -> bool {
+    name.contains(['.', '@'])
+}
+
+// The name of an address, preferring global labels over local ones
+fn labels(image: &Image) -> HashMap<u32, String> {
+    let mut labels: HashMap<u32, String> = HashMap::new();
+    for symbol in &image.symbols {
+        let replace = labels
+            .get(&symbol.address)
+            .is_none_or(|name| is_local(name) && !is_local(&symbol.name));
+        if replace {
+            labels.insert(symbol.address, symbol.name.clone());
+        }
+    }
+    labels
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl ControlFlowGraph {
+    pub fn build(image: &Image) -> ControlFlowGraph {
+        let mut code = HashMap::new();
+        let mut addresses = Vec::new();
+        for segment in &image.segments {
+            if segment.permissions & EXECUTE == 0 {
+                continue;
+            }
+            for (index, bytes) in segment.data.chunks_exact(8).enumerate() {
+                let address = segment.address + 8 * index as u32;
+                code.insert(address, DecodedInstruction::decode(bytes));
+                addresses.push(address);
+            }
+        }
+        addresses.sort_unstable();
+
+        let mut leaders = BTreeSet::new();
+        leaders.insert(image.entry);
+        leaders.extend(image.symbols.iter().map(|symbol| symbol.address));
+        for (&address, instruction) in &code {
+            if ends_block(instruction) {
+                leaders.insert(address + 8);
+            }
+            leaders.extend(target(instruction));
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut open = false;
+        for &address in &addresses {
+            let instruction = code[&address];
+            let continues = open
+                && !leaders.contains(&address)
+                && blocks.last().is_some_and(|block| block.end() == address);
+            if continues {
+                blocks.last_mut().unwrap().instructions.push(instruction);
+            } else {
+                blocks.push(BasicBlock {
+                    start: address,
+                    instructions: vec![instruction],
+                    successors: Vec::new(),
+                    call: None,
+                });
+            }
+            open = !ends_block(&instruction);
+        }
+
+        for block in &mut blocks {
+            let last = *block.instructions.last().unwrap();
+            let next = block.end();
+            let mut successors = Vec::new();
+            let mut fallthrough = true;
+
+            match last.instruction_type {
+                Jump => {
+                    successors.push((last.operand, EdgeKind::Jump));
+                    fallthrough = false;
+                }
+                BranchEqual | BranchNotEqual => successors.push((last.operand, EdgeKind::Branch)),
+                Call => block.call = Some(last.operand),
+                Return | Halt | Invalid => fallthrough = false,
+                // Indirect jumps have no known target
+                _ if writes_pc(&last) => fallthrough = false,
+                _ => {}
+            }
+            if fallthrough {
+                successors.push((next, EdgeKind::Fallthrough));
+            }
+
+            block.successors = successors
+                .into_iter()
+                .filter(|(target, _)| code.contains_key(target))
+                .map(|(target, kind)| Edge {
+                    target,
+                    kind,
+                    back_edge: false,
+                })
+                .collect();
+        }
+
+        let mut graph = ControlFlowGraph {
+            entry: image.entry,
+            blocks,
+            functions: Vec::new(),
+            labels: labels(image),
+        };
+        graph.find_functions();
+        graph
+    }
+
+    pub fn block(&self, address: u32) -> Option<&BasicBlock> {
+        self.blocks
+            .binary_search_by_key(&address, |block| block.start)
+            .ok()
+            .map(|index| &self.blocks[index])
+    }
+
+    pub fn function(&self, address: u32) -> Option<&Function> {
+        self.functions
+            .iter()
+            .find(|function| function.start == address)
+    }
+
+    pub fn label(&self, address: u32) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    fn index(&self, address: u32) -> Option<usize> {
+        self.blocks
+            .binary_search_by_key(&address, |block| block.start)
+            .ok()
+    }
+
+    fn find_functions(&mut self) {
+        let mut roots: Vec<u32> = vec![self.entry];
+        roots.extend(self.blocks.iter().filter_map(|block| block.call));
+        let mut starts: HashSet<usize> = roots.iter().filter_map(|&r| self.index(r)).collect();
+        let mut owned = vec![false; self.blocks.len()];
+        let mut functions = Vec::new();
+
+        // Unreachable code is added once all functions are known
+        roots.extend(self.blocks.iter().map(|block| block.start));
+
+        for root in roots {
+            let root = match self.index(root) {
+                Some(root) if !owned[root] => root,
+                _ => continue,
+            };
+            starts.insert(root);
+
+            let mut blocks = Vec::new();
+            let mut stack = vec![root];
+            owned[root] = true;
+            while let Some(index) = stack.pop() {
+                blocks.push(index);
+                for edge in &self.blocks[index].successors {
+                    if let Some(next) = self.index(edge.target) {
+                        if !owned[next] && !starts.contains(&next) {
+                            owned[next] = true;
+                            stack.push(next);
+                        }
+                    }
+                }
+            }
+            blocks.sort_unstable();
+
+            let start = self.blocks[root].start;
+            let callees: BTreeSet<u32> = blocks
+                .iter()
+                .filter_map(|&index| self.blocks[index].call)
+                .filter(|&target| self.index(target).is_some())
+                .collect();
+            functions.push(Function {
+                name: match self.label(start) {
+                    Some(label) => label.to_string(),
+                    None => format!("sub_{:08X}", start),
+                },
+                start,
+                blocks: blocks
+                    .iter()
+                    .map(|&index| self.blocks[index].start)
+                    .collect(),
+                callees: callees.into_iter().collect(),
+            });
+        }
+
+        functions.sort_by_key(|function| function.start);
+        for function in &functions {
+            self.mark_back_edges(function);
+        }
+        self.functions = functions;
+    }
+
+    fn mark_back_edges(&mut self, function: &Function) {
+        let members: HashSet<u32> = function.blocks.iter().copied().collect();
+        let mut visited = HashSet::new();
+        let mut on_stack = HashSet::new();
+        // Block and the index of the next successor to visit
+        let mut stack = vec![(function.start, 0)];
+        visited.insert(function.start);
+        on_stack.insert(function.start);
+
+        while let Some((address, edge)) = stack.pop() {
+            let index = self.index(address).unwrap();
+            let successors = &mut self.blocks[index].successors;
+            if edge == successors.len() {
+                on_stack.remove(&address);
+                continue;
+            }
+            stack.push((address, edge + 1));
+
+            let target = successors[edge].target;
+            if on_stack.contains(&target) {
+                successors[edge].back_edge = true;
+            } else if members.contains(&target) && visited.insert(target) {
+                on_stack.insert(target);
+                stack.push((target, 0));
+            }
+        }
+    }
+
+    fn function_name(&self, address: u32) -> String {
+        match self.function(address) {
+            Some(function) => function.name.clone(),
+            None => format!("0x{:08X}", address),
+        }
+    }
+
+    // Formats an instruction like the assembler source, with branch targets
+    // replaced by their labels
+    pub fn disassemble(&self, instruction: &DecodedInstruction) -> String {
+        let opcode = instruction.instruction_type as u8;
+        let mnemonic = match MNEMONICS.iter().find(|m| m.opcode == Some(opcode)) {
+            Some(mnemonic) => mnemonic,
+            None => return "invalid".into(),
+        };
+
+        let registers = [instruction.reg_1, instruction.reg_2, instruction.reg_3];
+        let mut operands = Vec::new();
+        for (index, kind) in mnemonic.operands.iter().enumerate() {
+            if *kind == "reg" {
+                let register = registers[index] as usize;
+                operands.push(match REGISTER_NAMES.get(register) {
+                    Some(name) => name.to_string(),
+                    None => format!("r{}", register),
+                });
+            } else if let Some(label) = target(instruction).and_then(|t| self.label(t)) {
+                operands.push(label.to_string());
+            } else if instruction.operand < 0x100 {
+                operands.push(instruction.operand.to_string());
+            } else {
+                operands.push(format!("0x{:X}", instruction.operand));
+            }
+        }
+
+        if operands.is_empty() {
+            mnemonic.keyword.to_string()
+        } else {
+            format!("{} {}", mnemonic.keyword, operands.join(", "))
+        }
+    }
+
+    // Graphviz graph with a cluster for every function. Calls are drawn as
+    // dashed edges to the start of the called function, loops in bold.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph cfg {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for function in &self.functions {
+            writeln!(dot, "    subgraph \"cluster_{:08X}\" {{", function.start).unwrap();
+            writeln!(dot, "        label=\"{}\";", escape_dot(&function.name)).unwrap();
+            for &start in &function.blocks {
+                let block = self.block(start).unwrap();
+                let mut label = String::new();
+                if let Some(name) = self.label(start) {
+                    write!(label, "{}:\\l", escape_dot(name)).unwrap();
+                }
+                for (index, instruction) in block.instructions.iter().enumerate() {
+                    let text = escape_dot(&self.disassemble(instruction));
+                    write!(label, "{:08X}  {}\\l", start + 8 * index as u32, text).unwrap();
+                }
+                writeln!(dot, "        \"{:08X}\" [label=\"{}\"];", start, label).unwrap();
+            }
+            dot.push_str("    }\n");
+        }
+
+        for block in &self.blocks {
+            for edge in &block.successors {
+                let mut attributes = Vec::new();
+                if edge.kind != EdgeKind::Fallthrough {
+                    attributes.push(format!("label=\"{}\"", edge.kind.name()));
+                }
+                if edge.back_edge {
+                    attributes.push("style=bold".to_string());
+                }
+                write!(
+                    dot,
+                    "    \"{:08X}\" -> \"{:08X}\"",
+                    block.start, edge.target
+                )
+                .unwrap();
+                if !attributes.is_empty() {
+                    write!(dot, " [{}]", attributes.join(", ")).unwrap();
+                }
+                dot.push_str(";\n");
+            }
+            if let Some(target) = block.call.filter(|&target| self.block(target).is_some()) {
+                writeln!(
+                    dot,
+                    "    \"{:08X}\" -> \"{:08X}\" [label=\"call\", style=dashed];",
+                    block.start, target
+                )
+                .unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn call_graph_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph calls {\n");
+        dot.push_str("    node [shape=box];\n");
+        for function in &self.functions {
+            writeln!(dot, "    \"{}\";", escape_dot(&function.name)).unwrap();
+        }
+        for function in &self.functions {
+            for &callee in &function.callees {
+                writeln!(
+                    dot,
+                    "    \"{}\" -> \"{}\";",
+                    escape_dot(&function.name),
+                    escape_dot(&self.function_name(callee))
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn function_json(&self, function: &Function, blocks: bool) -> Json {
+        let mut entries = vec![
+            ("name", function.name.as_str().into()),
+            ("start", function.start.into()),
+        ];
+        if blocks {
+            let starts = function.blocks.iter().map(|&start| start.into()).collect();
+            entries.push(("blocks", Json::Array(starts)));
+        }
+        let callees = function
+            .callees
+            .iter()
+            .map(|&callee| self.function_name(callee).into())
+            .collect();
+        entries.push(("calls", Json::Array(callees)));
+        Json::object(entries)
+    }
+
+    pub fn to_json(&self) -> Json {
+        let functions = self
+            .functions
+            .iter()
+            .map(|function| self.function_json(function, true))
+            .collect();
+
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| {
+                let instructions = block
+                    .instructions
+                    .iter()
+                    .map(|instruction| self.disassemble(instruction).into())
+                    .collect();
+                let successors = block
+                    .successors
+                    .iter()
+                    .map(|edge| {
+                        Json::object(vec![
+                            ("target", edge.target.into()),
+                            ("kind", edge.kind.name().into()),
+                            ("back_edge", edge.back_edge.into()),
+                        ])
+                    })
+                    .collect();
+                Json::object(vec![
+                    ("start", block.start.into()),
+                    ("end", block.end().into()),
+                    (
+                        "label",
+                        self.label(block.start).map_or(Json::Null, Json::from),
+                    ),
+                    ("instructions", Json::Array(instructions)),
+                    ("successors", Json::Array(successors)),
+                    ("call", block.call.map_or(Json::Null, Json::from)),
+                ])
+            })
+            .collect();
+
+        Json::object(vec![
+            ("entry", self.entry.into()),
+            ("functions", Json::Array(functions)),
+            ("blocks", Json::Array(blocks)),
+        ])
+    }
+
+    pub fn call_graph_json(&self) -> Json {
+        let functions = self
+            .functions
+            .iter()
+            .map(|function| self.function_json(function, false))
+            .collect();
+        Json::object(vec![("functions", Json::Array(functions))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::codegen::{assemble, assemble_file};
+    use crate::emulator::constants::MEMORY_START;
+    use std::io::Cursor;
+
+    fn build(source: &str) -> ControlFlowGraph {
+        ControlFlowGraph::build(&assemble(&mut Cursor::new(source)).unwrap())
+    }
+
+    fn successors(graph: &ControlFlowGraph, offset: u32) -> Vec<(u32, EdgeKind, bool)> {
+        graph
+            .block(MEMORY_START + offset)
+            .unwrap()
+            .successors
+            .iter()
+            .map(|edge| (edge.target - MEMORY_START, edge.kind, edge.back_edge))
+            .collect()
+    }
+
+    #[test]
+    fn test_blocks() {
+        let graph = ControlFlowGraph::build(&assemble_file("testdata/benchmark.asm").unwrap());
+
+        let starts: Vec<u32> = graph
+            .blocks
+            .iter()
+            .map(|b| b.start - MEMORY_START)
+            .collect();
+        assert_eq!(
+            starts,
+            vec![0x00, 0x10, 0x20, 0x28, 0x38, 0x40, 0x60, 0x70, 0x88]
+        );
+
+        assert_eq!(
+            successors(&graph, 0x00),
+            vec![(0x10, EdgeKind::Fallthrough, false)]
+        );
+        assert_eq!(
+            successors(&graph, 0x10),
+            vec![
+                (0x38, EdgeKind::Branch, false),
+                (0x20, EdgeKind::Fallthrough, false)
+            ]
+        );
+        assert_eq!(
+            graph.block(MEMORY_START + 0x20).unwrap().call,
+            Some(MEMORY_START + 0x40)
+        );
+        assert_eq!(successors(&graph, 0x28), vec![(0x10, EdgeKind::Jump, true)]);
+        assert!(successors(&graph, 0x38).is_empty());
+        assert_eq!(successors(&graph, 0x70), vec![(0x60, EdgeKind::Jump, true)]);
+        assert!(successors(&graph, 0x88).is_empty());
+    }
+
+    #[test]
+    fn test_functions() {
+        let graph = ControlFlowGraph::build(&assemble_file("testdata/benchmark.asm").unwrap());
+
+        let names: Vec<&str> = graph.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "print_alphabet"]);
+        assert_eq!(graph.functions[0].callees, vec![MEMORY_START + 0x40]);
+        assert_eq!(graph.functions[1].blocks.len(), 4);
+        assert!(graph.functions[1].callees.is_empty());
+
+        assert_eq!(
+            graph.call_graph_dot(),
+            "digraph calls {\n    node [shape=box];\n    \"main\";\n    \"print_alphabet\";\n    \"main\" -> \"print_alphabet\";\n}\n"
+        );
+        assert_eq!(
+            graph.call_graph_json().to_string(),
+            "{\"functions\":[{\"name\":\"main\",\"start\":1048576,\"calls\":[\"print_alphabet\"]},\
+             {\"name\":\"print_alphabet\",\"start\":1048640,\"calls\":[]}]}"
+        );
+    }
+
+    #[test]
+    fn test_unreachable_and_indirect() {
+        let graph = build(
+            "\
+ldi r0, target
+mov pc, r0
+halt
+target:
+halt
+",
+        );
+
+        assert!(successors(&graph, 0x00).is_empty());
+        let names: Vec<&str> = graph.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["sub_00100000", "sub_00100010", "target"]);
+
+        // Reading PC does not end a block, popping into it does
+        let graph = build(
+            "\
+cmp pc, r0
+st pc, r1
+pop pc
+halt
+",
+        );
+        assert_eq!(graph.block(MEMORY_START).unwrap().instructions.len(), 3);
+        assert!(successors(&graph, 0x00).is_empty());
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let graph = build("main:\nldi r1, 'A'\nstd r1, 0x1234\nbrne main\nadd r1, r2, sp\n");
+        let text: Vec<String> = graph
+            .blocks
+            .iter()
+            .flat_map(|block| block.instructions.iter())
+            .map(|instruction| graph.disassemble(instruction))
+            .collect();
+        assert_eq!(
+            text,
+            vec![
+                "ldi r1, 65",
+                "std r1, 0x1234",
+                "brne main",
+                "add r1, r2, sp"
+            ]
+        );
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("label=\"main:\\l00100000  ldi r1, 65\\l"));
+        assert!(dot.contains("\"00100000\" -> \"00100000\" [label=\"branch\", style=bold];"));
+    }
+}
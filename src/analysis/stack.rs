@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::analysis::cfg::{ControlFlowGraph, Function};
+use crate::common::encoding::DecodedInstruction;
+use crate::common::generated::instruction::Instruction::*;
+use crate::emulator::cpu::{registers, Register};
+
+// Worst-case stack usage. The frame of a function is the largest number of
+// bytes it pushes itself, following every path through its blocks. The
+// total adds the 4 byte return address and the total of the callee at every
+// call site. Recursion, indirect jumps, loops that grow the stack and writes
+// to the stack pointer other than push, pop, addi, subi and ldi make the
+// usage unbounded. ldi sp starts a new stack, like the usual
+// ldi sp, MEMORY_END at the entry point. A jump to the start of another
+// function is a tail call, a jump into the middle of one, like a shared
+// epilogue, continues the frame of the jumping function.
+
+const PC: u8 = Register::PC as u8;
+const SP: u8 = Register::SP as u8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackUsage {
+    pub name: String,
+    pub start: u32,
+    pub frame: Result<u32, String>,
+    pub total: Result<u32, String>,
+    // The callee on the worst path, if it goes through a call
+    pub callee: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackReport {
+    pub entry: u32,
+    // Sorted by address
+    pub functions: Vec<StackUsage>,
+}
+
+// Stack depth before a call or tail jump to another function
+struct CallSite {
+    depth: i64,
+    callee: u32,
+    // Tail jumps do not push a return address
+    tail: bool,
+}
+
+struct Frame {
+    size: Result<u32, String>,
+    calls: Vec<CallSite>,
+}
+
+// Applies an instruction to the stack depth
+fn step(address: u32, instruction: &DecodedInstruction, depth: i64) -> Result<i64, String> {
+    let i = instruction;
+    let (_, written) = registers(i.instruction_type, [i.reg_1, i.reg_2, i.reg_3]);
+    match i.instruction_type {
+        Push => Ok(depth + 4),
+        Pop if i.reg_1 != SP => Ok(depth - 4),
+        LoadImmediate if i.reg_1 == SP => Ok(0),
+        SubtractImmediate if i.reg_1 == SP && i.reg_2 == SP => Ok(depth + i64::from(i.operand)),
+        AddImmediate if i.reg_1 == SP && i.reg_2 == SP => Ok(depth - i64::from(i.operand)),
+        // Calls and returns are accounted for by the call sites
+        Jump | BranchEqual | BranchNotEqual | Call | Return => Ok(depth),
+        _ if written.contains(&SP) => Err(format!("stack pointer modified at 0x{:08X}", address)),
+        _ if written.contains(&PC) => Err(format!("indirect jump at 0x{:08X}", address)),
+        _ => Ok(depth),
+    }
+}
+
+fn frame(graph: &ControlFlowGraph, function: &Function) -> Frame {
+    let mut calls = Vec::new();
+    let mut depths: HashMap<u32, i64> = HashMap::new();
+    let mut max = 0;
+    let mut worklist = vec![(function.start, 0)];
+
+    while let Some((start, mut depth)) = worklist.pop() {
+        match depths.get(&start) {
+            Some(&known) if known >= depth => continue,
+            _ => depths.insert(start, depth),
+        };
+
+        let block = graph.block(start).unwrap();
+        for (index, instruction) in block.instructions.iter().enumerate() {
+            let address = start + 8 * index as u32;
+            depth = match step(address, instruction, depth) {
+                Ok(depth) => depth,
+                Err(reason) => {
+                    return Frame {
+                        size: Err(reason),
+                        calls,
+                    }
+                }
+            };
+            max = max.max(depth);
+        }
+
+        if let Some(callee) = block.call {
+            calls.push(CallSite {
+                depth,
+                callee,
+                tail: false,
+            });
+        }
+
+        for edge in &block.successors {
+            let owned = function.blocks.contains(&edge.target);
+            if !owned && graph.function(edge.target).is_some() {
+                calls.push(CallSite {
+                    depth,
+                    callee: edge.target,
+                    tail: true,
+                });
+            } else if (edge.back_edge || !owned)
+                && depths.get(&edge.target).is_some_and(|&d| d < depth)
+            {
+                return Frame {
+                    size: Err(format!("stack grows in loop at 0x{:08X}", edge.target)),
+                    calls,
+                };
+            } else {
+                worklist.push((edge.target, depth));
+            }
+        }
+    }
+
+    Frame {
+        size: Ok(max as u32),
+        calls,
+    }
+}
+
+struct Analysis<'a> {
+    graph: &'a ControlFlowGraph,
+    frames: HashMap<u32, Frame>,
+    totals: HashMap<u32, (Result<u32, String>, Option<u32>)>,
+    active: Vec<u32>,
+}
+
+impl<'a> Analysis<'a> {
+    fn name(&self, address: u32) -> String {
+        match self.graph.function(address) {
+            Some(function) => function.name.clone(),
+            None => format!("0x{:08X}", address),
+        }
+    }
+
+    fn total(&mut self, start: u32) -> Result<u32, String> {
+        if let Some((total, _)) = self.totals.get(&start) {
+            return total.clone();
+        }
+
+        if let Some(position) = self.active.iter().position(|&a| a == start) {
+            let mut cycle: Vec<String> = self.active[position..]
+                .iter()
+                .map(|&address| self.name(address))
+                .collect();
+            cycle.push(self.name(start));
+            return Err(format!("recursion {}", cycle.join(" -> ")));
+        }
+
+        let (size, calls) = match self.frames.get(&start) {
+            Some(frame) => (frame.size.clone(), &frame.calls),
+            None => return Err(format!("call to 0x{:08X} outside of code", start)),
+        };
+        let calls: Vec<(i64, u32, bool)> = calls
+            .iter()
+            .map(|call| (call.depth, call.callee, call.tail))
+            .collect();
+
+        self.active.push(start);
+        let mut result = size.map(|size| (size, None));
+        for (depth, callee, tail) in calls {
+            let total = self.total(callee);
+            result = match (result, total) {
+                (Ok((max, worst)), Ok(total)) => {
+                    let return_address = if tail { 0 } else { 4 };
+                    let usage = (depth + return_address + i64::from(total)).max(0) as u32;
+                    if usage > max {
+                        Ok((usage, Some(callee)))
+                    } else {
+                        Ok((max, worst))
+                    }
+                }
+                (Err(reason), _) | (_, Err(reason)) => Err(reason),
+            };
+        }
+        self.active.pop();
+
+        let (total, callee) = match result {
+            Ok((total, callee)) => (Ok(total), callee),
+            Err(reason) => (Err(reason), None),
+        };
+        self.totals.insert(start, (total.clone(), callee));
+        total
+    }
+}
+
+impl StackReport {
+    pub fn analyze(graph: &ControlFlowGraph) -> StackReport {
+        let mut analysis = Analysis {
+            graph,
+            frames: graph
+                .functions
+                .iter()
+                .map(|function| (function.start, frame(graph, function)))
+                .collect(),
+            totals: HashMap::new(),
+            active: Vec::new(),
+        };
+
+        let functions = graph
+            .functions
+            .iter()
+            .map(|function| {
+                let total = analysis.total(function.start);
+                StackUsage {
+                    name: function.name.clone(),
+                    start: function.start,
+                    frame: analysis.frames[&function.start].size.clone(),
+                    total,
+                    callee: analysis.totals[&function.start].1,
+                }
+            })
+            .collect();
+
+        StackReport {
+            entry: graph.entry,
+            functions,
+        }
+    }
+
+    pub fn function(&self, start: u32) -> Option<&StackUsage> {
+        self.functions.iter().find(|usage| usage.start == start)
+    }
+
+    // Worst-case usage of the program
+    pub fn total(&self) -> Result<u32, String> {
+        match self.function(self.entry) {
+            Some(usage) => usage.total.clone(),
+            None => Err(format!("entry point 0x{:08X} is not code", self.entry)),
+        }
+    }
+
+    // The call chain from the entry point with the largest usage
+    pub fn worst_path(&self) -> Vec<&StackUsage> {
+        let mut path: Vec<&StackUsage> = Vec::new();
+        let mut next = self.function(self.entry);
+        while let Some(usage) = next {
+            if path.iter().any(|u| u.start == usage.start) {
+                break;
+            }
+            path.push(usage);
+            next = usage.callee.and_then(|callee| self.function(callee));
+        }
+        path
+    }
+}
+
+fn format_usage(usage: &Result<u32, String>) -> String {
+    match usage {
+        Ok(bytes) => bytes.to_string(),
+        Err(_) => "unbounded".into(),
+    }
+}
+
+impl fmt::Display for StackReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .functions
+            .iter()
+            .map(|usage| usage.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("Function".len());
+
+        writeln!(
+            f,
+            "{:<width$}  {:>9}  {:>9}",
+            "Function",
+            "Frame",
+            "Total",
+            width = width
+        )?;
+        for usage in &self.functions {
+            write!(
+                f,
+                "{:<width$}  {:>9}  {:>9}",
+                usage.name,
+                format_usage(&usage.frame),
+                format_usage(&usage.total),
+                width = width
+            )?;
+            match &usage.total {
+                Err(reason) => writeln!(f, "  ({})", reason)?,
+                Ok(_) => writeln!(f)?,
+            }
+        }
+
+        writeln!(f)?;
+        match self.total() {
+            Ok(total) => {
+                let path: Vec<&str> = self
+                    .worst_path()
+                    .iter()
+                    .map(|usage| usage.name.as_str())
+                    .collect();
+                writeln!(f, "Maximum stack usage: {} bytes", total)?;
+                writeln!(f, "Worst path: {}", path.join(" -> "))
+            }
+            Err(reason) => writeln!(f, "Maximum stack usage: unbounded ({})", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::codegen::{assemble, assemble_file};
+    use std::io::Cursor;
+
+    fn analyze(source: &str) -> StackReport {
+        let image = assemble(&mut Cursor::new(source)).unwrap();
+        StackReport::analyze(&ControlFlowGraph::build(&image))
+    }
+
+    fn totals(report: &StackReport) -> Vec<(&str, Result<u32, String>)> {
+        report
+            .functions
+            .iter()
+            .map(|usage| (usage.name.as_str(), usage.total.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_testdata() {
+        let image = assemble_file("testdata/benchmark.asm").unwrap();
+        let report = StackReport::analyze(&ControlFlowGraph::build(&image));
+
+        assert_eq!(
+            totals(&report),
+            vec![("main", Ok(12)), ("print_alphabet", Ok(8))]
+        );
+        assert_eq!(report.functions[1].frame, Ok(8));
+        assert_eq!(report.total(), Ok(12));
+
+        let path: Vec<&str> = report
+            .worst_path()
+            .iter()
+            .map(|u| u.name.as_str())
+            .collect();
+        assert_eq!(path, vec!["main", "print_alphabet"]);
+    }
+
+    #[test]
+    fn test_call_chain() {
+        let report = analyze(
+            "\
+main:
+    ldi sp, MEMORY_END
+    push r0
+    call a
+    pop r0
+    call b
+    call c
+    call d
+    halt
+a:
+    push r1
+    call b
+shared:
+    pop r1
+    ret
+b:
+    subi sp, sp, 16
+    addi sp, sp, 16
+    jmp c
+c:
+    push r2
+    pop r2
+    ret
+d:
+    push r1
+    push r2
+    pop r2
+    jmp shared
+",
+        );
+
+        assert_eq!(
+            totals(&report),
+            vec![
+                ("main", Ok(32)),
+                ("a", Ok(24)),
+                ("b", Ok(16)),
+                ("c", Ok(4)),
+                ("d", Ok(8)),
+            ]
+        );
+        assert_eq!(report.function(0x0010_0090).unwrap().frame, Ok(8));
+        let path: Vec<&str> = report
+            .worst_path()
+            .iter()
+            .map(|u| u.name.as_str())
+            .collect();
+        assert_eq!(path, vec!["main", "a", "b"]);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let report = analyze(
+            "\
+main:
+    call even
+    call grow
+    halt
+even:
+    cmpi r0, 0
+    breq .done
+    dec r0
+    call odd
+.done:
+    ret
+odd:
+    call even
+    ret
+grow:
+    push r0
+    jmp grow
+",
+        );
+
+        assert_eq!(
+            totals(&report),
+            vec![
+                ("main", Err("recursion even -> odd -> even".into())),
+                ("even", Err("recursion even -> odd -> even".into())),
+                ("odd", Err("recursion even -> odd -> even".into())),
+                ("grow", Err("stack grows in loop at 0x00100050".into())),
+            ]
+        );
+        assert!(report
+            .to_string()
+            .contains("Maximum stack usage: unbounded"));
+
+        let report = analyze("ldi r0, 8\nmov pc, r0\n");
+        assert_eq!(report.total(), Err("indirect jump at 0x00100008".into()));
+
+        let report = analyze("mov sp, r0\nret\n");
+        assert_eq!(
+            report.total(),
+            Err("stack pointer modified at 0x00100000".into())
+        );
+    }
+}
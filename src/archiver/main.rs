@@ -0,0 +1,114 @@
+extern crate mycpu;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use mycpu::common::archive::{is_valid_member_name, Archive, Member};
+use mycpu::common::object::{Binding, ObjectFile};
+
+const USAGE: &str = "Usage: ar r|d|t|x LIBRARY [FILE|MEMBER]...\n\
+    \x20 r  add object files, replacing members with the same name\n\
+    \x20 d  delete members\n\
+    \x20 t  list members and the symbols they define\n\
+    \x20 x  extract members, all if none are given";
+
+fn with_path(path: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+fn read_archive(path: &str) -> io::Result<Archive> {
+    let bytes = fs::read(path).map_err(|e| with_path(path, e))?;
+    Archive::from_bytes(&bytes).map_err(|e| with_path(path, e))
+}
+
+fn write_archive(path: &str, archive: &Archive) -> io::Result<()> {
+    fs::write(path, archive.to_bytes()).map_err(|e| with_path(path, e))
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No member named '{}'", name),
+    )
+}
+
+fn run(command: &str, library: &str, names: &[String]) -> io::Result<()> {
+    match command {
+        "r" => {
+            let mut archive = if Path::new(library).exists() {
+                read_archive(library)?
+            } else {
+                Archive::default()
+            };
+
+            for path in names {
+                let bytes = fs::read(path).map_err(|e| with_path(path, e))?;
+                let object = ObjectFile::from_bytes(&bytes).map_err(|e| with_path(path, e))?;
+                let name = Path::new(path)
+                    .file_name()
+                    .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+                if !is_valid_member_name(&name) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid member name '{}'", name),
+                    ));
+                }
+                archive.insert(Member { name, object });
+            }
+
+            write_archive(library, &archive)
+        }
+        "d" => {
+            let mut archive = read_archive(library)?;
+            for name in names {
+                archive.remove(name).ok_or_else(|| not_found(name))?;
+            }
+            write_archive(library, &archive)
+        }
+        "t" => {
+            for member in read_archive(library)?.members {
+                println!("{}", member.name);
+                for symbol in &member.object.symbols {
+                    if symbol.binding == Binding::Global {
+                        println!("    {}", symbol.name);
+                    }
+                }
+            }
+            Ok(())
+        }
+        "x" => {
+            let archive = read_archive(library)?;
+            for name in names {
+                if !archive.members.iter().any(|member| &member.name == name) {
+                    return Err(not_found(name));
+                }
+            }
+
+            for member in &archive.members {
+                if names.is_empty() || names.contains(&member.name) {
+                    fs::write(&member.name, member.object.to_bytes())
+                        .map_err(|e| with_path(&member.name, e))?;
+                }
+            }
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.len() < 2 || !["r", "d", "t", "x"].contains(&args[0].as_str()) {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+
+    if let Err(e) = run(&args[0], &args[1], &args[2..]) {
+        eprintln!("IOError: {}", e);
+        process::exit(1);
+    }
+}
@@ -1,65 +1,794 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::Write;
-use std::u32;
-
-use crate::assembler::parser::{parse, Op, ParsedLine};
-use crate::assembler::tokenizer;
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use crate::assembler::error::{AsmError, Result};
+use crate::assembler::expression::{EvalError, Expr, Value};
+use crate::assembler::include;
+use crate::assembler::labels;
+use crate::assembler::listing::{self, Listing};
+use crate::assembler::macros;
+use crate::assembler::parser::{parse, Op, ParsedLine, Section, Statement, SECTIONS};
+use crate::assembler::tokenizer::{tokenize, Location, SourceFile, TokenizedLine};
+use crate::common::debug::DebugInfo;
 use crate::common::encoding::DecodedInstruction;
+use crate::common::generated::instruction::Instruction::NOp;
+use crate::common::image::{Image, ImageSymbol, Segment};
+use crate::common::object::{Binding, ObjectFile, ObjectSection, Relocation, Symbol};
 use crate::emulator::constants::*;
 
-pub fn assemble_file(path: &str) -> Option<Vec<u8>> {
-    let mut reader = BufReader::new(File::open(path).ok()?);
-    let tokens = tokenizer::tokenize(&mut reader);
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // Directories searched for included files
+    pub include_paths: Vec<PathBuf>,
+    // Base addresses of sections. Sections without a base address follow
+    // the previous section.
+    pub section_bases: Vec<(Section, u32)>,
+    // Symbol where execution starts, the start of .text by default
+    pub entry: Option<String>,
+}
+
+pub fn assemble_file(path: &str) -> Result<Image> {
+    assemble_files(&[path], &Options::default())
+}
+
+// Assembles several source files into one image, in the given order.
+// Included files are searched in the directory of the including file first
+// and then in the include paths.
+pub fn assemble_files(paths: &[&str], options: &Options) -> Result<Image> {
+    let output = generate(&parse_files(paths, options)?, options, false)?;
+    Ok(output.into_image())
+}
+
+// Assembles statements returned by parse_files into an image, together with
+// its listing
+pub fn assemble_statements(
+    statements: &[Statement],
+    options: &Options,
+) -> Result<(Image, Listing)> {
+    let output = generate(statements, options, false)?;
+    let listing = output.listing();
+    Ok((output.into_image(), listing))
+}
+
+// Like assemble_statements for a relocatable object file
+pub fn assemble_object_statements(
+    statements: &[Statement],
+    options: &Options,
+) -> Result<(ObjectFile, Listing)> {
+    let output = generate(statements, options, true)?;
+    let listing = output.listing();
+    Ok((output.into_object(), listing))
+}
+
+pub fn assemble(reader: &mut dyn BufRead) -> Result<Image> {
+    let file = SourceFile::new("<input>");
+    let lines = include::expand(tokenize(reader, &file), &[])?;
+    Ok(generate(&parse_lines(lines)?, &Options::default(), false)?.into_image())
+}
+
+pub fn assemble_object(reader: &mut dyn BufRead) -> Result<ObjectFile> {
+    let file = SourceFile::new("<input>");
+    let lines = include::expand(tokenize(reader, &file), &[])?;
+    Ok(generate(&parse_lines(lines)?, &Options::default(), true)?.into_object())
+}
+
+// Like assemble_object for a file that may not be saved yet, included files
+// are searched relative to the path.
+pub fn assemble_object_source(
+    path: &str,
+    reader: &mut dyn BufRead,
+    options: &Options,
+) -> Result<ObjectFile> {
+    let file = SourceFile::new(path);
+    let lines = include::expand(tokenize(reader, &file), &options.include_paths)?;
+    Ok(generate(&parse_lines(lines)?, options, true)?.into_object())
+}
+
+// Statements of the files after macro expansion, with local and anonymous
+// labels renamed, as checked by the linter
+pub fn parse_files(paths: &[&str], options: &Options) -> Result<Vec<Statement>> {
+    parse_lines(load_files(paths, options)?)
+}
+
+pub fn parse_source(
+    path: &str,
+    reader: &mut dyn BufRead,
+    options: &Options,
+) -> Result<Vec<Statement>> {
+    let file = SourceFile::new(path);
+    parse_lines(include::expand(
+        tokenize(reader, &file),
+        &options.include_paths,
+    )?)
+}
+
+fn parse_lines(lines: Vec<TokenizedLine>) -> Result<Vec<Statement>> {
+    let tokens = macros::expand(lines)?;
+
+    let mut parsed = parse(tokens)?;
+    labels::resolve(&mut parsed)?;
+    Ok(parsed)
+}
+
+fn load_files(paths: &[&str], options: &Options) -> Result<Vec<TokenizedLine>> {
+    let mut lines = Vec::new();
+    for path in paths {
+        lines.extend(include::load(
+            &SourceFile::new(path),
+            &options.include_paths,
+        )?);
+    }
+    Ok(lines)
+}
+
+// Section and address of a statement
+type Position = (Section, u32);
+
+struct SectionLayout {
+    section: Section,
+    start: u32,
+    end: u32,
+    alignment: u32,
+}
+
+struct Output {
+    entry: u32,
+    layout: Vec<SectionLayout>,
+    data: HashMap<Section, Vec<u8>>,
+    symbols: Vec<Symbol>,
+    relocations: Vec<Relocation>,
+    listing: Vec<listing::Entry>,
+}
+
+impl Output {
+    fn into_image(self) -> Image {
+        let mut data = self.data;
+        let segments: Vec<Segment> = self
+            .layout
+            .iter()
+            .filter(|l| l.end > l.start)
+            .map(|l| Segment {
+                name: l.section.name().into(),
+                address: l.start,
+                data: data.remove(&l.section).unwrap_or_default(),
+                size: l.end - l.start,
+                permissions: l.section.permissions(),
+            })
+            .collect();
+
+        let layout = &self.layout;
+        let symbols: Vec<ImageSymbol> = self
+            .symbols
+            .iter()
+            .filter_map(|symbol| {
+                let section = Section::from_name(symbol.section.as_ref()?)?;
+                Some(ImageSymbol {
+                    name: symbol.name.clone(),
+                    address: section_start(layout, section) + symbol.value,
+                })
+            })
+            .collect();
+
+        let debug = debug_info(&self.listing, &segments, &symbols);
+        Image {
+            entry: self.entry,
+            segments,
+            symbols,
+            debug: Some(debug.to_bytes()),
+            stack: None,
+        }
+    }
+
+    fn listing(&self) -> Listing {
+        let layout = &self.layout;
+        let symbols = self
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.binding != Binding::Undefined)
+            .map(|symbol| {
+                let base = symbol
+                    .section
+                    .as_ref()
+                    .and_then(|name| Section::from_name(name))
+                    .map_or(0, |section| section_start(layout, section));
+                (symbol.name.clone(), base + symbol.value)
+            })
+            .collect();
 
-    let parsed = parse(tokens)?;
+        Listing {
+            entries: self.listing.clone(),
+            symbols,
+        }
+    }
 
-    let mut lookup = HashMap::new();
-    let mut counter = MEMORY_START;
+    fn into_object(self) -> ObjectFile {
+        let mut data = self.data;
+        let sections = self
+            .layout
+            .iter()
+            .filter(|l| l.end > l.start)
+            .map(|l| ObjectSection {
+                name: l.section.name().into(),
+                alignment: l.alignment,
+                size: l.end - l.start,
+                data: data.remove(&l.section).unwrap_or_default(),
+            })
+            .collect();
+
+        ObjectFile {
+            sections,
+            symbols: self.symbols,
+            relocations: self.relocations,
+        }
+    }
+}
+
+// Line table of all statements that emitted bytes, and the ranges of
+// the labels
+fn debug_info(
+    listing: &[listing::Entry],
+    segments: &[Segment],
+    symbols: &[ImageSymbol],
+) -> DebugInfo {
+    let mut debug = DebugInfo::default();
+    for entry in listing.iter().filter(|entry| !entry.bytes.is_empty()) {
+        let line = &entry.line;
+        debug.add_line(
+            entry.address,
+            entry.bytes.len() as u32,
+            &line.file.path,
+            line.line_number as u32,
+        );
+    }
+
+    debug.add_symbol_ranges(segments, symbols);
+    debug
+}
+
+// Assembles the lines into sections. For object files every section starts
+// at address 0 and values depending on an address are stored as relocations.
+fn generate(parsed: &[Statement], options: &Options, relocatable: bool) -> Result<Output> {
+    let (positions, layout) = layout(parsed, options, relocatable)?;
+
+    let mut symbols = builtin_symbols(&layout, relocatable);
+    let mut constants = HashMap::new();
+    let mut variables = HashSet::new();
+    let mut globals = Vec::new();
+    let mut externs = Vec::new();
 
     // Build lookup table
-    for instr in &parsed {
-        match instr {
-            ParsedLine::Instruction(_dec) => counter += 8,
-            ParsedLine::Label(name) => {
-                if lookup.insert(name.clone(), counter).is_some() {
-                    return None;
-                }
+    for (statement, (section, address)) in parsed.iter().zip(&positions) {
+        let location = statement.line.location();
+        let name = match &statement.parsed {
+            ParsedLine::Variable(name, _) if variables.contains(name.as_str()) => continue,
+            ParsedLine::Label(name)
+            | ParsedLine::Constant(name, _)
+            | ParsedLine::Variable(name, _) => name,
+            ParsedLine::Global(names) => {
+                globals.extend(names.iter().map(|name| (location.clone(), name)));
+                continue;
             }
+            ParsedLine::Extern(names) => {
+                externs.extend(names.iter().map(String::as_str));
+                continue;
+            }
+            _ => continue,
+        };
+
+        if symbols.contains_key(name)
+            || constants.contains_key(name.as_str())
+            || variables.contains(name.as_str())
+        {
+            return Err(AsmError::DuplicateSymbol(location, name.clone()));
+        }
+
+        match &statement.parsed {
+            ParsedLine::Label(_) if relocatable => {
+                symbols.insert(
+                    name.clone(),
+                    Value::relative(section.name(), i64::from(*address)),
+                );
+            }
+            ParsedLine::Label(_) => {
+                symbols.insert(name.clone(), Value::absolute(i64::from(*address)));
+            }
+            ParsedLine::Constant(_, value) => {
+                constants.insert(name.as_str(), (location, value));
+            }
+            _ => {
+                variables.insert(name.as_str());
+            }
+        }
+    }
+
+    // External symbols are only known to the linker, in executables they
+    // have to be defined by one of the source files.
+    if relocatable {
+        for name in &externs {
+            if !symbols.contains_key(*name) && !constants.contains_key(name) {
+                symbols.insert(name.to_string(), Value::relative(name, 0));
+            }
+        }
+    }
+
+    for statement in parsed {
+        if let ParsedLine::Constant(name, _) = &statement.parsed {
+            resolve_constant(name, &constants, &mut symbols, &mut Vec::new())?;
         }
     }
 
-    lookup.insert(String::from("PROGRAM_START"), MEMORY_START);
-    lookup.insert(String::from("PROGRAM_END"), counter);
-    lookup.insert(String::from("MEMORY_END"), align_down(MEMORY_END));
-    lookup.insert(String::from("CONSOLEIO_START"), CONSOLEIO_START);
+    let mut data: HashMap<Section, Vec<u8>> = HashMap::new();
+    let mut relocations = Vec::new();
+    let mut listing = Vec::new();
 
-    let mut bytes = Vec::new();
+    for (statement, (section, address)) in parsed.iter().zip(&positions) {
+        let section = *section;
+        let location = statement.line.location();
+        let bytes = data.entry(section).or_default();
+        let start = bytes.len();
 
-    for instr in &parsed {
-        match instr {
+        // Offset of the statement within its section
+        let offset = address - section_start(&layout, section);
+
+        match &statement.parsed {
             ParsedLine::Instruction(dec) => {
-                let a: u32 = match &dec.op {
-                    Op::Label(name) => *(lookup.get(name)?),
-                    Op::Number(number) => *number,
+                let value = resolve_operand(&dec.op, &symbols, &location)?;
+                let a = relocate(value, section, offset + 4, &mut relocations, &location)?;
+
+                let instr =
+                    DecodedInstruction::new(dec.instruction, dec.reg1, dec.reg2, dec.reg3, a);
+
+                bytes.extend_from_slice(&instr.encode());
+            }
+            // Variables take the value of the last preceding definition
+            ParsedLine::Variable(name, value) => {
+                let value = evaluate(value, &symbols, &location)?;
+                symbols.insert(name.clone(), value);
+            }
+            // Uninitialized data is not stored in the image
+            ParsedLine::Align(_) | ParsedLine::Space(_) if section == Section::BSS => {}
+            ParsedLine::Align(alignment) => {
+                let padding = padding(*address, *alignment);
+                if section == Section::Text {
+                    bytes.resize(bytes.len() + (padding % 8) as usize, 0);
+                    for _ in 0..padding / 8 {
+                        bytes.extend_from_slice(&DecodedInstruction::new(NOp, 0, 0, 0, 0).encode());
+                    }
+                } else {
+                    bytes.resize(bytes.len() + padding as usize, 0);
+                }
+            }
+            ParsedLine::Space(size) => bytes.resize(bytes.len() + *size as usize, 0),
+            ParsedLine::Ascii(string) => bytes.extend_from_slice(string),
+            ParsedLine::Bytes(values) => {
+                for value in values {
+                    let value = evaluate_absolute(value, &symbols, &location)?;
+                    bytes.push(fit(value, 8, &location)? as u8);
+                }
+            }
+            ParsedLine::Words(values) => {
+                for (index, value) in values.iter().enumerate() {
+                    let value = evaluate(value, &symbols, &location)?;
+                    let word_offset = offset + 4 * index as u32;
+                    let word = relocate(value, section, word_offset, &mut relocations, &location)?;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            ParsedLine::Label(_)
+            | ParsedLine::Constant(_, _)
+            | ParsedLine::Section(_)
+            | ParsedLine::Global(_)
+            | ParsedLine::Extern(_) => {}
+        };
+
+        listing.push(listing::Entry {
+            line: statement.line.clone(),
+            address: *address,
+            bytes: bytes[start..].to_vec(),
+        });
+    }
+
+    let entry = match &options.entry {
+        _ if relocatable => 0,
+        None => section_start(&layout, Section::Text),
+        Some(name) => match symbols.get(name) {
+            Some(Value { base: None, offset }) => *offset as u32,
+            _ => {
+                return Err(AsmError::Layout(format!(
+                    "Entry point '{}' is not defined",
+                    name
+                )))
+            }
+        },
+    };
+
+    let symbols = symbol_table(parsed, &positions, &layout, &symbols, &globals, &externs)?;
+
+    Ok(Output {
+        entry,
+        layout,
+        data,
+        symbols,
+        relocations,
+        listing,
+    })
+}
+
+// Returns the value to store in a 32 bit field. Values depending on the
+// address of a symbol are stored as 0 and resolved by a relocation.
+fn relocate(
+    value: Value,
+    section: Section,
+    offset: u32,
+    relocations: &mut Vec<Relocation>,
+    location: &Location,
+) -> Result<u32> {
+    match value.base {
+        None => fit(value.offset, 32, location),
+        Some(symbol) => {
+            relocations.push(Relocation {
+                section: section.name().into(),
+                offset,
+                symbol,
+                addend: value.offset,
+            });
+            Ok(0)
+        }
+    }
+}
+
+fn section_start(layout: &[SectionLayout], section: Section) -> u32 {
+    layout
+        .iter()
+        .find(|l| l.section == section)
+        .map_or(0, |l| l.start)
+}
+
+// Labels, constants and external symbols of the object file. Values are
+// relative to the start of their section. Only global and undefined symbols
+// are used by the linker, local symbols are kept for debugging.
+fn symbol_table(
+    parsed: &[Statement],
+    positions: &[Position],
+    layout: &[SectionLayout],
+    symbols: &HashMap<String, Value>,
+    globals: &[(Location, &String)],
+    externs: &[&str],
+) -> Result<Vec<Symbol>> {
+    let mut table = Vec::new();
+    let mut defined = HashSet::new();
+
+    let global_names: HashSet<&str> = globals.iter().map(|(_, name)| name.as_str()).collect();
+    let binding = |name: &str| {
+        if global_names.contains(name) {
+            Binding::Global
+        } else {
+            Binding::Local
+        }
+    };
+
+    for (statement, (section, address)) in parsed.iter().zip(positions) {
+        let location = statement.line.location();
+
+        match &statement.parsed {
+            ParsedLine::Label(name) => {
+                defined.insert(name.as_str());
+                table.push(Symbol {
+                    name: name.clone(),
+                    binding: binding(name),
+                    section: Some(section.name().into()),
+                    value: address - section_start(layout, *section),
+                });
+            }
+            ParsedLine::Constant(name, _) => {
+                let value = &symbols[name.as_str()];
+                let local = binding(name) == Binding::Local;
+
+                // Constants relative to external symbols or out of range can
+                // not be represented in the symbol table
+                let (section, offset) = match &value.base {
+                    None => (None, value.offset),
+                    Some(base) => match Section::from_name(base) {
+                        Some(section) => (
+                            Some(base.clone()),
+                            value.offset - i64::from(section_start(layout, section)),
+                        ),
+                        None if local => continue,
+                        None => {
+                            return Err(AsmError::Expression(
+                                location,
+                                EvalError::NotRelocatable.to_string(),
+                            ))
+                        }
+                    },
+                };
+                let value = match fit(offset, 32, &location) {
+                    Ok(value) => value,
+                    Err(_) if local => continue,
+                    Err(e) => return Err(e),
                 };
 
-                let instr = DecodedInstruction::new(
-                    dec.instruction.clone(),
-                    dec.reg1,
-                    dec.reg2,
-                    dec.reg3,
-                    a,
-                );
+                defined.insert(name.as_str());
+                table.push(Symbol {
+                    name: name.clone(),
+                    binding: binding(name),
+                    section,
+                    value,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    for (location, name) in globals {
+        if !defined.contains(name.as_str()) {
+            return Err(AsmError::UndefinedSymbol(location.clone(), (*name).clone()));
+        }
+    }
+
+    for name in externs {
+        if !defined.contains(name) {
+            defined.insert(name);
+            table.push(Symbol {
+                name: name.to_string(),
+                binding: Binding::Undefined,
+                section: None,
+                value: 0,
+            });
+        }
+    }
+
+    Ok(table)
+}
+
+fn statement_size(statement: &Statement, section: Section, address: u32) -> Result<u32> {
+    let size = match &statement.parsed {
+        ParsedLine::Instruction(_) => 8,
+        ParsedLine::Bytes(values) => values.len() as u32,
+        ParsedLine::Words(values) => 4 * values.len() as u32,
+        ParsedLine::Ascii(string) => string.len() as u32,
+        ParsedLine::Space(size) => return Ok(*size),
+        ParsedLine::Align(alignment) => return Ok(padding(address, *alignment)),
+        _ => return Ok(0),
+    };
+
+    if section == Section::BSS {
+        return Err(AsmError::Syntax(
+            statement.line.location(),
+            "Only uninitialized data is allowed in .bss".into(),
+        ));
+    }
+
+    Ok(size)
+}
 
-                bytes.write(&instr.encode()).ok()?;
+// Computes the section and address of every statement. Sections are laid
+// out in a fixed order; each section starts at its configured base address,
+// or after the previous section aligned to the instruction size. In object
+// files every section starts at 0.
+fn layout(
+    parsed: &[Statement],
+    options: &Options,
+    relocatable: bool,
+) -> Result<(Vec<Position>, Vec<SectionLayout>)> {
+    let mut section = Section::Text;
+    let mut positions: Vec<Position> = parsed
+        .iter()
+        .map(|statement| {
+            if let ParsedLine::Section(next) = statement.parsed {
+                section = next;
             }
-            ParsedLine::Label(_name) => {}
+            (section, 0)
+        })
+        .collect();
+
+    let mut layout: Vec<SectionLayout> = Vec::new();
+    let mut previous_end = MEMORY_START;
+
+    for section in SECTIONS.iter().cloned() {
+        let start = match options.section_bases.iter().find(|(s, _)| *s == section) {
+            _ if relocatable => 0,
+            Some((_, base)) => *base,
+            None => previous_end + padding(previous_end, 8),
+        };
+
+        let mut counter = start;
+        let mut alignment = 8;
+        for (statement, position) in parsed.iter().zip(positions.iter_mut()) {
+            if position.0 != section {
+                continue;
+            }
+
+            if let ParsedLine::Align(align) = statement.parsed {
+                alignment = alignment.max(align);
+            }
+
+            position.1 = counter;
+            let size = statement_size(statement, section, counter)?;
+            counter = counter.checked_add(size).ok_or_else(|| {
+                AsmError::Layout(format!(
+                    "Section {} exceeds the address space",
+                    section.name()
+                ))
+            })?;
+        }
+
+        // Checked before any data is generated, so a large .space can not
+        // allocate more than the memory size
+        let outside = if relocatable {
+            counter > MEMORY_SIZE
+        } else {
+            counter > start && (start < MEMORY_START || counter > MEMORY_START + MEMORY_SIZE)
+        };
+        if outside {
+            return Err(AsmError::Layout(format!(
+                "Section {} does not fit into memory",
+                section.name()
+            )));
+        }
+
+        if let Some(other) = layout
+            .iter()
+            .filter(|l| l.end > l.start && counter > start && !relocatable)
+            .find(|l| l.start < counter && start < l.end)
+        {
+            return Err(AsmError::Layout(format!(
+                "Sections {} and {} overlap",
+                other.section.name(),
+                section.name()
+            )));
+        }
+
+        layout.push(SectionLayout {
+            section,
+            start,
+            end: counter,
+            alignment,
+        });
+        previous_end = counter;
+    }
+
+    Ok((positions, layout))
+}
+
+// Symbols defined by the assembler. PROGRAM_START is the start of .text and
+// PROGRAM_END the end of the last non-empty section. In object files, symbols depending on the final layout
+// are resolved by the linker.
+fn builtin_symbols(layout: &[SectionLayout], relocatable: bool) -> HashMap<String, Value> {
+    let mut symbols = HashMap::new();
+
+    let program_start = layout
+        .iter()
+        .find(|l| l.section == Section::Text)
+        .map_or(MEMORY_START, |l| l.start);
+    let program_end = layout
+        .iter()
+        .filter(|l| l.end > l.start || l.section == Section::Text)
+        .map(|l| l.end)
+        .max()
+        .unwrap_or(MEMORY_START);
+
+    let mut define = |name: String, value: u32| {
+        let value = if relocatable {
+            Value::relative(&name, 0)
+        } else {
+            Value::absolute(i64::from(value))
         };
+        symbols.insert(name, value);
+    };
+
+    define(String::from("PROGRAM_START"), program_start);
+    define(String::from("PROGRAM_END"), program_end);
+
+    for l in layout {
+        let prefix = l.section.symbol_prefix();
+        define(format!("{}_START", prefix), l.start);
+        define(format!("{}_END", prefix), l.end);
+    }
+
+    for region in MEMORY_MAP.iter() {
+        let end = align_down(region.start + (region.size - 1));
+        symbols.insert(
+            format!("{}_START", region.name),
+            Value::absolute(i64::from(region.start)),
+        );
+        symbols.insert(
+            format!("{}_END", region.name),
+            Value::absolute(i64::from(end)),
+        );
+    }
+
+    symbols
+}
+
+// Constants may reference labels and other constants in any order, so the
+// constants an expression depends on are resolved first.
+fn resolve_constant<'a>(
+    name: &'a str,
+    constants: &HashMap<&'a str, (Location, &'a Expr)>,
+    symbols: &mut HashMap<String, Value>,
+    visiting: &mut Vec<&'a str>,
+) -> Result<()> {
+    let (location, value) = match constants.get(name) {
+        Some((location, value)) if !symbols.contains_key(name) => (location, *value),
+        _ => return Ok(()),
+    };
+
+    if visiting.contains(&name) {
+        return Err(AsmError::Expression(
+            location.clone(),
+            format!("Circular definition of '{}'", name),
+        ));
+    }
+
+    visiting.push(name);
+    for dependency in value.symbols() {
+        resolve_constant(dependency, constants, symbols, visiting)?;
+    }
+    visiting.pop();
+
+    let value = evaluate(value, symbols, location)?;
+    symbols.insert(name.into(), value);
+
+    Ok(())
+}
+
+fn evaluate(expr: &Expr, symbols: &HashMap<String, Value>, location: &Location) -> Result<Value> {
+    expr.evaluate_value(&|name: &str| symbols.get(name).cloned())
+        .map_err(|e| match e {
+            EvalError::UndefinedSymbol(name) => AsmError::UndefinedSymbol(location.clone(), name),
+            e => AsmError::Expression(location.clone(), e.to_string()),
+        })
+}
+
+fn evaluate_absolute(
+    expr: &Expr,
+    symbols: &HashMap<String, Value>,
+    location: &Location,
+) -> Result<i64> {
+    match evaluate(expr, symbols, location)? {
+        Value { base: None, offset } => Ok(offset),
+        _ => Err(AsmError::Expression(
+            location.clone(),
+            EvalError::NotRelocatable.to_string(),
+        )),
     }
+}
 
-    Some(bytes)
+fn resolve_operand(
+    op: &Op,
+    symbols: &HashMap<String, Value>,
+    location: &Location,
+) -> Result<Value> {
+    match op {
+        Op::Number(number) => Ok(Value::absolute(i64::from(*number))),
+        Op::Label(name) => symbols
+            .get(name)
+            .cloned()
+            .ok_or_else(|| AsmError::UndefinedSymbol(location.clone(), name.clone())),
+        Op::Expression(expr) => evaluate(expr, symbols, location),
+    }
+}
+
+// Checks that the value fits into the given number of bits. Negative values
+// are stored in two's complement.
+fn fit(value: i64, bits: u32, location: &Location) -> Result<u32> {
+    let min = -(1i64 << (bits - 1));
+    let max = (1i64 << bits) - 1;
+
+    if value < min || value > max {
+        return Err(AsmError::Expression(
+            location.clone(),
+            format!("Value {} does not fit into {} bits", value, bits),
+        ));
+    }
+    Ok(value as u32)
+}
+
+// Number of bytes needed to align the address
+fn padding(address: u32, alignment: u32) -> u32 {
+    (alignment - address % alignment) % alignment
 }
 
 #[allow(unused)]
@@ -79,6 +808,9 @@ fn align_down(addr: u32) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::generated::instruction::Instruction;
+    use crate::common::image::{EXECUTE, READ};
+    use std::io::Cursor;
 
     #[test]
     fn test_align_unchanged() {
@@ -99,4 +831,503 @@ mod tests {
         assert_eq!(16, align_down(n));
     }
 
+    // Returns the contents of the .text section
+    fn assemble_str(source: &str) -> Result<Vec<u8>> {
+        let image = assemble(&mut Cursor::new(source))?;
+        Ok(image
+            .segment(".text")
+            .map(|segment| segment.data.clone())
+            .unwrap_or_default())
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let image = assemble(&mut Cursor::new(
+            "main:\n\
+             ldi r1, 1\n\
+             call work\n\
+             halt\n\
+             work:\n\
+             .loop:\n\
+             ret\n\
+             .section .data\n\
+             table:\n\
+             .word 1, 2\n",
+        ))
+        .unwrap();
+        let debug = DebugInfo::from_bytes(image.debug.as_ref().unwrap()).unwrap();
+
+        assert_eq!(debug.describe(MEMORY_START), "main (<input>:2)");
+        assert_eq!(debug.describe(MEMORY_START + 0x10), "main+0x10 (<input>:4)");
+        assert_eq!(debug.describe(MEMORY_START + 0x18), "work (<input>:7)");
+        assert_eq!(debug.location(MEMORY_START + 0x24).unwrap().line, 10);
+        assert_eq!(debug.symbol(MEMORY_START + 0x24).unwrap().name, "table");
+    }
+
+    fn operand(bytes: &[u8], index: usize) -> u32 {
+        DecodedInstruction::decode(&bytes[index * 8..]).operand
+    }
+
+    #[test]
+    fn test_expressions() {
+        let bytes = assemble_str(
+            "start:\n\
+             ldi r0, start+8\n\
+             ldi r1, (end - start) / 4\n\
+             ldi r2, 1 << 5\n\
+             ldi r3, -1\n\
+             ldi r4, hi(0x12345678) | lo(end)\n\
+             end:\n",
+        )
+        .unwrap();
+
+        assert_eq!(operand(&bytes, 0), MEMORY_START + 8);
+        assert_eq!(operand(&bytes, 1), 10);
+        assert_eq!(operand(&bytes, 2), 32);
+        assert_eq!(operand(&bytes, 3), 0xFFFF_FFFF);
+        assert_eq!(operand(&bytes, 4), 0x1234 | ((MEMORY_START + 40) & 0xFFFF));
+    }
+
+    #[test]
+    fn test_errors() {
+        match assemble_str("ldi r0, missing + 1") {
+            Err(AsmError::UndefinedSymbol(location, name)) => {
+                assert_eq!(location.line_number, 1);
+                assert_eq!(name, "missing");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str("halt\nldi r0, 1 / 0") {
+            Err(AsmError::Expression(location, _)) => assert_eq!(location.line_number, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str("ldi r0, 0xFFFFFFFF + 1") {
+            Err(AsmError::Expression(location, _)) => assert_eq!(location.line_number, 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str("a:\na:") {
+            Err(AsmError::DuplicateSymbol(location, _)) => assert_eq!(location.line_number, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str("ldi r0, PROGRAM_END\nPROGRAM_END:") {
+            Err(AsmError::DuplicateSymbol(location, _)) => assert_eq!(location.line_number, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str("ldi r0, (1") {
+            Err(AsmError::Syntax(location, _)) => assert_eq!(location.line_number, 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        let bytes = assemble_str(
+            ".equ STACK_TOP, STACK_BOTTOM + STACK_SIZE\n\
+             .equ STACK_SIZE, 0x1000\n\
+             .equ STACK_BOTTOM, end\n\
+             ldi sp, STACK_TOP\n\
+             end:\n",
+        )
+        .unwrap();
+
+        assert_eq!(operand(&bytes, 0), MEMORY_START + 8 + 0x1000);
+    }
+
+    #[test]
+    fn test_variables() {
+        let bytes = assemble_str(
+            ".set COUNT, 1\n\
+             ldi r0, COUNT\n\
+             .set COUNT, COUNT + 1\n\
+             ldi r0, COUNT\n",
+        )
+        .unwrap();
+
+        assert_eq!(operand(&bytes, 0), 1);
+        assert_eq!(operand(&bytes, 1), 2);
+        assert!(assemble_str("ldi r0, COUNT\n.set COUNT, 1").is_err());
+    }
+
+    #[test]
+    fn test_constant_errors() {
+        match assemble_str(".equ A, B\n.equ B, A + 1") {
+            Err(AsmError::Expression(_, _)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str("a:\n.equ a, 1") {
+            Err(AsmError::DuplicateSymbol(location, _)) => assert_eq!(location.line_number, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str(".equ A, 1\n.set A, 2") {
+            Err(AsmError::DuplicateSymbol(location, _)) => assert_eq!(location.line_number, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str(".equ MEMORY_START, 1") {
+            Err(AsmError::DuplicateSymbol(location, _)) => assert_eq!(location.line_number, 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str(".equ A") {
+            Err(AsmError::Syntax(location, _)) => assert_eq!(location.line_number, 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_builtin_symbols() {
+        let symbols = builtin_symbols(&[], false);
+        let value = |name: &str| symbols[name].offset;
+        assert_eq!(value("MEMORY_START"), i64::from(MEMORY_START));
+        assert_eq!(value("MEMORY_END"), i64::from(MEMORY_END - 3));
+        assert_eq!(value("CONSOLEIO_START"), i64::from(CONSOLEIO_START));
+        assert_eq!(value("CONSOLEIO_END"), i64::from(CONSOLEIO_START + 4));
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        let bytes = assemble_str(
+            "clr r3\n\
+             push r0, r1, r2\n\
+             pop r0, r1, r2\n\
+             call function, 5, r4\n\
+             function:\n",
+        )
+        .unwrap();
+
+        let decoded: Vec<(Instruction, u8, u32)> = bytes
+            .chunks(8)
+            .map(DecodedInstruction::decode)
+            .map(|d| (d.instruction_type, d.reg_1, d.operand))
+            .collect();
+
+        assert_eq!(
+            decoded,
+            vec![
+                (Instruction::XOr, 3, 0),
+                (Instruction::Push, 0, 0),
+                (Instruction::Push, 1, 0),
+                (Instruction::Push, 2, 0),
+                (Instruction::Pop, 2, 0),
+                (Instruction::Pop, 1, 0),
+                (Instruction::Pop, 0, 0),
+                (Instruction::LoadImmediate, 0, 5),
+                (Instruction::Move, 1, 0),
+                (Instruction::Call, 0, MEMORY_START + 80),
+            ]
+        );
+
+        // r0 is already overwritten when it is moved into r1
+        match assemble_str("call function, r1, r0\nfunction:\n") {
+            Err(AsmError::Syntax(location, _)) => assert_eq!(location.line_number, 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(assemble_str("call function, r0, r0, r2, r5\nfunction:\n").is_ok());
+    }
+
+    #[test]
+    fn test_align() {
+        let bytes = assemble_str("halt\n.align 32\nstart:\nldi r0, start\n.align 4\n").unwrap();
+        assert_eq!(bytes.len(), 40);
+        assert_eq!(operand(&bytes, 4), MEMORY_START + 32);
+        assert!(assemble_str(".align 12").is_err());
+        assert!(assemble_str(".align 8192").is_err());
+    }
+
+    #[test]
+    fn test_sections() {
+        let image = assemble(&mut Cursor::new(
+            ".data
+             counter:
+             .word 1, -1
+             .text
+             ldi r0, counter
+             ldi r1, buffer
+             ldi r2, message
+             ldi r3, PROGRAM_END
+             .bss
+             buffer:
+             .space 64
+             .rodata
+             message:
+             .asciz \"hi\"
+             .byte 'x', -1
+",
+        ))
+        .unwrap();
+
+        let names: Vec<&str> = image.segments.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec![".text", ".rodata", ".data", ".bss"]);
+
+        let text = &image.segments[0].data;
+        let rodata = &image.segments[1];
+        let data = &image.segments[2];
+        let bss = &image.segments[3];
+
+        assert_eq!(rodata.address, MEMORY_START + 32);
+        assert_eq!(rodata.data, b"hi\0x\xFF");
+        assert_eq!(data.address, MEMORY_START + 40);
+        assert_eq!(data.data, vec![0, 0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(bss.address, MEMORY_START + 48);
+        assert_eq!(bss.size, 64);
+        assert!(bss.data.is_empty());
+
+        assert_eq!(operand(text, 0), data.address);
+        assert_eq!(operand(text, 1), bss.address);
+        assert_eq!(operand(text, 2), rodata.address);
+        assert_eq!(operand(text, 3), bss.end());
+    }
+
+    #[test]
+    fn test_section_bases() {
+        let options = Options {
+            section_bases: vec![(Section::Data, 0x18_0000)],
+            ..Options::default()
+        };
+        let source = ".data
+.word DATA_END - DATA_START
+.bss
+.space 4
+.text
+halt
+";
+        let file = SourceFile::new("<input>");
+        let lines = tokenize(&mut Cursor::new(source), &file);
+        let image = generate(&parse_lines(lines).unwrap(), &options, false)
+            .unwrap()
+            .into_image();
+
+        assert_eq!(image.segment(".text").unwrap().address, MEMORY_START);
+        assert_eq!(image.segment(".data").unwrap().address, 0x18_0000);
+        assert_eq!(image.segment(".data").unwrap().data, vec![0, 0, 0, 4]);
+        assert_eq!(image.segment(".bss").unwrap().address, 0x18_0008);
+
+        let overlapping = Options {
+            section_bases: vec![(Section::Data, MEMORY_START + 4)],
+            ..Options::default()
+        };
+        let lines = tokenize(&mut Cursor::new(source), &file);
+        match generate(&parse_lines(lines).unwrap(), &overlapping, false) {
+            Err(AsmError::Layout(_)) => {}
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("sections overlap"),
+        }
+
+        let moved = Options {
+            section_bases: vec![(Section::Text, MEMORY_START + 0x100)],
+            ..Options::default()
+        };
+        let lines = tokenize(&mut Cursor::new("ldi r0, PROGRAM_START\n"), &file);
+        let image = generate(&parse_lines(lines).unwrap(), &moved, false)
+            .unwrap()
+            .into_image();
+        assert_eq!(
+            operand(&image.segment(".text").unwrap().data, 0),
+            MEMORY_START + 0x100
+        );
+    }
+
+    #[test]
+    fn test_outside_memory() {
+        let file = SourceFile::new("<input>");
+        let below = Options {
+            section_bases: vec![(Section::Data, CONSOLEIO_START)],
+            ..Options::default()
+        };
+        let sources = [
+            (".data\n.space 0x100001\n", Options::default(), false),
+            (".data\n.space 0xFFF00000\n", Options::default(), true),
+            (".bss\n.space 0x100001\n", Options::default(), false),
+            (".data\n.byte 1\n", below.clone(), false),
+        ];
+
+        for (source, options, relocatable) in sources.iter() {
+            let lines = tokenize(&mut Cursor::new(*source), &file);
+            match generate(&parse_lines(lines).unwrap(), options, *relocatable) {
+                Err(AsmError::Layout(_)) => {}
+                Err(e) => panic!("unexpected error {}", e),
+                Ok(_) => panic!("{} fits into memory", source),
+            }
+        }
+
+        // An empty section may start anywhere
+        let lines = tokenize(&mut Cursor::new(".data\n"), &file);
+        assert!(generate(&parse_lines(lines).unwrap(), &below, false).is_ok());
+    }
+
+    #[test]
+    fn test_object() {
+        let object = assemble_object(&mut Cursor::new(
+            ".extern print\n\
+             .global main, SIZE\n\
+             .equ SIZE, end - start\n\
+             main:\n\
+             ldi r0, 1\n\
+             start:\n\
+             call print + 8\n\
+             ldi r1, SIZE\n\
+             end:\n\
+             .data\n\
+             .word start, PROGRAM_END\n",
+        ))
+        .unwrap();
+
+        assert_eq!(object.section(".text").unwrap().size, 24);
+        assert_eq!(object.section(".data").unwrap().data, vec![0; 8]);
+
+        let symbols: Vec<(&str, Binding, Option<&str>, u32)> = object
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.binding, s.section.as_deref(), s.value))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("SIZE", Binding::Global, None, 16),
+                ("main", Binding::Global, Some(".text"), 0),
+                ("start", Binding::Local, Some(".text"), 8),
+                ("end", Binding::Local, Some(".text"), 24),
+                ("print", Binding::Undefined, None, 0),
+            ]
+        );
+
+        let relocations: Vec<(&str, u32, &str, i64)> = object
+            .relocations
+            .iter()
+            .map(|r| (r.section.as_str(), r.offset, r.symbol.as_str(), r.addend))
+            .collect();
+        assert_eq!(
+            relocations,
+            vec![
+                (".text", 12, "print", 8),
+                (".data", 0, ".text", 8),
+                (".data", 4, "PROGRAM_END", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_object_errors() {
+        let assemble_object_str = |source: &str| assemble_object(&mut Cursor::new(source));
+
+        match assemble_object_str("ldi r0, missing") {
+            Err(AsmError::UndefinedSymbol(_, name)) => assert_eq!(name, "missing"),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_object_str("nop\n.global missing") {
+            Err(AsmError::UndefinedSymbol(location, _)) => assert_eq!(location.line_number, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_object_str("a:\n.data\nb:\n.word b - a") {
+            Err(AsmError::Expression(location, _)) => assert_eq!(location.line_number, 4),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(assemble_object_str("a:\n.data\n.byte a").is_err());
+        assert!(assemble_str(".extern missing\nldi r0, missing").is_err());
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let source = "nop\nstart:\nhalt\n";
+        let file = SourceFile::new("<input>");
+        let mut options = Options::default();
+
+        let lines = tokenize(&mut Cursor::new(source), &file);
+        let image = generate(&parse_lines(lines).unwrap(), &options, false)
+            .unwrap()
+            .into_image();
+        assert_eq!(image.entry, MEMORY_START);
+        assert_eq!(
+            image.symbols,
+            vec![ImageSymbol {
+                name: "start".into(),
+                address: MEMORY_START + 8
+            }]
+        );
+        assert_eq!(image.segments[0].permissions, READ | EXECUTE);
+
+        options.entry = Some("start".into());
+        let lines = tokenize(&mut Cursor::new(source), &file);
+        let image = generate(&parse_lines(lines).unwrap(), &options, false)
+            .unwrap()
+            .into_image();
+        assert_eq!(image.entry, MEMORY_START + 8);
+
+        options.entry = Some("missing".into());
+        let lines = tokenize(&mut Cursor::new(source), &file);
+        assert!(generate(&parse_lines(lines).unwrap(), &options, false).is_err());
+    }
+
+    #[test]
+    fn test_data_errors() {
+        match assemble_str(
+            "nop
+.bss
+halt",
+        ) {
+            Err(AsmError::Syntax(location, _)) => assert_eq!(location.line_number, 3),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match assemble_str(
+            ".data
+.byte 256",
+        ) {
+            Err(AsmError::Expression(location, _)) => assert_eq!(location.line_number, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(assemble_str(
+            ".data
+.byte -128, 255"
+        )
+        .is_ok());
+        assert!(assemble_str(".text 1").is_err());
+    }
+
+    #[test]
+    fn test_synthesized_branches() {
+        use crate::emulator::cpu::{Register, StepResult, CPU};
+        use crate::emulator::memory::AddressSpace;
+
+        // r5 collects one bit for every branch that is taken
+        let mut source = String::from(
+            "ldi sp, MEMORY_END\n\
+             ldi r0, 1\n\
+             ldi r1, 0xFFFFFFFF\n\
+             ldi r2, 0x80000000\n\
+             ldi r3, 0x7FFFFFFF\n\
+             ldi r15, 42\n",
+        );
+        let branches = [
+            "bltu r0, r1",
+            "bgtu r0, r1",
+            "bgeu r0, r0",
+            "bleu r1, r0",
+            "bne r0, r1",
+            "blt r1, r0",
+            "bgt r1, r0",
+            "bge r0, r0",
+            "ble r0, r1",
+            "blt r2, r3",
+            "bgt r3, r2",
+            "ble r3, r3",
+        ];
+        for (index, branch) in branches.iter().enumerate() {
+            source.push_str(&format!(
+                "{}, 1f\nb 2f\n1:\naddi r5, r5, {}\n2:\n",
+                branch,
+                1 << index
+            ));
+        }
+        source.push_str("halt\n");
+
+        let image = assemble(&mut Cursor::new(source)).unwrap();
+        let mut memory = AddressSpace::default();
+        memory.load_image(&image).unwrap();
+        let mut cpu = CPU::new(memory);
+
+        assert_eq!(cpu.run(), StepResult::Halted);
+        assert_eq!(cpu.get_register(Register::R5), 0b1110_1011_0101);
+        assert_eq!(cpu.get_register(Register::R1), 0xFFFFFFFF);
+        assert_eq!(cpu.get_register(Register::R2), 0x80000000);
+        assert_eq!(cpu.get_register(Register::R15), 42);
+        assert_eq!(cpu.get_register(Register::SP), MEMORY_END - 3);
+    }
 }
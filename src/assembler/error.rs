@@ -0,0 +1,68 @@
+use std::fmt;
+use std::io;
+
+use crate::assembler::tokenizer::Location;
+
+pub type Result<T> = std::result::Result<T, AsmError>;
+
+#[derive(Debug)]
+pub enum AsmError {
+    IO(io::Error),
+    Syntax(Location, String),
+    UndefinedSymbol(Location, String),
+    DuplicateSymbol(Location, String),
+    Expression(Location, String),
+    Include(Location, String),
+    Layout(String),
+}
+
+impl AsmError {
+    pub fn location(&self) -> Option<&Location> {
+        match *self {
+            AsmError::IO(_) | AsmError::Layout(_) => None,
+            AsmError::Syntax(ref location, _)
+            | AsmError::UndefinedSymbol(ref location, _)
+            | AsmError::DuplicateSymbol(ref location, _)
+            | AsmError::Expression(ref location, _)
+            | AsmError::Include(ref location, _) => Some(location),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsmError::IO(ref e) => write!(f, "IOError: {}", e),
+            AsmError::Syntax(ref location, ref s) => {
+                write!(f, "{}: SyntaxError: {}", location, s)
+            }
+            AsmError::UndefinedSymbol(ref location, ref s) => {
+                write!(f, "{}: Undefined symbol '{}'", location, s)
+            }
+            AsmError::DuplicateSymbol(ref location, ref s) => {
+                write!(f, "{}: Duplicate symbol '{}'", location, s)
+            }
+            AsmError::Expression(ref location, ref s) => {
+                write!(f, "{}: ExpressionError: {}", location, s)
+            }
+            AsmError::Include(ref location, ref s) => {
+                write!(f, "{}: IncludeError: {}", location, s)
+            }
+            AsmError::Layout(ref s) => write!(f, "LayoutError: {}", s),
+        }?;
+
+        if let Some(location) = self.location() {
+            for include in location.file.include_chain() {
+                write!(f, "\n    included from {}", include)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<io::Error> for AsmError {
+    fn from(err: io::Error) -> AsmError {
+        AsmError::IO(err)
+    }
+}
@@ -0,0 +1,531 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::assembler::parser::parse_literal;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Function(Function, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Complement,
+    // Logical not, 1 for zero and 0 otherwise
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    XOr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    // Upper and lower 16 bits of a value
+    Hi,
+    Lo,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EvalError {
+    UndefinedSymbol(String),
+    DivisionByZero,
+    Overflow,
+    InvalidShift(i64),
+    NotRelocatable,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::UndefinedSymbol(ref s) => write!(f, "Undefined symbol '{}'", s),
+            EvalError::DivisionByZero => write!(f, "Division by zero"),
+            EvalError::Overflow => write!(f, "Arithmetic overflow"),
+            EvalError::InvalidShift(amount) => write!(f, "Invalid shift amount {}", amount),
+            EvalError::NotRelocatable => write!(f, "Expression can not be relocated"),
+        }
+    }
+}
+
+// Result of an expression that may depend on an address only known after
+// linking: the address of base plus offset. Absolute values have no base.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    pub base: Option<String>,
+    pub offset: i64,
+}
+
+impl Value {
+    pub fn absolute(offset: i64) -> Value {
+        Value { base: None, offset }
+    }
+
+    pub fn relative(base: &str, offset: i64) -> Value {
+        Value {
+            base: Some(base.into()),
+            offset,
+        }
+    }
+
+    fn to_absolute(&self) -> Result<i64, EvalError> {
+        match self.base {
+            None => Ok(self.offset),
+            Some(_) => Err(EvalError::NotRelocatable),
+        }
+    }
+}
+
+impl Expr {
+    // Evaluates the expression with 64 bit signed arithmetic. Symbols are
+    // resolved with the given lookup function and may be relative to a base.
+    // Only adding an absolute value to a relative one and subtracting two
+    // values with the same base keep the result representable.
+    pub fn evaluate_value<F>(&self, lookup: &F) -> Result<Value, EvalError>
+    where
+        F: Fn(&str) -> Option<Value>,
+    {
+        match self {
+            Expr::Symbol(name) => {
+                lookup(name).ok_or_else(|| EvalError::UndefinedSymbol(name.clone()))
+            }
+            Expr::Binary(op, left, right) => {
+                let l = left.evaluate_value(lookup)?;
+                let r = right.evaluate_value(lookup)?;
+                let offset = evaluate_binary(*op, l.offset, r.offset)?;
+
+                let base = match (op, l.base, r.base) {
+                    (_, None, None) => None,
+                    (BinaryOp::Add, base, None) | (BinaryOp::Add, None, base) => base,
+                    (BinaryOp::Subtract, base, None) => base,
+                    (BinaryOp::Subtract, Some(l), Some(r)) if l == r => None,
+                    _ => return Err(EvalError::NotRelocatable),
+                };
+                Ok(Value { base, offset })
+            }
+            Expr::Number(value) => Ok(Value::absolute(*value)),
+            Expr::Unary(op, operand) => {
+                let value = operand.evaluate_value(lookup)?.to_absolute()?;
+                Ok(Value::absolute(evaluate_unary(*op, value)))
+            }
+            Expr::Function(function, argument) => {
+                let value = argument.evaluate_value(lookup)?.to_absolute()?;
+                Ok(Value::absolute(evaluate_function(*function, value)))
+            }
+        }
+    }
+
+    // Names of all symbols referenced by the expression
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols = Vec::new();
+        self.collect_symbols(&mut symbols);
+        symbols
+    }
+
+    // Replaces every symbol name with the result of the given function
+    pub fn rename_symbols<F>(&mut self, rename: &mut F) -> Result<(), String>
+    where
+        F: FnMut(&str) -> Result<String, String>,
+    {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Symbol(name) => *name = rename(name)?,
+            Expr::Unary(_, operand) | Expr::Function(_, operand) => {
+                operand.rename_symbols(rename)?
+            }
+            Expr::Binary(_, left, right) => {
+                left.rename_symbols(rename)?;
+                right.rename_symbols(rename)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_symbols<'a>(&'a self, symbols: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Symbol(name) => symbols.push(name),
+            Expr::Unary(_, operand) | Expr::Function(_, operand) => {
+                operand.collect_symbols(symbols)
+            }
+            Expr::Binary(_, left, right) => {
+                left.collect_symbols(symbols);
+                right.collect_symbols(symbols);
+            }
+        }
+    }
+}
+
+fn evaluate_unary(op: UnaryOp, value: i64) -> i64 {
+    match op {
+        UnaryOp::Negate => value.wrapping_neg(),
+        UnaryOp::Complement => !value,
+        UnaryOp::Not => i64::from(value == 0),
+    }
+}
+
+fn evaluate_function(function: Function, value: i64) -> i64 {
+    match function {
+        Function::Hi => (value >> 16) & 0xFFFF,
+        Function::Lo => value & 0xFFFF,
+    }
+}
+
+fn evaluate_binary(op: BinaryOp, l: i64, r: i64) -> Result<i64, EvalError> {
+    Ok(match op {
+        BinaryOp::Add => l.wrapping_add(r),
+        BinaryOp::Subtract => l.wrapping_sub(r),
+        BinaryOp::Multiply => l.wrapping_mul(r),
+        BinaryOp::Divide | BinaryOp::Remainder if r == 0 => {
+            return Err(EvalError::DivisionByZero);
+        }
+        BinaryOp::Divide => l.checked_div(r).ok_or(EvalError::Overflow)?,
+        BinaryOp::Remainder => l.checked_rem(r).ok_or(EvalError::Overflow)?,
+        BinaryOp::ShiftLeft | BinaryOp::ShiftRight if !(0..64).contains(&r) => {
+            return Err(EvalError::InvalidShift(r));
+        }
+        BinaryOp::ShiftLeft => l << r,
+        BinaryOp::ShiftRight => l >> r,
+        BinaryOp::And => l & r,
+        BinaryOp::Or => l | r,
+        BinaryOp::XOr => l ^ r,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExprToken {
+    Number(i64),
+    Identifier(String),
+    Operator(&'static str),
+    OpenParen,
+    CloseParen,
+}
+
+const OPERATORS: [&str; 12] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!"];
+
+pub(crate) fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+pub(crate) fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// References to anonymous labels like 1b (backward) or 2f (forward)
+pub fn is_anonymous_label_reference(s: &str) -> bool {
+    s.len() > 1
+        && (s.ends_with('b') || s.ends_with('f'))
+        && s[..s.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
+
+fn take_while<F>(s: &str, chars: &mut Peekable<CharIndices>, start: usize, f: F) -> String
+where
+    F: Fn(char) -> bool,
+{
+    let mut end = start;
+    while let Some(&(index, c)) = chars.peek() {
+        if !f(c) {
+            break;
+        }
+        end = index + c.len_utf8();
+        chars.next();
+    }
+    s[start..end].into()
+}
+
+fn take_char_literal(
+    s: &str,
+    chars: &mut Peekable<CharIndices>,
+    start: usize,
+) -> Result<String, String> {
+    chars.next();
+    let mut escaped = false;
+    for (index, c) in chars {
+        match c {
+            '\'' if !escaped => return Ok(s[start..=index].into()),
+            '\\' if !escaped => escaped = true,
+            _ => escaped = false,
+        }
+    }
+    Err(format!("Unterminated character literal {}", &s[start..]))
+}
+
+fn lex(s: &str) -> Result<Vec<ExprToken>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let literal = take_while(s, &mut chars, start, is_identifier_char);
+            if is_anonymous_label_reference(&literal) {
+                tokens.push(ExprToken::Identifier(literal));
+                continue;
+            }
+            let value = parse_literal(&literal)?;
+            tokens.push(ExprToken::Number(i64::from(value)));
+        } else if c == '\'' {
+            let literal = take_char_literal(s, &mut chars, start)?;
+            tokens.push(ExprToken::Number(i64::from(parse_literal(&literal)?)));
+        } else if is_identifier_start(c) {
+            let name = take_while(s, &mut chars, start, is_identifier_char);
+            tokens.push(ExprToken::Identifier(name));
+        } else if c == '(' {
+            chars.next();
+            tokens.push(ExprToken::OpenParen);
+        } else if c == ')' {
+            chars.next();
+            tokens.push(ExprToken::CloseParen);
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|op| s[start..].starts_with(*op))
+                .ok_or_else(|| format!("Unexpected character '{}'", c))?;
+            for _ in 0..operator.len() {
+                chars.next();
+            }
+            tokens.push(ExprToken::Operator(operator));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Binary operators from lowest to highest precedence
+const PRECEDENCE: [&[(&str, BinaryOp)]; 6] = [
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::XOr)],
+    &[("&", BinaryOp::And)],
+    &[("<<", BinaryOp::ShiftLeft), (">>", BinaryOp::ShiftRight)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+    &[
+        ("*", BinaryOp::Multiply),
+        ("/", BinaryOp::Divide),
+        ("%", BinaryOp::Remainder),
+    ],
+];
+
+struct ExprParser {
+    tokens: Vec<ExprToken>,
+    position: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&ExprToken> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<ExprToken> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+
+        while let Some(ExprToken::Operator(operator)) = self.peek() {
+            let op = match PRECEDENCE[level].iter().find(|(s, _)| s == operator) {
+                Some((_, op)) => *op,
+                None => break,
+            };
+            self.next();
+            let right = self.parse_binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(ExprToken::Operator("-")) => {
+                Ok(Expr::Unary(UnaryOp::Negate, Box::new(self.parse_unary()?)))
+            }
+            Some(ExprToken::Operator("~")) => Ok(Expr::Unary(
+                UnaryOp::Complement,
+                Box::new(self.parse_unary()?),
+            )),
+            Some(ExprToken::Operator("!")) => {
+                Ok(Expr::Unary(UnaryOp::Not, Box::new(self.parse_unary()?)))
+            }
+            Some(ExprToken::Operator("+")) => self.parse_unary(),
+            Some(ExprToken::Number(value)) => Ok(Expr::Number(value)),
+            Some(ExprToken::Identifier(name)) => {
+                if self.peek() != Some(&ExprToken::OpenParen) {
+                    return Ok(Expr::Symbol(name));
+                }
+
+                let function = match name.as_str() {
+                    "hi" => Function::Hi,
+                    "lo" => Function::Lo,
+                    _ => return Err(format!("Unknown function '{}'", name)),
+                };
+                self.next();
+                let argument = self.parse_parenthesized()?;
+                Ok(Expr::Function(function, Box::new(argument)))
+            }
+            Some(ExprToken::OpenParen) => self.parse_parenthesized(),
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Err("Unexpected end of expression".into()),
+        }
+    }
+
+    // Parses the rest of a parenthesized expression after the opening parenthesis
+    fn parse_parenthesized(&mut self) -> Result<Expr, String> {
+        let expr = self.parse_binary(0)?;
+        match self.next() {
+            Some(ExprToken::CloseParen) => Ok(expr),
+            _ => Err("Missing closing parenthesis".into()),
+        }
+    }
+}
+
+pub fn parse_expression(s: &str) -> Result<Expr, String> {
+    let mut parser = ExprParser {
+        tokens: lex(s)?,
+        position: 0,
+    };
+
+    let expr = parser.parse_binary(0)?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("Unexpected {:?}", token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(s: &str) -> Result<i64, EvalError> {
+        let lookup = |name: &str| match name {
+            "BUF" => Some(Value::absolute(0x1000)),
+            "BUF_END" => Some(Value::absolute(0x1040)),
+            "label" => Some(Value::absolute(0x10_0010)),
+            _ => None,
+        };
+        parse_expression(s)
+            .unwrap()
+            .evaluate_value(&lookup)
+            .map(|value| value.offset)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(7));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(9));
+        assert_eq!(evaluate("1 << 5"), Ok(32));
+        assert_eq!(evaluate("1 | 2 & 3"), Ok(3));
+        assert_eq!(evaluate("8 - 2 - 1"), Ok(5));
+        assert_eq!(evaluate("0xF0 >> 4 ^ 1"), Ok(14));
+    }
+
+    #[test]
+    fn test_unary() {
+        assert_eq!(evaluate("-1"), Ok(-1));
+        assert_eq!(evaluate("- -4"), Ok(4));
+        assert_eq!(evaluate("~0"), Ok(-1));
+        assert_eq!(evaluate("!0"), Ok(1));
+        assert_eq!(evaluate("!5"), Ok(0));
+        assert_eq!(evaluate("!!label"), Ok(1));
+        assert_eq!(evaluate("+3"), Ok(3));
+    }
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(evaluate("label+8"), Ok(0x10_0018));
+        assert_eq!(evaluate("(BUF_END - BUF) / 4"), Ok(16));
+        assert_eq!(
+            parse_expression("(BUF_END - BUF) / 4").unwrap().symbols(),
+            vec!["BUF_END", "BUF"]
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(evaluate("hi(0x12345678)"), Ok(0x1234));
+        assert_eq!(evaluate("lo(0x12345678)"), Ok(0x5678));
+        assert_eq!(evaluate("hi(label) << 16 | lo(label)"), Ok(0x10_0010));
+        assert!(parse_expression("foo(1)").is_err());
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(evaluate("'A' + 1"), Ok(66));
+        assert_eq!(evaluate("'\\'' | 0b1_0000_0000"), Ok(0x127));
+        assert_eq!(evaluate("-0o10"), Ok(-8));
+        assert_eq!(evaluate("0b11"), Ok(3));
+        assert!(parse_expression("'A").is_err());
+        assert!(parse_expression("0x1_0000_0000").is_err());
+        assert!(parse_expression("0x1_ + 1").is_err());
+    }
+
+    #[test]
+    fn test_rename_symbols() {
+        let mut expr = parse_expression("(.end - 1b) / 4").unwrap();
+        expr.rename_symbols(&mut |name: &str| Ok(format!("x{}", name)))
+            .unwrap();
+        assert_eq!(expr.symbols(), vec!["x.end", "x1b"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            evaluate("missing + 1"),
+            Err(EvalError::UndefinedSymbol("missing".into()))
+        );
+        assert_eq!(evaluate("1 / 0"), Err(EvalError::DivisionByZero));
+        assert_eq!(evaluate("1 % 0"), Err(EvalError::DivisionByZero));
+        assert_eq!(evaluate("(1 << 63) % -1"), Err(EvalError::Overflow));
+        assert_eq!(evaluate("(1 << 63) / -1"), Err(EvalError::Overflow));
+        assert_eq!(evaluate("1 << 64"), Err(EvalError::InvalidShift(64)));
+        assert!(parse_expression("(1 + 2").is_err());
+        assert!(parse_expression("1 +").is_err());
+        assert!(parse_expression("1 2").is_err());
+        assert!(parse_expression("1 $ 2").is_err());
+    }
+
+    #[test]
+    fn test_relocatable_values() {
+        let evaluate = |s: &str| {
+            let lookup = |name: &str| match name {
+                "start" => Some(Value::relative(".text", 8)),
+                "end" => Some(Value::relative(".text", 40)),
+                "buffer" => Some(Value::relative(".bss", 0)),
+                "SIZE" => Some(Value::absolute(16)),
+                _ => None,
+            };
+            parse_expression(s).unwrap().evaluate_value(&lookup)
+        };
+
+        assert_eq!(evaluate("start + SIZE"), Ok(Value::relative(".text", 24)));
+        assert_eq!(evaluate("4 + buffer - 1"), Ok(Value::relative(".bss", 3)));
+        assert_eq!(evaluate("(end - start) / 8"), Ok(Value::absolute(4)));
+        assert_eq!(evaluate("-SIZE"), Ok(Value::absolute(-16)));
+        assert_eq!(evaluate("start + end"), Err(EvalError::NotRelocatable));
+        assert_eq!(evaluate("buffer - start"), Err(EvalError::NotRelocatable));
+        assert_eq!(evaluate("SIZE - start"), Err(EvalError::NotRelocatable));
+        assert_eq!(evaluate("lo(start)"), Err(EvalError::NotRelocatable));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::assembler::tokenizer::{split_comment, tokenize_line};
+
+// Source formatter used by asmfmt. Labels and top-level directives start at
+// the beginning of the line, everything else is indented. Operands start in
+// the same column and comments after code are aligned in a column.
+// Consecutive blank lines are collapsed.
+
+const INDENT: &str = "    ";
+const MNEMONIC_WIDTH: usize = 8;
+const COMMENT_COLUMN: usize = 40;
+
+// Directives that are not indented
+const TOP_LEVEL: [&str; 12] = [
+    ".section", ".text", ".rodata", ".data", ".bss", ".macro", ".endm", ".include", ".equ", ".set",
+    ".global", ".extern",
+];
+
+fn format_code(code: &str) -> String {
+    let tokens = tokenize_line(code);
+    let mnemonic = &tokens[0].token;
+
+    let flush_left = mnemonic.ends_with(':') || TOP_LEVEL.contains(&mnemonic.as_str());
+    let indent = if flush_left { "" } else { INDENT };
+
+    if tokens.len() == 1 {
+        return format!("{}{}", indent, mnemonic);
+    }
+
+    let operands: Vec<&str> = tokens[1..].iter().map(|t| t.token.as_str()).collect();
+    format!(
+        "{}{:<width$}{}",
+        indent,
+        mnemonic,
+        operands.join(", "),
+        width = MNEMONIC_WIDTH.max(mnemonic.len() + 1)
+    )
+}
+
+fn format_line(line: &str) -> String {
+    let (code, comment) = split_comment(line);
+    let code = code.trim();
+    let comment = comment.map(str::trim_end);
+
+    match (code.is_empty(), comment) {
+        (true, None) => String::new(),
+        // Comments on their own line keep whether they were indented
+        (true, Some(comment)) => {
+            if line.starts_with(char::is_whitespace) {
+                format!("{}{}", INDENT, comment)
+            } else {
+                comment.to_string()
+            }
+        }
+        (false, None) => format_code(code),
+        (false, Some(comment)) => {
+            let code = format_code(code);
+            let padding = COMMENT_COLUMN.saturating_sub(code.len()).max(1);
+            format!("{}{}{}", code, " ".repeat(padding), comment)
+        }
+    }
+}
+
+pub fn format_source(text: &str) -> String {
+    let mut output = String::new();
+    let mut blank = false;
+
+    for line in text.lines() {
+        let formatted = format_line(line);
+        if formatted.is_empty() {
+            blank = !output.is_empty();
+            continue;
+        }
+
+        if blank {
+            output.push('\n');
+            blank = false;
+        }
+        output.push_str(&formatted);
+        output.push('\n');
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::codegen::assemble;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_format() {
+        let source = "\
+.equ   COUNT,3
+
+main:   // entry
+
+
+  ldi r1,COUNT//counter
+.loop:
+      dec r1
+\tbne r1 , r0, .loop
+// done
+    // still looping
+stdb r1, (END - START) / 4
+halt
+.section .data
+msg:
+ .ascii \"a, b // c\"  ,  \"d\"
+
+";
+        let expected = "\
+.equ    COUNT, 3
+
+main:                                   // entry
+
+    ldi     r1, COUNT                   //counter
+.loop:
+    dec     r1
+    bne     r1, r0, .loop
+// done
+    // still looping
+    stdb    r1, (END - START) / 4
+    halt
+.section .data
+msg:
+    .ascii  \"a, b // c\", \"d\"
+";
+
+        let formatted = format_source(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted), formatted);
+    }
+
+    // Formatting must not change the assembled program
+    #[test]
+    fn test_testdata_unchanged() {
+        for path in &[
+            "testdata/alphabet.asm",
+            "testdata/test.asm",
+            "testdata/benchmark.asm",
+        ] {
+            let text = std::fs::read_to_string(path).unwrap();
+            let formatted = format_source(&text);
+
+            let original = assemble(&mut Cursor::new(text)).unwrap();
+            let reformatted = assemble(&mut Cursor::new(formatted)).unwrap();
+            assert_eq!(original.segments, reformatted.segments, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_long_lines() {
+        assert_eq!(
+            format_source(".section .rodata\nldi r1, VERY_LONG_CONSTANT_NAME + 1 // x\n"),
+            ".section .rodata\n    ldi     r1, VERY_LONG_CONSTANT_NAME + 1 // x\n"
+        );
+    }
+}
@@ -2,10 +2,10 @@ use crate::common::generated::instruction::Instruction;
 use crate::assembler::tokenizer::TokenizedLine;
 use crate::assembler::parser::{*};
 
-#[allow(clippy::cyclomatic_complexity)]
-pub fn match_instruction(line: &TokenizedLine) -> Option<ParsedLine> {
+#[allow(clippy::cognitive_complexity)]
+pub fn match_instruction(line: &TokenizedLine) -> Result<ParsedLine, String> {
     if line.tokens.is_empty() {
-        return None;
+        return Err("Empty line".into());
     }
     let instruction_identifier = line.tokens[0].token.as_str();
 
@@ -25,157 +25,157 @@ pub fn match_instruction(line: &TokenizedLine) -> Option<ParsedLine> {
             Op::Number(0)), 
         "inc" if line.tokens.len() == 2 => MatchedInstruction::new(
             Instruction::Increment,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             Op::Number(0)), 
         "dec" if line.tokens.len() == 2 => MatchedInstruction::new(
             Instruction::Decrement,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             Op::Number(0)), 
         "add" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::Add,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
-            parse_register_name(line.tokens[3].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[3].token.as_str())?,
             Op::Number(0)), 
         "sub" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::Subtract,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
-            parse_register_name(line.tokens[3].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[3].token.as_str())?,
             Op::Number(0)), 
         "mul" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::Multiply,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
-            parse_register_name(line.tokens[3].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[3].token.as_str())?,
             Op::Number(0)), 
         "div" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::Divide,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
-            parse_register_name(line.tokens[3].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[3].token.as_str())?,
             Op::Number(0)), 
         "cmp" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::Compare,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             Op::Number(0)), 
         "cmpi" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::CompareImmediate,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             parse_operand(line.tokens[2].token.as_str())?), 
         "addi" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::AddImmediate,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             parse_operand(line.tokens[3].token.as_str())?), 
         "subi" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::SubtractImmediate,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             parse_operand(line.tokens[3].token.as_str())?), 
         "or" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::Or,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
-            parse_register_name(line.tokens[3].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[3].token.as_str())?,
             Op::Number(0)), 
         "and" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::And,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
-            parse_register_name(line.tokens[3].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[3].token.as_str())?,
             Op::Number(0)), 
         "xor" if line.tokens.len() == 4 => MatchedInstruction::new(
             Instruction::XOr,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
-            parse_register_name(line.tokens[3].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[3].token.as_str())?,
             Op::Number(0)), 
         "neg" if line.tokens.len() == 2 => MatchedInstruction::new(
             Instruction::Negate,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             Op::Number(0)), 
         "com" if line.tokens.len() == 2 => MatchedInstruction::new(
             Instruction::Complement,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             Op::Number(0)), 
         "ldi" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::LoadImmediate,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             parse_operand(line.tokens[2].token.as_str())?), 
         "ld" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::Load,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             Op::Number(0)), 
         "ldb" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::LoadByte,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             Op::Number(0)), 
         "ldd" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::LoadDirect,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             parse_operand(line.tokens[2].token.as_str())?), 
         "lddb" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::LoadDirectByte,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             parse_operand(line.tokens[2].token.as_str())?), 
         "st" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::Store,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             Op::Number(0)), 
         "stb" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::StoreByte,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             Op::Number(0)), 
         "std" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::StoreDirect,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             parse_operand(line.tokens[2].token.as_str())?), 
         "stdb" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::StoreDirectByte,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             parse_operand(line.tokens[2].token.as_str())?), 
         "push" if line.tokens.len() == 2 => MatchedInstruction::new(
             Instruction::Push,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             Op::Number(0)), 
         "pop" if line.tokens.len() == 2 => MatchedInstruction::new(
             Instruction::Pop,
-            parse_register_name(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
             0,
             0,
             Op::Number(0)), 
@@ -211,8 +211,8 @@ pub fn match_instruction(line: &TokenizedLine) -> Option<ParsedLine> {
             parse_operand(line.tokens[1].token.as_str())?), 
         "mov" if line.tokens.len() == 3 => MatchedInstruction::new(
             Instruction::Move,
-            parse_register_name(line.tokens[1].token.as_str())?,
-            parse_register_name(line.tokens[2].token.as_str())?,
+            parse_register(line.tokens[1].token.as_str())?,
+            parse_register(line.tokens[2].token.as_str())?,
             0,
             Op::Number(0)), 
         "invalid" if line.tokens.len() == 1 => MatchedInstruction::new(
@@ -221,9 +221,499 @@ pub fn match_instruction(line: &TokenizedLine) -> Option<ParsedLine> {
             0,
             0,
             Op::Number(0)),
-        _ => return None
+        _ => return Err(format!(
+            "Invalid instruction '{}' with {} operands",
+            instruction_identifier,
+            line.tokens.len() - 1
+        ))
     };
 
 
-    Some(ParsedLine::Instruction(dec))
-}
\ No newline at end of file
+    Ok(ParsedLine::Instruction(dec))
+}
+
+fn is_register(operand: &str) -> bool {
+    parse_register_name(operand).is_some()
+}
+
+// Arguments are loaded into r0, r1, ... in order, so a register argument
+// must not name a register that an earlier argument already changed
+fn overwritten_argument<'a>(arguments: &[&'a str]) -> Option<&'a str> {
+    let changed = |register: usize| parse_register_name(arguments[register]) != Some(register as u8);
+    arguments.iter().enumerate().find_map(|(index, &argument)| {
+        parse_register_name(argument)
+            .map(usize::from)
+            .filter(|&register| register < index && changed(register))
+            .map(|_| argument)
+    })
+}
+
+// Expands a pseudo-instruction into lines of instructions, or returns None if
+// the line is not a pseudo-instruction
+#[allow(clippy::cognitive_complexity)]
+pub fn expand_pseudo_instruction(line: &TokenizedLine) -> Option<Result<Vec<String>, String>> {
+    let keyword = line.tokens[0].token.as_str();
+    let operands: Vec<&str> = line.tokens[1..].iter().map(|t| t.token.as_str()).collect();
+    let mut lines = Vec::new();
+
+    match keyword {
+
+        "clr" if operands.len() == 1 && is_register(operands[0]) => {
+            lines.push(format!("xor {}, {}, {}", operands[0], operands[0], operands[0]));
+        }
+        "not" if operands.len() == 1 && is_register(operands[0]) => {
+            lines.push(format!("com {}", operands[0]));
+        }
+        "not" if operands.len() == 2 && is_register(operands[0]) && is_register(operands[1]) => {
+            lines.push(format!("mov {}, {}", operands[0], operands[1]));
+            lines.push(format!("com {}", operands[0]));
+        }
+        "b" if operands.len() == 1 && !is_register(operands[0]) => {
+            lines.push(format!("jmp {}", operands[0]));
+        }
+        "la" if operands.len() == 2 && is_register(operands[0]) && !is_register(operands[1]) => {
+            lines.push(format!("ldi {}, {}", operands[0], operands[1]));
+        }
+        "load" if operands.len() == 2 && is_register(operands[0]) && is_register(operands[1]) => {
+            lines.push(format!("mov {}, {}", operands[0], operands[1]));
+        }
+        "load" if operands.len() == 2 && is_register(operands[0]) && !is_register(operands[1]) => {
+            lines.push(format!("ldi {}, {}", operands[0], operands[1]));
+        }
+        "push" if operands.len() > 1 && is_register(operands[0]) && operands[1..].iter().all(|o| is_register(o)) => {
+            lines.push(format!("push {}", operands[0]));
+            for operand in operands[1..].iter() {
+                lines.push(format!("push {}", operand));
+            }
+        }
+        "pop" if operands.len() > 1 && is_register(operands[0]) && operands[1..].iter().all(|o| is_register(o)) => {
+            lines.push(format!("pop {}", operands[0]));
+            for operand in operands[1..].iter() {
+                lines.push(format!("pop {}", operand));
+            }
+            lines.reverse();
+        }
+        "call" if operands.len() > 1 && !is_register(operands[0]) => {
+            if let Some(register) = overwritten_argument(&operands[1..]) {
+                return Some(Err(format!(
+                    "Argument {} of call is overwritten by an earlier argument", register
+                )));
+            }
+            for (index, operand) in operands[1..].iter().enumerate() {
+                lines.push(format!("load r{}, {}", index, operand));
+            }
+            lines.push(format!("call {}", operands[0]));
+        }
+        "beq" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("cmp {}, {}", operands[0], operands[1]));
+            lines.push(format!("breq {}", operands[2]));
+        }
+        "bne" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("cmp {}, {}", operands[0], operands[1]));
+            lines.push(format!("brne {}", operands[2]));
+        }
+        "bltu" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("cmp {}, {}", operands[0], operands[1]));
+            lines.push(format!("brc {}", operands[2]));
+        }
+        "bgtu" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("cmp {}, {}", operands[1], operands[0]));
+            lines.push(format!("brc {}", operands[2]));
+        }
+        "bgeu" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("cmp {}, {}", operands[0], operands[1]));
+            lines.push(format!("brnc {}", operands[2]));
+        }
+        "bleu" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("cmp {}, {}", operands[1], operands[0]));
+            lines.push(format!("brnc {}", operands[2]));
+        }
+        "blt" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("addi {}, {}, 0x80000000", operands[0], operands[0]));
+            lines.push(format!("addi {}, {}, 0x80000000", operands[1], operands[1]));
+            lines.push(format!("cmp {}, {}", operands[0], operands[1]));
+            lines.push(format!("subi {}, {}, 0x80000000", operands[0], operands[0]));
+            lines.push(format!("subi {}, {}, 0x80000000", operands[1], operands[1]));
+            lines.push(format!("brc {}", operands[2]));
+        }
+        "bgt" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("blt {}, {}, {}", operands[1], operands[0], operands[2]));
+        }
+        "bge" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("addi {}, {}, 0x80000000", operands[0], operands[0]));
+            lines.push(format!("addi {}, {}, 0x80000000", operands[1], operands[1]));
+            lines.push(format!("cmp {}, {}", operands[0], operands[1]));
+            lines.push(format!("subi {}, {}, 0x80000000", operands[0], operands[0]));
+            lines.push(format!("subi {}, {}, 0x80000000", operands[1], operands[1]));
+            lines.push(format!("brnc {}", operands[2]));
+        }
+        "ble" if operands.len() == 3 && is_register(operands[0]) && is_register(operands[1]) && !is_register(operands[2]) => {
+            lines.push(format!("bge {}, {}, {}", operands[1], operands[0], operands[2]));
+        }
+        "brc" if operands.len() == 1 && !is_register(operands[0]) => {
+            lines.push("push r15".into());
+            lines.push("ldi r15, 4".into());
+            lines.push("div r15, sr, r15".into());
+            lines.push("cmpi r15, 0".into());
+            lines.push("pop r15".into());
+            lines.push(format!("brne {}", operands[0]));
+        }
+        "brnc" if operands.len() == 1 && !is_register(operands[0]) => {
+            lines.push("push r15".into());
+            lines.push("ldi r15, 4".into());
+            lines.push("div r15, sr, r15".into());
+            lines.push("cmpi r15, 0".into());
+            lines.push("pop r15".into());
+            lines.push(format!("breq {}", operands[0]));
+        }
+        _ => return None
+    }
+
+    Some(Ok(lines))
+}
+
+// Instructions and pseudo-instructions with their operand kinds
+pub const MNEMONICS: &[Mnemonic] = &[
+    Mnemonic {
+        keyword: "nop",
+        operands: &[],
+        opcode: Some(0x00),
+        description: "N op",
+    },
+    Mnemonic {
+        keyword: "halt",
+        operands: &[],
+        opcode: Some(0x01),
+        description: "Halt",
+    },
+    Mnemonic {
+        keyword: "inc",
+        operands: &["reg"],
+        opcode: Some(0x10),
+        description: "Increment",
+    },
+    Mnemonic {
+        keyword: "dec",
+        operands: &["reg"],
+        opcode: Some(0x11),
+        description: "Decrement",
+    },
+    Mnemonic {
+        keyword: "add",
+        operands: &["reg", "reg", "reg"],
+        opcode: Some(0x12),
+        description: "Add",
+    },
+    Mnemonic {
+        keyword: "sub",
+        operands: &["reg", "reg", "reg"],
+        opcode: Some(0x13),
+        description: "Subtract",
+    },
+    Mnemonic {
+        keyword: "mul",
+        operands: &["reg", "reg", "reg"],
+        opcode: Some(0x14),
+        description: "Multiply",
+    },
+    Mnemonic {
+        keyword: "div",
+        operands: &["reg", "reg", "reg"],
+        opcode: Some(0x15),
+        description: "Divide",
+    },
+    Mnemonic {
+        keyword: "cmp",
+        operands: &["reg", "reg"],
+        opcode: Some(0x16),
+        description: "Compare",
+    },
+    Mnemonic {
+        keyword: "cmpi",
+        operands: &["reg", "op"],
+        opcode: Some(0x17),
+        description: "Compare immediate",
+    },
+    Mnemonic {
+        keyword: "addi",
+        operands: &["reg", "reg", "op"],
+        opcode: Some(0x18),
+        description: "Add immediate",
+    },
+    Mnemonic {
+        keyword: "subi",
+        operands: &["reg", "reg", "op"],
+        opcode: Some(0x19),
+        description: "Subtract immediate",
+    },
+    Mnemonic {
+        keyword: "or",
+        operands: &["reg", "reg", "reg"],
+        opcode: Some(0x20),
+        description: "Or",
+    },
+    Mnemonic {
+        keyword: "and",
+        operands: &["reg", "reg", "reg"],
+        opcode: Some(0x21),
+        description: "And",
+    },
+    Mnemonic {
+        keyword: "xor",
+        operands: &["reg", "reg", "reg"],
+        opcode: Some(0x22),
+        description: "X or",
+    },
+    Mnemonic {
+        keyword: "neg",
+        operands: &["reg"],
+        opcode: Some(0x23),
+        description: "Negate",
+    },
+    Mnemonic {
+        keyword: "com",
+        operands: &["reg"],
+        opcode: Some(0x24),
+        description: "Complement",
+    },
+    Mnemonic {
+        keyword: "ldi",
+        operands: &["reg", "op"],
+        opcode: Some(0x30),
+        description: "Load immediate",
+    },
+    Mnemonic {
+        keyword: "ld",
+        operands: &["reg", "reg"],
+        opcode: Some(0x31),
+        description: "Load",
+    },
+    Mnemonic {
+        keyword: "ldb",
+        operands: &["reg", "reg"],
+        opcode: Some(0x32),
+        description: "Load byte",
+    },
+    Mnemonic {
+        keyword: "ldd",
+        operands: &["reg", "op"],
+        opcode: Some(0x33),
+        description: "Load direct",
+    },
+    Mnemonic {
+        keyword: "lddb",
+        operands: &["reg", "op"],
+        opcode: Some(0x34),
+        description: "Load direct byte",
+    },
+    Mnemonic {
+        keyword: "st",
+        operands: &["reg", "reg"],
+        opcode: Some(0x35),
+        description: "Store",
+    },
+    Mnemonic {
+        keyword: "stb",
+        operands: &["reg", "reg"],
+        opcode: Some(0x36),
+        description: "Store byte",
+    },
+    Mnemonic {
+        keyword: "std",
+        operands: &["reg", "op"],
+        opcode: Some(0x37),
+        description: "Store direct",
+    },
+    Mnemonic {
+        keyword: "stdb",
+        operands: &["reg", "op"],
+        opcode: Some(0x38),
+        description: "Store direct byte",
+    },
+    Mnemonic {
+        keyword: "push",
+        operands: &["reg"],
+        opcode: Some(0x39),
+        description: "Push",
+    },
+    Mnemonic {
+        keyword: "pop",
+        operands: &["reg"],
+        opcode: Some(0x3A),
+        description: "Pop",
+    },
+    Mnemonic {
+        keyword: "jmp",
+        operands: &["op"],
+        opcode: Some(0x40),
+        description: "Jump",
+    },
+    Mnemonic {
+        keyword: "call",
+        operands: &["op"],
+        opcode: Some(0x41),
+        description: "Call",
+    },
+    Mnemonic {
+        keyword: "ret",
+        operands: &[],
+        opcode: Some(0x42),
+        description: "Return",
+    },
+    Mnemonic {
+        keyword: "breq",
+        operands: &["op"],
+        opcode: Some(0x50),
+        description: "Branch equal",
+    },
+    Mnemonic {
+        keyword: "brne",
+        operands: &["op"],
+        opcode: Some(0x51),
+        description: "Branch not equal",
+    },
+    Mnemonic {
+        keyword: "mov",
+        operands: &["reg", "reg"],
+        opcode: Some(0x60),
+        description: "Move",
+    },
+    Mnemonic {
+        keyword: "invalid",
+        operands: &[],
+        opcode: Some(0xFF),
+        description: "Invalid",
+    },
+    Mnemonic {
+        keyword: "clr",
+        operands: &["reg"],
+        opcode: None,
+        description: "Sets a register to zero",
+    },
+    Mnemonic {
+        keyword: "not",
+        operands: &["reg"],
+        opcode: None,
+        description: "Inverts all bits of a register",
+    },
+    Mnemonic {
+        keyword: "not",
+        operands: &["reg", "reg"],
+        opcode: None,
+        description: "Stores the inverted bits of the second register in the first",
+    },
+    Mnemonic {
+        keyword: "b",
+        operands: &["op"],
+        opcode: None,
+        description: "Branches unconditionally",
+    },
+    Mnemonic {
+        keyword: "la",
+        operands: &["reg", "op"],
+        opcode: None,
+        description: "Loads the address of a label into a register",
+    },
+    Mnemonic {
+        keyword: "load",
+        operands: &["reg", "reg"],
+        opcode: None,
+        description: "Copies a register",
+    },
+    Mnemonic {
+        keyword: "load",
+        operands: &["reg", "op"],
+        opcode: None,
+        description: "Loads a value into a register",
+    },
+    Mnemonic {
+        keyword: "push",
+        operands: &["reg", "regs"],
+        opcode: None,
+        description: "Pushes several registers, from left to right",
+    },
+    Mnemonic {
+        keyword: "pop",
+        operands: &["reg", "regs"],
+        opcode: None,
+        description: "Pops several registers pushed with the same register list",
+    },
+    Mnemonic {
+        keyword: "call",
+        operands: &["op", "args"],
+        opcode: None,
+        description: "Loads the arguments into r0, r1, ... and calls a function",
+    },
+    Mnemonic {
+        keyword: "beq",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if both registers are equal",
+    },
+    Mnemonic {
+        keyword: "bne",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the registers are not equal",
+    },
+    Mnemonic {
+        keyword: "bltu",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is lower (unsigned)",
+    },
+    Mnemonic {
+        keyword: "bgtu",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is greater (unsigned)",
+    },
+    Mnemonic {
+        keyword: "bgeu",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is greater or equal (unsigned)",
+    },
+    Mnemonic {
+        keyword: "bleu",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is lower or equal (unsigned)",
+    },
+    Mnemonic {
+        keyword: "blt",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is less (signed)",
+    },
+    Mnemonic {
+        keyword: "bgt",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is greater (signed)",
+    },
+    Mnemonic {
+        keyword: "bge",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is greater or equal (signed)",
+    },
+    Mnemonic {
+        keyword: "ble",
+        operands: &["reg", "reg", "op"],
+        opcode: None,
+        description: "Branches if the first register is less or equal (signed)",
+    },
+    Mnemonic {
+        keyword: "brc",
+        operands: &["op"],
+        opcode: None,
+        description: "Branches if the carry flag is set, saving r15 on the stack",
+    },
+    Mnemonic {
+        keyword: "brnc",
+        operands: &["op"],
+        opcode: None,
+        description: "Branches if the carry flag is clear, saving r15 on the stack",
+    },
+];
@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::assembler::error::{AsmError, Result};
+use crate::assembler::parser::unescape;
+use crate::assembler::tokenizer::{tokenize, SourceFile, TokenizedLine};
+
+// Reads and tokenizes a source file, including all files it includes.
+pub fn load(file: &Rc<SourceFile>, include_paths: &[PathBuf]) -> Result<Vec<TokenizedLine>> {
+    let text = fs::read_to_string(&file.path).map_err(|e| match &file.included_from {
+        Some(location) => AsmError::Include(location.clone(), format!("{}: {}", file.path, e)),
+        None => AsmError::IO(io::Error::new(e.kind(), format!("{}: {}", file.path, e))),
+    })?;
+
+    expand(tokenize(&mut Cursor::new(text), file), include_paths)
+}
+
+// Replaces every `.include "file.asm"` directive with the contents of the file
+pub fn expand(lines: Vec<TokenizedLine>, include_paths: &[PathBuf]) -> Result<Vec<TokenizedLine>> {
+    let mut output = Vec::new();
+
+    for line in lines {
+        if line.tokens[0].token != ".include" {
+            output.push(line);
+            continue;
+        }
+
+        let location = line.line.location();
+        let name = parse_include(&line).map_err(|e| AsmError::Syntax(location.clone(), e))?;
+
+        let path = resolve(&name, &location.file, include_paths).ok_or_else(|| {
+            AsmError::Include(location.clone(), format!("File \"{}\" not found", name))
+        })?;
+
+        if is_included(&path, &location.file) {
+            return Err(AsmError::Include(
+                location,
+                format!("Circular include of \"{}\"", name),
+            ));
+        }
+
+        let file = SourceFile::included(&path.to_string_lossy(), location);
+        output.extend(load(&file, include_paths)?);
+    }
+
+    Ok(output)
+}
+
+fn parse_include(line: &TokenizedLine) -> std::result::Result<String, String> {
+    let usage = || "Expected '.include \"file\"'".to_string();
+
+    if line.tokens.len() != 2 {
+        return Err(usage());
+    }
+
+    let literal = &line.tokens[1].token;
+    if literal.len() < 2 || !literal.starts_with('"') || !literal.ends_with('"') {
+        return Err(usage());
+    }
+
+    unescape(&literal[1..literal.len() - 1])
+}
+
+fn resolve(name: &str, including: &SourceFile, include_paths: &[PathBuf]) -> Option<PathBuf> {
+    let directory = Path::new(&including.path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+
+    std::iter::once(directory)
+        .chain(include_paths.iter().map(PathBuf::as_path))
+        .map(|directory| directory.join(name))
+        .find(|path| path.is_file())
+}
+
+// Checks whether the path is the including file or one of the files that
+// (transitively) included it
+fn is_included(path: &Path, including: &SourceFile) -> bool {
+    let canonical = match fs::canonicalize(path) {
+        Ok(canonical) => canonical,
+        Err(_) => return false,
+    };
+
+    let mut file = Some(including);
+    while let Some(current) = file {
+        if fs::canonicalize(&current.path).ok().as_ref() == Some(&canonical) {
+            return true;
+        }
+        file = current.included_from.as_ref().map(|l| l.file.as_ref());
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // Creates a fresh directory containing the given files
+    fn create_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = env::temp_dir().join(format!("mycpu-include-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        for (path, contents) in files {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        directory
+    }
+
+    fn load_path(path: &Path, include_paths: &[PathBuf]) -> Result<Vec<TokenizedLine>> {
+        load(&SourceFile::new(&path.to_string_lossy()), include_paths)
+    }
+
+    #[test]
+    fn test_include() {
+        let directory = create_files(
+            "nested",
+            &[
+                ("main.asm", ".include \"lib/a.asm\"\nhalt\n"),
+                ("lib/a.asm", "nop\n.include \"b.asm\"\n"),
+                ("lib/b.asm", "inc r0\n"),
+            ],
+        );
+
+        let lines = load_path(&directory.join("main.asm"), &[]).unwrap();
+        let texts: Vec<&str> = lines.iter().map(|l| l.line.text.as_str()).collect();
+        assert_eq!(texts, vec!["nop", "inc r0", "halt"]);
+
+        let chain = lines[1].line.file.include_chain();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[0].line_number, 2);
+        assert_eq!(chain[1].line_number, 1);
+    }
+
+    #[test]
+    fn test_include_path() {
+        let directory = create_files(
+            "paths",
+            &[
+                ("src/main.asm", ".include \"runtime.asm\"\n"),
+                ("lib/runtime.asm", "halt\n"),
+            ],
+        );
+
+        let main = directory.join("src/main.asm");
+        assert!(load_path(&main, &[]).is_err());
+        let lines = load_path(&main, &[directory.join("lib")]).unwrap();
+        assert_eq!(lines.len(), 1);
+    }
+
+    #[test]
+    fn test_circular_include() {
+        let directory = create_files(
+            "circular",
+            &[
+                ("a.asm", ".include \"b.asm\"\n"),
+                ("b.asm", "nop\n.include \"a.asm\"\n"),
+            ],
+        );
+
+        match load_path(&directory.join("a.asm"), &[]) {
+            Err(error @ AsmError::Include(..)) => {
+                let message = error.to_string();
+                assert!(message.contains("b.asm:2: IncludeError: Circular include"));
+                assert!(message.contains("included from"));
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use crate::assembler::error::{AsmError, Result};
+use crate::assembler::expression::is_anonymous_label_reference;
+use crate::assembler::parser::{Op, ParsedLine, Statement};
+
+fn is_local(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+fn is_anonymous(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_digit())
+}
+
+// Unique name of the n-th definition of an anonymous label
+fn anonymous_name(label: &str, ordinal: usize) -> String {
+    format!("{}@{}", label, ordinal)
+}
+
+struct Scope {
+    global: Option<String>,
+    // Number of definitions of each anonymous label so far, and in total
+    defined: HashMap<String, usize>,
+    total: HashMap<String, usize>,
+}
+
+impl Scope {
+    // Returns the unique name of a symbol at the current position, or the
+    // name itself as error if it can not be resolved.
+    fn rename(&self, name: &str) -> std::result::Result<String, String> {
+        if is_local(name) {
+            return Ok(match &self.global {
+                Some(global) => format!("{}{}", global, name),
+                None => name.into(),
+            });
+        }
+
+        if !is_anonymous_label_reference(name) {
+            return Ok(name.into());
+        }
+
+        let label = &name[..name.len() - 1];
+        let defined = self.defined.get(label).cloned().unwrap_or(0);
+        let total = self.total.get(label).cloned().unwrap_or(0);
+
+        let ordinal = if name.ends_with('b') {
+            defined.checked_sub(1)
+        } else if defined < total {
+            Some(defined)
+        } else {
+            None
+        };
+
+        ordinal
+            .map(|ordinal| anonymous_name(label, ordinal))
+            .ok_or_else(|| name.into())
+    }
+}
+
+// Gives local labels (.name) and anonymous labels (1:) unique names.
+// Local labels belong to the previous global label, anonymous labels are
+// referenced as 1b (previous definition) or 1f (next definition). Labels
+// defined by macro expansions do not start a new scope.
+pub fn resolve(statements: &mut [Statement]) -> Result<()> {
+    let mut scope = Scope {
+        global: None,
+        defined: HashMap::new(),
+        total: HashMap::new(),
+    };
+
+    for statement in statements.iter() {
+        if let ParsedLine::Label(name) = &statement.parsed {
+            if is_anonymous(name) {
+                *scope.total.entry(name.clone()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    for statement in statements.iter_mut() {
+        let location = statement.line.location();
+
+        let result = match &mut statement.parsed {
+            ParsedLine::Label(name) if is_anonymous(name) => {
+                let defined = scope.defined.entry(name.clone()).or_insert(0);
+                *name = anonymous_name(name, *defined);
+                *defined += 1;
+                Ok(())
+            }
+            ParsedLine::Label(name) if is_anonymous_label_reference(name) => {
+                return Err(AsmError::Syntax(
+                    location,
+                    format!("Invalid label name '{}'", name),
+                ));
+            }
+            ParsedLine::Label(name) if is_local(name) => {
+                *name = scope.rename(name).unwrap();
+                Ok(())
+            }
+            ParsedLine::Label(name) => {
+                if statement.line.macro_depth == 0 {
+                    scope.global = Some(name.clone());
+                }
+                Ok(())
+            }
+            ParsedLine::Align(_)
+            | ParsedLine::Section(_)
+            | ParsedLine::Ascii(_)
+            | ParsedLine::Space(_)
+            | ParsedLine::Global(_)
+            | ParsedLine::Extern(_) => Ok(()),
+            ParsedLine::Bytes(values) | ParsedLine::Words(values) => values
+                .iter_mut()
+                .try_for_each(|value| value.rename_symbols(&mut |name| scope.rename(name))),
+            ParsedLine::Instruction(instruction) => match &mut instruction.op {
+                Op::Number(_) => Ok(()),
+                Op::Label(name) => scope.rename(name).map(|renamed| *name = renamed),
+                Op::Expression(expr) => expr.rename_symbols(&mut |name| scope.rename(name)),
+            },
+            ParsedLine::Constant(name, value) | ParsedLine::Variable(name, value) => {
+                // The parser rejects anonymous label references as names
+                *name = scope.rename(name).unwrap();
+                value.rename_symbols(&mut |name| scope.rename(name))
+            }
+        };
+
+        result.map_err(|name| AsmError::UndefinedSymbol(location, name))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::macros;
+    use crate::assembler::parser::parse;
+    use crate::assembler::tokenizer::{tokenize, SourceFile};
+    use std::io::Cursor;
+
+    fn resolve_str(source: &str) -> Result<Vec<String>> {
+        let lines = tokenize(&mut Cursor::new(source), &SourceFile::new("test.asm"));
+        let mut statements = parse(macros::expand(lines)?)?;
+        resolve(&mut statements)?;
+
+        Ok(statements
+            .into_iter()
+            .map(|statement| match statement.parsed {
+                ParsedLine::Label(name) => format!("{}:", name),
+                ParsedLine::Instruction(instruction) => match instruction.op {
+                    Op::Label(name) => name,
+                    op => format!("{:?}", op),
+                },
+                ParsedLine::Constant(name, _) | ParsedLine::Variable(name, _) => name,
+                parsed => format!("{:?}", parsed),
+            })
+            .collect())
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let names = resolve_str(
+            "jmp .loop\n\
+             first:\n\
+             .loop:\n\
+             jmp .loop\n\
+             second:\n\
+             jmp .loop\n\
+             .loop:\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            names,
+            vec![
+                ".loop",
+                "first:",
+                "first.loop:",
+                "first.loop",
+                "second:",
+                "second.loop",
+                "second.loop:"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_anonymous_labels() {
+        let names = resolve_str(
+            "1:\n\
+             jmp 1f\n\
+             jmp 1b\n\
+             1:\n\
+             jmp 1b\n",
+        )
+        .unwrap();
+
+        assert_eq!(names, vec!["1@0:", "1@1", "1@0", "1@1:", "1@1"]);
+    }
+
+    #[test]
+    fn test_macro_labels_keep_scope() {
+        let names = resolve_str(
+            ".macro wait\n\
+             wait\\@:\n\
+             jmp wait\\@\n\
+             .endm\n\
+             function:\n\
+             wait\n\
+             jmp .end\n",
+        )
+        .unwrap();
+
+        assert_eq!(names[3], "function.end");
+    }
+
+    #[test]
+    fn test_unresolved_anonymous_label() {
+        match resolve_str("1:\njmp 1f") {
+            Err(AsmError::UndefinedSymbol(location, name)) => {
+                assert_eq!(location.line_number, 2);
+                assert_eq!(name, "1f");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(resolve_str("jmp 2b\n2:").is_err());
+    }
+
+    #[test]
+    fn test_anonymous_reference_as_name() {
+        for source in ["1f:\njmp 1f\n", ".equ 1b, 5\n", ".set 2f, 1\n"] {
+            match resolve_str(source) {
+                Err(AsmError::Syntax(location, _)) => assert_eq!(location.line_number, 1),
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+    }
+}
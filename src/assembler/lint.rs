@@ -0,0 +1,423 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::assembler::expression::Expr;
+use crate::assembler::parser::{Op, ParsedLine, Statement, REGISTER_NAMES};
+use crate::assembler::tokenizer::{split_comment, Line};
+use crate::common::generated::instruction::Instruction::{self, *};
+use crate::emulator::cpu::{registers, Register};
+
+// Lints over the parsed statements of a program. A lint is suppressed for a
+// line by a comment like `// lint: allow(unused-label, unbalanced-stack)`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    UnreachableCode,
+    UnusedLabel,
+    BranchWithoutCompare,
+    SpecialRegisterWrite,
+    UninitializedRegister,
+    UnbalancedStack,
+}
+
+impl Lint {
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::UnusedLabel => "unused-label",
+            Lint::BranchWithoutCompare => "branch-without-compare",
+            Lint::SpecialRegisterWrite => "special-register-write",
+            Lint::UninitializedRegister => "uninitialized-register",
+            Lint::UnbalancedStack => "unbalanced-stack",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: Line,
+    pub lint: Lint,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: warning[{}]: {}",
+            self.line.location(),
+            self.lint.name(),
+            self.message
+        )
+    }
+}
+
+// Names of the lints allowed by a comment of the line
+fn allowed(line: &Line) -> Vec<&str> {
+    let comment = match split_comment(&line.text).1 {
+        Some(comment) => comment.trim_start_matches('/').trim(),
+        None => return Vec::new(),
+    };
+
+    comment
+        .strip_prefix("lint:")
+        .map(str::trim_start)
+        .and_then(|rest| rest.strip_prefix("allow("))
+        .and_then(|rest| rest.split(')').next())
+        .map_or(Vec::new(), |names| {
+            names.split(',').map(str::trim).collect()
+        })
+}
+
+fn is_branch(instruction: Instruction) -> bool {
+    instruction == BranchEqual || instruction == BranchNotEqual
+}
+
+// Instructions after which execution does not continue with the next one
+fn ends_flow(instruction: Instruction) -> bool {
+    instruction == Jump || instruction == Return || instruction == Halt
+}
+
+// Labels that start a function, local and anonymous labels are renamed to
+// contain '.' or '@'
+fn is_function_label(name: &str) -> bool {
+    !name.contains(['.', '@'])
+}
+
+fn add_symbols<'a>(expr: &'a Expr, symbols: &mut HashSet<&'a str>) {
+    symbols.extend(expr.symbols());
+}
+
+fn referenced_symbols(statements: &[Statement]) -> HashSet<&str> {
+    let mut symbols = HashSet::new();
+
+    for statement in statements {
+        match &statement.parsed {
+            ParsedLine::Instruction(instruction) => match &instruction.op {
+                Op::Label(name) => {
+                    symbols.insert(name.as_str());
+                }
+                Op::Expression(expr) => add_symbols(expr, &mut symbols),
+                Op::Number(_) => {}
+            },
+            ParsedLine::Constant(_, expr) | ParsedLine::Variable(_, expr) => {
+                add_symbols(expr, &mut symbols)
+            }
+            ParsedLine::Bytes(exprs) | ParsedLine::Words(exprs) => {
+                for expr in exprs {
+                    add_symbols(expr, &mut symbols);
+                }
+            }
+            ParsedLine::Global(names) => symbols.extend(names.iter().map(String::as_str)),
+            _ => {}
+        }
+    }
+
+    symbols
+}
+
+fn unused_labels(statements: &[Statement], warnings: &mut Vec<Warning>) {
+    let referenced = referenced_symbols(statements);
+
+    for statement in statements {
+        if let ParsedLine::Label(name) = &statement.parsed {
+            if !referenced.contains(name.as_str()) {
+                warnings.push(Warning {
+                    line: statement.line.clone(),
+                    lint: Lint::UnusedLabel,
+                    message: format!("Label '{}' is never used", name),
+                });
+            }
+        }
+    }
+}
+
+// Reports the first instruction of every sequence that can not be reached,
+// because it follows a jmp, ret or halt without a label in between.
+fn unreachable_code(statements: &[Statement], warnings: &mut Vec<Warning>) {
+    let mut reachable = true;
+
+    for statement in statements {
+        match &statement.parsed {
+            ParsedLine::Label(_) | ParsedLine::Section(_) => reachable = true,
+            ParsedLine::Instruction(instruction) => {
+                if !reachable {
+                    warnings.push(Warning {
+                        line: statement.line.clone(),
+                        lint: Lint::UnreachableCode,
+                        message: "Instruction is never executed".into(),
+                    });
+                    // Only report the first instruction of the sequence
+                    reachable = true;
+                }
+                if ends_flow(instruction.instruction) {
+                    reachable = false;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Branches test the flags set by cmp and cmpi. Flags are unknown at the
+// start of a basic block, calls are assumed to set them.
+fn branches_without_compare(statements: &[Statement], warnings: &mut Vec<Warning>) {
+    let mut flags_set = false;
+
+    for statement in statements {
+        match &statement.parsed {
+            ParsedLine::Label(_) | ParsedLine::Section(_) => flags_set = false,
+            ParsedLine::Instruction(instruction) => {
+                let regs = [instruction.reg1, instruction.reg2, instruction.reg3];
+                let (_, written) = registers(instruction.instruction, regs);
+
+                if is_branch(instruction.instruction) && !flags_set {
+                    warnings.push(Warning {
+                        line: statement.line.clone(),
+                        lint: Lint::BranchWithoutCompare,
+                        message: "Branch without a preceding compare".into(),
+                    });
+                }
+                if written.contains(&(Register::SR as u8)) || instruction.instruction == Call {
+                    flags_set = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn special_register_writes(statements: &[Statement], warnings: &mut Vec<Warning>) {
+    for statement in statements {
+        if let ParsedLine::Instruction(instruction) = &statement.parsed {
+            let message = match (instruction.instruction, instruction.reg1) {
+                (Move, reg) if reg == Register::PC as u8 => "Write to pc with mov, use jmp",
+                (Move, reg) if reg == Register::SR as u8 => "Write to sr with mov",
+                _ => continue,
+            };
+            warnings.push(Warning {
+                line: statement.line.clone(),
+                lint: Lint::SpecialRegisterWrite,
+                message: message.into(),
+            });
+        }
+    }
+}
+
+// Reports the first read of every register that no instruction of the
+// program writes. The program counter is set by the CPU.
+fn uninitialized_registers(statements: &[Statement], warnings: &mut Vec<Warning>) {
+    let accesses: Vec<(&Statement, Vec<u8>, Vec<u8>)> = statements
+        .iter()
+        .filter_map(|statement| match &statement.parsed {
+            ParsedLine::Instruction(i) => {
+                let (read, written) = registers(i.instruction, [i.reg1, i.reg2, i.reg3]);
+                Some((statement, read, written))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut written: HashSet<u8> = accesses
+        .iter()
+        .flat_map(|(_, _, written)| written.iter().cloned())
+        .collect();
+    written.insert(Register::PC as u8);
+
+    for (statement, read, _) in &accesses {
+        for &register in read {
+            // Reported registers are not reported again
+            if written.insert(register) {
+                warnings.push(Warning {
+                    line: statement.line.clone(),
+                    lint: Lint::UninitializedRegister,
+                    message: format!(
+                        "Register {} is read but never written",
+                        REGISTER_NAMES[register as usize]
+                    ),
+                });
+            }
+        }
+    }
+}
+
+// Checks that every ret of a function is reached with as many pops as
+// pushes. The stack depth at a label is the depth of the fallthrough, or of
+// the first branch to it.
+fn unbalanced_stacks(statements: &[Statement], warnings: &mut Vec<Warning>) {
+    let mut function: Option<&str> = None;
+    let mut depth: i64 = 0;
+    let mut reachable = true;
+    let mut depths: HashMap<&str, i64> = HashMap::new();
+
+    for statement in statements {
+        match &statement.parsed {
+            ParsedLine::Label(name) if is_function_label(name) => {
+                function = Some(name);
+                depth = 0;
+                reachable = true;
+                depths.clear();
+            }
+            ParsedLine::Label(name) => {
+                if !reachable {
+                    depth = depths.get(name.as_str()).cloned().unwrap_or(0);
+                }
+                reachable = true;
+            }
+            ParsedLine::Instruction(instruction) => {
+                let kind = instruction.instruction;
+                match kind {
+                    Push => depth += 1,
+                    Pop => depth -= 1,
+                    Return if depth != 0 => {
+                        let (count, what) = if depth > 0 {
+                            (depth, "push")
+                        } else {
+                            (-depth, "pop")
+                        };
+                        warnings.push(Warning {
+                            line: statement.line.clone(),
+                            lint: Lint::UnbalancedStack,
+                            message: format!(
+                                "{} returns with {} unmatched {}{}",
+                                function.map_or("Code".into(), |f| format!("Function '{}'", f)),
+                                count,
+                                what,
+                                if count == 1 { "" } else { "es" }
+                            ),
+                        });
+                    }
+                    _ => {}
+                }
+
+                if is_branch(kind) || kind == Jump {
+                    if let Op::Label(target) = &instruction.op {
+                        depths.entry(target.as_str()).or_insert(depth);
+                    }
+                }
+                if ends_flow(kind) {
+                    reachable = false;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn lint(statements: &[Statement]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    unreachable_code(statements, &mut warnings);
+    unused_labels(statements, &mut warnings);
+    branches_without_compare(statements, &mut warnings);
+    special_register_writes(statements, &mut warnings);
+    uninitialized_registers(statements, &mut warnings);
+    unbalanced_stacks(statements, &mut warnings);
+
+    warnings.retain(|warning| !allowed(&warning.line).contains(&warning.lint.name()));
+    warnings.sort_by(|a, b| {
+        let key = |w: &Warning| (w.line.file.path.clone(), w.line.line_number);
+        key(a).cmp(&key(b))
+    });
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::codegen::{parse_source, Options};
+    use std::io::Cursor;
+
+    fn lint_str(source: &str) -> Vec<(Lint, usize)> {
+        let statements =
+            parse_source("test.asm", &mut Cursor::new(source), &Options::default()).unwrap();
+        lint(&statements)
+            .iter()
+            .map(|warning| (warning.lint, warning.line.line_number))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_program() {
+        let source = "\
+ldi sp, MEMORY_END
+call print
+halt
+print:
+push r1
+ldi r1, 'A'
+cmpi r1, 0
+breq .skip
+stdb r1, CONSOLEIO_START
+.skip:
+pop r1
+ret
+";
+        assert_eq!(lint_str(source), vec![]);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let source = "\
+start:
+ldi sp, MEMORY_END
+jmp .end
+inc r1
+.end:
+breq .end
+mov pc, r2
+mov sr, r3  // lint: allow(special-register-write, uninitialized-register)
+f:
+push r4
+ret
+";
+        assert_eq!(
+            lint_str(source),
+            vec![
+                (Lint::UnusedLabel, 1),
+                (Lint::UnreachableCode, 4),
+                (Lint::BranchWithoutCompare, 6),
+                (Lint::SpecialRegisterWrite, 7),
+                (Lint::UninitializedRegister, 7),
+                (Lint::UnusedLabel, 9),
+                (Lint::UninitializedRegister, 10),
+                (Lint::UnbalancedStack, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_depth_at_branch_targets() {
+        let source = "\
+ldi sp, MEMORY_END
+call f
+halt
+f:
+push r0
+cmpi r0, 0
+breq .other
+pop r0
+ret
+.other:
+pop r0
+ret
+";
+        assert_eq!(lint_str(source), vec![]);
+    }
+
+    #[test]
+    fn test_allowed() {
+        let line = |text: &str| Line {
+            file: crate::assembler::tokenizer::SourceFile::new("a.asm"),
+            line_number: 1,
+            text: text.into(),
+            macro_depth: 0,
+        };
+        assert_eq!(
+            allowed(&line("ret // lint: allow(a, b) reason")),
+            vec!["a", "b"]
+        );
+        assert!(allowed(&line("ret // allow(a)")).is_empty());
+        assert!(allowed(&line("ret")).is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::assembler::tokenizer::Line;
+
+// Assembly listing as written by `asm -l`. Every source line is shown next to
+// its address and the bytes it produced, lines from macro expansions and
+// included files are indented by their nesting depth. The listing ends with
+// the symbol table, sorted by name and by address.
+
+const BYTES_PER_ROW: usize = 8;
+const INDENT: usize = 2;
+
+// A statement and the bytes emitted for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub line: Line,
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Listing {
+    pub entries: Vec<Entry>,
+    pub symbols: Vec<(String, u32)>,
+}
+
+fn same_line(a: &Line, b: &Line) -> bool {
+    Rc::ptr_eq(&a.file, &b.file)
+        && a.line_number == b.line_number
+        && a.macro_depth == b.macro_depth
+        && a.text == b.text
+}
+
+fn depth(line: &Line) -> usize {
+    line.macro_depth + line.file.include_chain().len()
+}
+
+fn write_row(f: &mut fmt::Formatter, address: u32, bytes: &[u8], source: &str) -> fmt::Result {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    let row = format!("{:08X}  {:<23}  {}", address, bytes.join(" "), source);
+    writeln!(f, "{}", row.trim_end())
+}
+
+fn write_symbols(f: &mut fmt::Formatter, title: &str, symbols: &[&(String, u32)]) -> fmt::Result {
+    writeln!(f)?;
+    writeln!(f, "{}", title)?;
+    for (name, address) in symbols {
+        writeln!(f, "{:08X}  {}", address, name)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut previous: Option<&Line> = None;
+
+        for entry in &self.entries {
+            let line = &entry.line;
+            let continued = previous.is_some_and(|p| same_line(p, line));
+            let indent = " ".repeat(INDENT * depth(line));
+
+            // Mark the start of every included file
+            if previous.is_none_or(|p| !Rc::ptr_eq(&p.file, &line.file)) {
+                writeln!(f, "{:42}{}// {}", "", indent, line.file.path)?;
+            }
+            previous = Some(line);
+
+            // Statements without bytes only show up once per line
+            if continued && entry.bytes.is_empty() {
+                continue;
+            }
+
+            let source = if continued {
+                String::new()
+            } else {
+                format!("{:>5}  {}{}", line.line_number, indent, line.text.trim())
+            };
+
+            let mut rows = entry.bytes.chunks(BYTES_PER_ROW);
+            let first = rows.next().unwrap_or(&[]);
+            write_row(f, entry.address, first, &source)?;
+            for (index, row) in rows.enumerate() {
+                let address = entry.address + ((index + 1) * BYTES_PER_ROW) as u32;
+                write_row(f, address, row, "")?;
+            }
+        }
+
+        let mut symbols: Vec<&(String, u32)> = self.symbols.iter().collect();
+        symbols.sort_by(|a, b| a.0.cmp(&b.0));
+        write_symbols(f, "Symbols by name:", &symbols)?;
+
+        symbols.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        write_symbols(f, "Symbols by address:", &symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::codegen::{assemble_files, assemble_statements, parse_files, Options};
+    use crate::assembler::tokenizer::SourceFile;
+
+    fn line(file: &Rc<SourceFile>, line_number: usize, text: &str, macro_depth: usize) -> Line {
+        Line {
+            file: file.clone(),
+            line_number,
+            text: text.into(),
+            macro_depth,
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let file = SourceFile::new("main.asm");
+        let listing = Listing {
+            entries: vec![
+                Entry {
+                    line: line(&file, 1, "start:", 0),
+                    address: 0x10_0000,
+                    bytes: Vec::new(),
+                },
+                Entry {
+                    line: line(&file, 2, "  push r1 // save", 1),
+                    address: 0x10_0000,
+                    bytes: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                },
+                Entry {
+                    line: line(&file, 2, "  push r1 // save", 1),
+                    address: 0x10_0008,
+                    bytes: vec![9, 10],
+                },
+            ],
+            symbols: vec![("start".into(), 0x10_0000), ("SIZE".into(), 0x20)],
+        };
+
+        assert_eq!(
+            listing.to_string(),
+            "                                          // main.asm\n\
+             00100000                               1  start:\n\
+             00100000  01 02 03 04 05 06 07 08      2    push r1 // save\n\
+             00100008  09 0A\n\
+             \n\
+             Symbols by name:\n\
+             00000020  SIZE\n\
+             00100000  start\n\
+             \n\
+             Symbols by address:\n\
+             00000020  SIZE\n\
+             00100000  start\n"
+        );
+    }
+
+    #[test]
+    fn test_assemble_statements() {
+        let paths = ["testdata/alphabet.asm"];
+        let statements = parse_files(&paths, &Options::default()).unwrap();
+        let (image, listing) = assemble_statements(&statements, &Options::default()).unwrap();
+
+        let call = &listing.entries[1];
+        assert_eq!(call.line.text, "    call    print_alphabet");
+        assert_eq!(call.address, 0x10_0008);
+        assert_eq!(call.bytes.len(), 8);
+
+        let text = listing.to_string();
+        assert!(text.contains("00100008  "));
+        assert!(listing
+            .symbols
+            .contains(&("print_alphabet".into(), 0x10_0018)));
+
+        // The image is the same as without a listing
+        assert_eq!(image, assemble_files(&paths, &Options::default()).unwrap());
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use crate::assembler::error::{AsmError, Result};
+use crate::assembler::tokenizer::{tokenize_line, Line, QuoteState, Token, TokenizedLine};
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+// Limits the total number of invocations, macros that invoke others several
+// times grow exponentially with the depth
+const MAX_EXPANSIONS: usize = 100_000;
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<String>,
+}
+
+struct MacroExpander {
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+}
+
+// Joins the tokens of a line back into a single line of text
+fn join_tokens(tokens: &[Token]) -> String {
+    let operands: Vec<&str> = tokens[1..].iter().map(|t| t.token.as_str()).collect();
+    if operands.is_empty() {
+        tokens[0].token.clone()
+    } else {
+        format!("{} {}", tokens[0].token, operands.join(", "))
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Reads a macro definition up to the matching .endm directive. Definition
+// lines look like `.macro name param1, param2`.
+fn read_definition(
+    header: &TokenizedLine,
+    lines: &mut dyn Iterator<Item = TokenizedLine>,
+) -> Result<(String, Macro)> {
+    let location = header.line.location();
+
+    if header.tokens.len() < 2 {
+        return Err(AsmError::Syntax(location, "Missing macro name".into()));
+    }
+
+    let header_operands: Vec<&str> = header.tokens[1..]
+        .iter()
+        .map(|t| t.token.as_str())
+        .collect();
+    let signature: Vec<String> = tokenize_line(&header_operands.join(", "))
+        .into_iter()
+        .map(|t| t.token)
+        .collect();
+
+    if let Some(invalid) = signature.iter().find(|s| !is_identifier(s)) {
+        return Err(AsmError::Syntax(
+            location,
+            format!("Invalid macro name or parameter '{}'", invalid),
+        ));
+    }
+
+    let name = signature[0].clone();
+    let parameters = signature[1..].to_vec();
+    let mut body = Vec::new();
+
+    for line in lines {
+        match line.tokens[0].token.as_str() {
+            ".endm" => return Ok((name, Macro { parameters, body })),
+            ".macro" => {
+                return Err(AsmError::Syntax(
+                    line.line.location(),
+                    "Nested macro definition".into(),
+                ))
+            }
+            _ => body.push(join_tokens(&line.tokens)),
+        }
+    }
+
+    Err(AsmError::Syntax(
+        location,
+        format!("Unterminated macro '{}'", name),
+    ))
+}
+
+// Replaces \parameter with the corresponding argument and \@ with a number
+// that is unique for every expansion. Character and string literals are left
+// untouched.
+fn substitute(
+    text: &str,
+    parameters: &[String],
+    arguments: &[&str],
+    expansion: usize,
+) -> std::result::Result<String, String> {
+    let mut result = String::new();
+    let mut state = QuoteState::default();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if !state.update(c) || c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        if chars.peek() == Some(&'@') {
+            chars.next();
+            result.push_str(&expansion.to_string());
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+
+        match parameters.iter().position(|p| *p == name) {
+            Some(index) => result.push_str(arguments[index]),
+            None => return Err(format!("Unknown macro parameter '\\{}'", name)),
+        }
+    }
+
+    Ok(result)
+}
+
+impl MacroExpander {
+    fn expand_line(
+        &mut self,
+        line: TokenizedLine,
+        depth: usize,
+        output: &mut Vec<TokenizedLine>,
+    ) -> Result<()> {
+        let name = line.tokens[0].token.as_str();
+        let location = line.line.location();
+
+        let (parameters, body) = match self.macros.get(name) {
+            Some(m) => (m.parameters.clone(), m.body.clone()),
+            None => {
+                output.push(line);
+                return Ok(());
+            }
+        };
+
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(AsmError::Syntax(
+                location,
+                format!(
+                    "Expansion of macro '{}' exceeds the maximum depth of {}",
+                    name, MAX_EXPANSION_DEPTH
+                ),
+            ));
+        }
+
+        let arguments: Vec<&str> = line.tokens[1..].iter().map(|t| t.token.as_str()).collect();
+        if arguments.len() != parameters.len() {
+            return Err(AsmError::Syntax(
+                location,
+                format!(
+                    "Macro '{}' expects {} arguments, got {}",
+                    name,
+                    parameters.len(),
+                    arguments.len()
+                ),
+            ));
+        }
+
+        if self.expansions >= MAX_EXPANSIONS {
+            return Err(AsmError::Syntax(
+                location,
+                format!(
+                    "Expansion of macro '{}' exceeds the maximum of {} macro invocations",
+                    name, MAX_EXPANSIONS
+                ),
+            ));
+        }
+
+        self.expansions += 1;
+        let expansion = self.expansions;
+
+        for body_line in &body {
+            let text = substitute(body_line, &parameters, &arguments, expansion)
+                .map_err(|e| AsmError::Syntax(location.clone(), e))?;
+            let tokens = tokenize_line(&text);
+            if tokens.is_empty() {
+                continue;
+            }
+
+            // Expanded lines keep the line number of the invocation
+            let expanded = TokenizedLine {
+                line: Line {
+                    file: location.file.clone(),
+                    line_number: location.line_number,
+                    text,
+                    macro_depth: depth + 1,
+                },
+                tokens,
+            };
+            self.expand_line(expanded, depth + 1, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Collects macro definitions and replaces every invocation with the macro
+// body. Macros have to be defined before they are used.
+pub fn expand(lines: Vec<TokenizedLine>) -> Result<Vec<TokenizedLine>> {
+    let mut expander = MacroExpander {
+        macros: HashMap::new(),
+        expansions: 0,
+    };
+    let mut output = Vec::new();
+    let mut lines = lines.into_iter();
+
+    while let Some(line) = lines.next() {
+        let location = line.line.location();
+        match line.tokens[0].token.as_str() {
+            ".macro" => {
+                let (name, definition) = read_definition(&line, &mut lines)?;
+                if expander.macros.insert(name.clone(), definition).is_some() {
+                    return Err(AsmError::DuplicateSymbol(location, name));
+                }
+            }
+            ".endm" => {
+                return Err(AsmError::Syntax(
+                    location,
+                    "'.endm' without '.macro'".into(),
+                ))
+            }
+            _ => expander.expand_line(line, 0, &mut output)?,
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::tokenizer::{tokenize, SourceFile};
+    use std::io::Cursor;
+
+    fn expand_str(source: &str) -> Result<Vec<String>> {
+        let lines = expand(tokenize(
+            &mut Cursor::new(source),
+            &SourceFile::new("test.asm"),
+        ))?;
+        Ok(lines.into_iter().map(|l| l.line.text).collect())
+    }
+
+    #[test]
+    fn test_substitution() {
+        let lines = expand_str(
+            ".macro save reg1, reg2\n\
+             push \\reg1 // first\n\
+             push \\reg2\n\
+             .endm\n\
+             save r0, r1\n\
+             halt\n",
+        )
+        .unwrap();
+
+        assert_eq!(lines, vec!["push r0", "push r1", "halt"]);
+    }
+
+    #[test]
+    fn test_unique_labels() {
+        let lines = expand_str(
+            ".macro wait\n\
+             loop\\@:\n\
+             jmp loop\\@\n\
+             .endm\n\
+             wait\n\
+             wait\n",
+        )
+        .unwrap();
+
+        assert_eq!(lines, vec!["loop1:", "jmp loop1", "loop2:", "jmp loop2"]);
+    }
+
+    #[test]
+    fn test_nested_invocation() {
+        let lines = expand(tokenize(
+            &mut Cursor::new(
+                ".macro inner value\n\
+             ldi r0, \\value\n\
+             .endm\n\
+             .macro outer\n\
+             inner '\\n'\n\
+             inner 2\n\
+             .endm\n\
+             outer\n",
+            ),
+            &SourceFile::new("test.asm"),
+        ))
+        .unwrap();
+
+        let texts: Vec<&str> = lines.iter().map(|l| l.line.text.as_str()).collect();
+        assert_eq!(texts, vec!["ldi r0, '\\n'", "ldi r0, 2"]);
+        assert_eq!(lines[0].line.line_number, 8);
+        assert_eq!(lines[0].line.macro_depth, 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let recursive = ".macro forever\nforever\n.endm\nforever\n";
+        match expand_str(recursive) {
+            Err(AsmError::Syntax(location, message)) => {
+                assert_eq!(location.line_number, 4);
+                assert!(message.contains("maximum depth"));
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        // Every level doubles the number of invocations
+        let mut exponential = String::from(".macro m0\nnop\n.endm\n");
+        for level in 1..40 {
+            exponential.push_str(&format!(
+                ".macro m{0}\nm{1}\nm{1}\n.endm\n",
+                level,
+                level - 1
+            ));
+        }
+        exponential.push_str("m39\n");
+        match expand_str(&exponential) {
+            Err(AsmError::Syntax(location, message)) => {
+                assert_eq!(location.line_number, 160);
+                assert!(message.contains("maximum of 100000 macro invocations"));
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        assert!(expand_str(".macro m a\nnop\n.endm\nm\n").is_err());
+        assert!(expand_str(".macro m\nldi r0, \\x\n.endm\nm\n").is_err());
+        assert!(expand_str(".macro m\nnop\n").is_err());
+        assert!(expand_str(".endm\n").is_err());
+        assert!(expand_str(".macro m\n.endm\n.macro m\n.endm\n").is_err());
+    }
+}
@@ -1,12 +1,180 @@
 extern crate mycpu;
 
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use mycpu::assembler::codegen::assemble_file;
+use mycpu::assembler::codegen::{
+    assemble_object_statements, assemble_statements, parse_files, Options,
+};
+use mycpu::assembler::lint::lint;
+use mycpu::assembler::parser::{parse_literal, Section};
+use mycpu::common::image::Image;
+use mycpu::common::{ihex, srec};
+
+const USAGE: &str =
+    "Usage: asm [-c] [-s] [-l] [-W] [-o OUTPUT] [-O bin|raw|ihex|srec] [-e SYMBOL] \
+                     [-I DIR]... [--section NAME=ADDRESS]... FILE...";
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+// Only the executable format stores everything in an image, the other formats
+// silently lose parts of it
+fn warn_lost(image: &Image, format: &str) {
+    let mut lost = Vec::new();
+    // A raw image is entered at its first byte
+    let start = image.segments.iter().map(|segment| segment.address).min();
+    if format == "raw" && start != Some(image.entry) {
+        lost.push("entry point");
+    }
+    if image.stack.is_some() {
+        lost.push("stack bounds");
+    }
+    if !image.symbols.is_empty() {
+        lost.push("symbols");
+    }
+    if image.debug.is_some() {
+        lost.push("debug information");
+    }
+
+    if !lost.is_empty() {
+        eprintln!(
+            "Warning: {} output does not store the {}",
+            format,
+            lost.join(", ")
+        );
+    }
+}
+
+// Parses a section base address like ".data=0x180000"
+fn parse_section_base(arg: &str) -> Result<(Section, u32), String> {
+    let mut parts = arg.splitn(2, '=');
+    let name = parts.next().unwrap_or("");
+    let address = parts
+        .next()
+        .ok_or_else(|| format!("Expected NAME=ADDRESS, found '{}'", arg))?;
+
+    let section = Section::from_name(name).ok_or_else(|| format!("Unknown section '{}'", name))?;
+    Ok((section, parse_literal(address)?))
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let bytes = assemble_file(&args[1]).unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut options = Options::default();
+    let mut files = Vec::new();
+    let mut object = false;
+    let mut strip = false;
+    let mut list = false;
+    let mut warnings = false;
+    let mut format = "bin";
+    let mut output = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-I" {
+            match iter.next() {
+                Some(path) => options.include_paths.push(PathBuf::from(path)),
+                None => exit_with("Missing directory after -I"),
+            }
+        } else if let Some(path) = arg.strip_prefix("-I") {
+            options.include_paths.push(PathBuf::from(path));
+        } else if arg == "-c" {
+            object = true;
+        } else if arg == "-O" {
+            match iter.next().map(String::as_str) {
+                Some(name @ "bin") | Some(name @ "raw") | Some(name @ "ihex")
+                | Some(name @ "srec") => format = name,
+                _ => exit_with("Expected bin, raw, ihex or srec after -O"),
+            }
+        } else if arg == "-s" {
+            strip = true;
+        } else if arg == "-l" {
+            list = true;
+        } else if arg == "-W" {
+            warnings = true;
+        } else if arg == "-e" {
+            match iter.next() {
+                Some(symbol) => options.entry = Some(symbol.clone()),
+                None => exit_with("Missing symbol after -e"),
+            }
+        } else if arg == "-o" {
+            match iter.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => exit_with("Missing file after -o"),
+            }
+        } else if arg == "--section" {
+            let base = iter
+                .next()
+                .ok_or_else(|| "Missing NAME=ADDRESS after --section".to_string())
+                .and_then(|arg| parse_section_base(arg));
+            match base {
+                Ok(base) => options.section_bases.push(base),
+                Err(e) => exit_with(&e),
+            }
+        } else {
+            files.push(arg.as_str());
+        }
+    }
+
+    if files.is_empty() {
+        exit_with(USAGE);
+    }
+
+    let statements = match parse_files(&files, &options) {
+        Ok(statements) => statements,
+        Err(e) => exit_with(&e.to_string()),
+    };
+
+    // Lint warnings do not stop the assembly
+    if warnings {
+        for warning in lint(&statements) {
+            eprintln!("{}", warning);
+        }
+    }
+
+    // Object files and executables are written to OUTPUT, or next to the
+    // first source file
+    let (bytes, extension, listing) = if object {
+        match assemble_object_statements(&statements, &options) {
+            Ok((object, listing)) => (object.to_bytes(), "o", listing),
+            Err(e) => exit_with(&e.to_string()),
+        }
+    } else {
+        match assemble_statements(&statements, &options) {
+            Ok((mut image, listing)) => {
+                if strip {
+                    image.symbols.clear();
+                    image.debug = None;
+                }
+                if format != "bin" {
+                    warn_lost(&image, format);
+                }
+                match format {
+                    "raw" => (image.to_raw(), "raw", listing),
+                    "ihex" => (ihex::write(&image).into_bytes(), "hex", listing),
+                    "srec" => (srec::write(&image).into_bytes(), "srec", listing),
+                    _ => (image.to_bytes(), "bin", listing),
+                }
+            }
+            Err(e) => exit_with(&e.to_string()),
+        }
+    };
+
+    let output = output.unwrap_or_else(|| Path::new(files[0]).with_extension(extension));
+    if let Err(e) = fs::write(&output, bytes) {
+        exit_with(&format!("IOError: {}: {}", output.display(), e));
+    }
 
-    println!("{:#?}", bytes);
+    // The listing is written next to the output
+    if list {
+        let path = output.with_extension("lst");
+        if let Err(e) = fs::write(&path, listing.to_string()) {
+            exit_with(&format!("IOError: {}: {}", path.display(), e));
+        }
+    }
 }
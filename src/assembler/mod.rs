@@ -1,4 +1,12 @@
 pub mod codegen;
+pub mod error;
+pub mod expression;
+pub mod format;
 pub mod generated;
+pub mod include;
+pub mod labels;
+pub mod lint;
+pub mod listing;
+pub mod macros;
 pub mod parser;
 pub mod tokenizer;
@@ -1,18 +1,99 @@
+use crate::assembler::error::{AsmError, Result};
+use crate::assembler::expression::{is_anonymous_label_reference, parse_expression, Expr};
 use crate::assembler::generated::matcher;
-use crate::assembler::tokenizer::TokenizedLine;
+use crate::assembler::tokenizer::{tokenize_line, Line, TokenizedLine};
 use crate::common::generated::instruction::Instruction;
-use std::str::FromStr;
+use crate::common::image;
+use crate::common::object::{is_valid_alignment, MAX_ALIGNMENT};
+use std::num::IntErrorKind;
 
 #[derive(Debug)]
 pub enum ParsedLine {
     Instruction(MatchedInstruction),
     Label(String),
+    // .equ NAME, value
+    Constant(String, Expr),
+    // .set NAME, value; may be redefined by later .set directives
+    Variable(String, Expr),
+    // .align N; pads to a multiple of N bytes, with nop instructions in code
+    Align(u32),
+    Section(Section),
+    // .byte and .word values
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+    // .ascii and .asciz strings, .space N
+    Ascii(Vec<u8>),
+    Space(u32),
+    // .global and .extern symbol lists, only used for object files
+    Global(Vec<String>),
+    Extern(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Section {
+    Text,
+    ROData,
+    Data,
+    BSS,
+}
+
+// Sections in the order they are laid out in memory
+pub const SECTIONS: [Section; 4] = [Section::Text, Section::ROData, Section::Data, Section::BSS];
+
+impl Section {
+    pub fn from_name(name: &str) -> Option<Section> {
+        SECTIONS.iter().cloned().find(|s| s.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Text => ".text",
+            Section::ROData => ".rodata",
+            Section::Data => ".data",
+            Section::BSS => ".bss",
+        }
+    }
+
+    // Prefix of the NAME_START and NAME_END symbols
+    pub fn symbol_prefix(self) -> &'static str {
+        match self {
+            Section::Text => "TEXT",
+            Section::ROData => "RODATA",
+            Section::Data => "DATA",
+            Section::BSS => "BSS",
+        }
+    }
+
+    pub fn permissions(self) -> u8 {
+        match self {
+            Section::Text => image::READ | image::EXECUTE,
+            Section::ROData => image::READ,
+            Section::Data | Section::BSS => image::READ | image::WRITE,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Op {
     Number(u32),
     Label(String),
+    Expression(Expr),
+}
+
+#[derive(Debug)]
+pub struct Statement {
+    pub line: Line,
+    pub parsed: ParsedLine,
+}
+
+// Keyword of an instruction or pseudo-instruction, see MNEMONICS. Operand
+// kinds are named like in pseudo_instructions.yaml.
+#[derive(Debug)]
+pub struct Mnemonic {
+    pub keyword: &'static str,
+    pub operands: &'static [&'static str],
+    pub opcode: Option<u8>,
+    pub description: &'static str,
 }
 
 #[derive(Debug)]
@@ -36,45 +117,132 @@ impl MatchedInstruction {
     }
 }
 
+// Register names, indexed by register number
+pub const REGISTER_NAMES: [&str; 19] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "r13", "r14",
+    "r15", "pc", "sp", "sr",
+];
+
 pub fn parse_register_name(name: &str) -> Option<u8> {
-    match name {
-        "r0" => Some(0),
-        "r1" => Some(1),
-        "r2" => Some(2),
-        "r3" => Some(3),
-        "r4" => Some(4),
-        "r5" => Some(5),
-        "r6" => Some(6),
-        "r7" => Some(7),
-        "r8" => Some(8),
-        "r9" => Some(9),
-        "r10" => Some(10),
-        "r11" => Some(11),
-        "r12" => Some(12),
-        "r13" => Some(13),
-        "r14" => Some(14),
-        "r15" => Some(15),
-        "pc" => Some(16),
-        "sp" => Some(17),
-        "sr" => Some(18),
-        _ => None,
-    }
+    REGISTER_NAMES
+        .iter()
+        .position(|&register| register == name)
+        .map(|index| index as u8)
 }
 
 pub fn parse_numeric_literal(literal: &str) -> Option<u32> {
-    if literal.starts_with("0x") {
-        let without_prefix = literal.trim_left_matches("0x");
-        u32::from_str_radix(without_prefix, 16).ok()
-    } else {
-        u32::from_str(literal).ok()
+    parse_literal(literal).ok()
+}
+
+// Parses decimal, hexadecimal (0x), binary (0b) and octal (0o) numbers with
+// optional underscores between digits, as well as character literals. Negative
+// numbers are returned in two's complement.
+pub fn parse_literal(literal: &str) -> std::result::Result<u32, String> {
+    if literal.starts_with('\'') {
+        return parse_char_literal(literal);
+    }
+
+    let (negative, unsigned) = match literal.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, literal),
+    };
+
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") | Some("0X") => (16, &unsigned[2..]),
+        Some("0b") | Some("0B") => (2, &unsigned[2..]),
+        Some("0o") | Some("0O") => (8, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+
+    let invalid = || format!("Invalid numeric literal '{}'", literal);
+    let out_of_range = || format!("Numeric literal '{}' out of range", literal);
+
+    // Underscores may only separate digits
+    if !digits.starts_with(|c: char| c.is_digit(radix))
+        || !digits.chars().all(|c| c.is_digit(radix) || c == '_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+    {
+        return Err(invalid());
+    }
+
+    let value =
+        u32::from_str_radix(&digits.replace('_', ""), radix).map_err(|e| match e.kind() {
+            IntErrorKind::PosOverflow => out_of_range(),
+            _ => invalid(),
+        })?;
+
+    match (negative, value) {
+        (false, value) => Ok(value),
+        (true, value) if value <= 0x8000_0000 => Ok(value.wrapping_neg()),
+        _ => Err(out_of_range()),
     }
 }
 
-pub fn parse_operand(s: &str) -> Option<Op> {
-    if let Some(number) = parse_numeric_literal(s) {
-        Some(Op::Number(number))
-    } else {
-        Some(Op::Label(s.into()))
+fn parse_char_literal(literal: &str) -> std::result::Result<u32, String> {
+    let invalid = || format!("Invalid character literal {}", literal);
+
+    if literal.len() < 3 || !literal.ends_with('\'') {
+        return Err(invalid());
+    }
+
+    let unescaped = unescape(&literal[1..literal.len() - 1])?;
+    let mut chars = unescaped.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c as u32),
+        _ => Err(invalid()),
+    }
+}
+
+// Replaces the escape sequences \n, \r, \t, \0, \\, \', \" and \xHH
+pub fn unescape(s: &str) -> std::result::Result<String, String> {
+    let mut result = String::new();
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => char::from(byte),
+                    _ => return Err(format!("Invalid escape sequence '\\x{}'", hex)),
+                }
+            }
+            Some(c) => return Err(format!("Invalid escape sequence '\\{}'", c)),
+            None => return Err("Incomplete escape sequence".into()),
+        };
+        result.push(escaped);
+    }
+
+    Ok(result)
+}
+
+pub fn parse_register(name: &str) -> std::result::Result<u8, String> {
+    parse_register_name(name).ok_or_else(|| format!("Invalid register '{}'", name))
+}
+
+pub fn parse_operand(s: &str) -> std::result::Result<Op, String> {
+    if s.starts_with(|c: char| c.is_ascii_digit() || c == '\'' || c == '-') {
+        if let Ok(number) = parse_literal(s) {
+            return Ok(Op::Number(number));
+        }
+    }
+
+    match parse_expression(s)? {
+        Expr::Symbol(name) => Ok(Op::Label(name)),
+        expr => Ok(Op::Expression(expr)),
     }
 }
 
@@ -87,18 +255,244 @@ pub fn parse_label(token: &TokenizedLine) -> Option<ParsedLine> {
     }
 }
 
-pub fn parse(tokens: Vec<TokenizedLine>) -> Option<Vec<ParsedLine>> {
-    let mut parsed_instructions = Vec::new();
+fn parse_symbol_definition(line: &TokenizedLine) -> std::result::Result<(String, Expr), String> {
+    let directive = &line.tokens[0].token;
+    if line.tokens.len() != 3 {
+        return Err(format!("Expected '{} NAME, value'", directive));
+    }
 
-    for token in tokens {
-        if let Some(instr) = parse_label(&token) {
-            parsed_instructions.push(instr);
-        } else if let Some(instr) = matcher::match_instruction(&token) {
-            parsed_instructions.push(instr);
-        } else {
-            return None;
+    let name = match parse_expression(&line.tokens[1].token)? {
+        Expr::Symbol(name) if !is_anonymous_label_reference(&name) => name,
+        _ => return Err(format!("Invalid symbol name '{}'", line.tokens[1].token)),
+    };
+    let value = parse_expression(&line.tokens[2].token)?;
+
+    Ok((name, value))
+}
+
+fn parse_symbol_list(line: &TokenizedLine) -> std::result::Result<Vec<String>, String> {
+    let directive = &line.tokens[0].token;
+    if line.tokens.len() < 2 {
+        return Err(format!("Expected '{} NAME, ...'", directive));
+    }
+
+    line.tokens[1..]
+        .iter()
+        .map(|token| match parse_expression(&token.token)? {
+            Expr::Symbol(name) => Ok(name),
+            _ => Err(format!("Invalid symbol name '{}'", token.token)),
+        })
+        .collect()
+}
+
+fn parse_align(line: &TokenizedLine) -> std::result::Result<ParsedLine, String> {
+    if line.tokens.len() != 2 {
+        return Err("Expected '.align N'".into());
+    }
+
+    match parse_literal(&line.tokens[1].token)? {
+        alignment if is_valid_alignment(alignment) => Ok(ParsedLine::Align(alignment)),
+        alignment => Err(format!(
+            "Alignment {} is not a power of two up to {}",
+            alignment, MAX_ALIGNMENT
+        )),
+    }
+}
+
+fn parse_section(line: &TokenizedLine) -> std::result::Result<ParsedLine, String> {
+    let name = match line.tokens.len() {
+        2 => line.tokens[1].token.as_str(),
+        _ => return Err("Expected '.section NAME'".into()),
+    };
+
+    Section::from_name(name)
+        .map(ParsedLine::Section)
+        .ok_or_else(|| format!("Unknown section '{}'", name))
+}
+
+fn parse_values(line: &TokenizedLine) -> std::result::Result<Vec<Expr>, String> {
+    if line.tokens.len() < 2 {
+        return Err(format!("Expected '{} value, ...'", line.tokens[0].token));
+    }
+
+    line.tokens[1..]
+        .iter()
+        .map(|token| parse_expression(&token.token))
+        .collect()
+}
+
+fn parse_strings(line: &TokenizedLine, terminate: bool) -> std::result::Result<ParsedLine, String> {
+    if line.tokens.len() < 2 {
+        return Err(format!(
+            "Expected '{} \"string\", ...'",
+            line.tokens[0].token
+        ));
+    }
+
+    let mut bytes = Vec::new();
+    for token in &line.tokens[1..] {
+        let literal = &token.token;
+        if literal.len() < 2 || !literal.starts_with('"') || !literal.ends_with('"') {
+            return Err(format!("Invalid string literal {}", literal));
+        }
+
+        // Characters up to \xFF are stored as single bytes, all others as UTF-8
+        for c in unescape(&literal[1..literal.len() - 1])?.chars() {
+            match c as u32 {
+                byte @ 0..=0xFF => bytes.push(byte as u8),
+                _ => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        if terminate {
+            bytes.push(0);
+        }
+    }
+
+    Ok(ParsedLine::Ascii(bytes))
+}
+
+fn parse_space(line: &TokenizedLine) -> std::result::Result<ParsedLine, String> {
+    match line.tokens.len() {
+        2 => Ok(ParsedLine::Space(parse_literal(&line.tokens[1].token)?)),
+        _ => Err("Expected '.space N'".into()),
+    }
+}
+
+pub fn parse_directive(line: &TokenizedLine) -> Option<std::result::Result<ParsedLine, String>> {
+    let directive = line.tokens[0].token.as_str();
+    let parsed = match directive {
+        ".align" => parse_align(line),
+        ".section" => parse_section(line),
+        ".text" | ".rodata" | ".data" | ".bss" if line.tokens.len() == 1 => {
+            Ok(ParsedLine::Section(Section::from_name(directive).unwrap()))
+        }
+        ".byte" => parse_values(line).map(ParsedLine::Bytes),
+        ".word" => parse_values(line).map(ParsedLine::Words),
+        ".ascii" => parse_strings(line, false),
+        ".asciz" => parse_strings(line, true),
+        ".space" => parse_space(line),
+        ".global" => parse_symbol_list(line).map(ParsedLine::Global),
+        ".extern" => parse_symbol_list(line).map(ParsedLine::Extern),
+        ".equ" => {
+            parse_symbol_definition(line).map(|(name, value)| ParsedLine::Constant(name, value))
+        }
+        ".set" => {
+            parse_symbol_definition(line).map(|(name, value)| ParsedLine::Variable(name, value))
+        }
+        _ => return None,
+    };
+
+    Some(parsed)
+}
+
+const MAX_PSEUDO_DEPTH: usize = 16;
+
+fn parse_instruction(
+    line: &TokenizedLine,
+    depth: usize,
+    statements: &mut Vec<Statement>,
+) -> Result<()> {
+    if let Some(expansion) = matcher::expand_pseudo_instruction(line) {
+        let expansion = expansion.map_err(|e| AsmError::Syntax(line.line.location(), e))?;
+        if depth >= MAX_PSEUDO_DEPTH {
+            return Err(AsmError::Syntax(
+                line.line.location(),
+                "Pseudo-instruction expansion too deep".into(),
+            ));
         }
+
+        // The expanded instructions keep the original source line
+        for text in expansion {
+            let expanded = TokenizedLine {
+                line: line.line.clone(),
+                tokens: tokenize_line(&text),
+            };
+            parse_instruction(&expanded, depth + 1, statements)?;
+        }
+        return Ok(());
+    }
+
+    let parsed =
+        matcher::match_instruction(line).map_err(|e| AsmError::Syntax(line.line.location(), e))?;
+    statements.push(Statement {
+        line: line.line.clone(),
+        parsed,
+    });
+
+    Ok(())
+}
+
+pub fn parse(tokens: Vec<TokenizedLine>) -> Result<Vec<Statement>> {
+    let mut statements = Vec::new();
+
+    for token in tokens {
+        let parsed = match parse_label(&token) {
+            Some(label) => label,
+            None => match parse_directive(&token) {
+                Some(directive) => {
+                    directive.map_err(|e| AsmError::Syntax(token.line.location(), e))?
+                }
+                None => {
+                    parse_instruction(&token, 0, &mut statements)?;
+                    continue;
+                }
+            },
+        };
+
+        statements.push(Statement {
+            line: token.line,
+            parsed,
+        });
+    }
+
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(parse_literal("42"), Ok(42));
+        assert_eq!(parse_literal("0x2A"), Ok(42));
+        assert_eq!(parse_literal("0b101010"), Ok(42));
+        assert_eq!(parse_literal("0o52"), Ok(42));
+        assert_eq!(parse_literal("1_000_000"), Ok(1_000_000));
+        assert_eq!(parse_literal("0xFFFF_FFFF"), Ok(0xFFFF_FFFF));
+        assert_eq!(parse_literal("-1"), Ok(0xFFFF_FFFF));
+        assert_eq!(parse_literal("-0x80000000"), Ok(0x8000_0000));
     }
 
-    Some(parsed_instructions)
+    #[test]
+    fn test_parse_literal_errors() {
+        assert!(parse_literal("0x1_0000_0000")
+            .unwrap_err()
+            .contains("out of range"));
+        assert!(parse_literal("-0x80000001")
+            .unwrap_err()
+            .contains("out of range"));
+        assert!(parse_literal("0b102").is_err());
+        assert!(parse_literal("0x").is_err());
+        assert!(parse_literal("0x_1").is_err());
+        assert!(parse_literal("0x1_").is_err());
+        assert!(parse_literal("1_").is_err());
+        assert!(parse_literal("1__000").is_err());
+        assert!(parse_literal("_1").is_err());
+        assert!(parse_literal("-_1").is_err());
+        assert!(parse_literal("12ab").is_err());
+        assert!(parse_literal("--1").is_err());
+    }
+
+    #[test]
+    fn test_parse_char_literal() {
+        assert_eq!(parse_literal("'A'"), Ok(65));
+        assert_eq!(parse_literal("'\\n'"), Ok(10));
+        assert_eq!(parse_literal("'\\''"), Ok(39));
+        assert_eq!(parse_literal("'\\x7F'"), Ok(127));
+        assert_eq!(parse_literal("','"), Ok(44));
+        assert!(parse_literal("'AB'").is_err());
+        assert!(parse_literal("''").is_err());
+        assert!(parse_literal("'\\q'").is_err());
+    }
 }
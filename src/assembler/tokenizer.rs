@@ -1,12 +1,72 @@
+use std::fmt;
 use std::io::BufRead;
+use std::rc::Rc;
 
-#[derive(Debug, PartialOrd, PartialEq)]
+// A source file, and the location of the .include directive that included it
+#[derive(Debug, PartialEq)]
+pub struct SourceFile {
+    pub path: String,
+    pub included_from: Option<Location>,
+}
+
+impl SourceFile {
+    pub fn new(path: &str) -> Rc<Self> {
+        Rc::new(SourceFile {
+            path: path.into(),
+            included_from: None,
+        })
+    }
+
+    pub fn included(path: &str, included_from: Location) -> Rc<Self> {
+        Rc::new(SourceFile {
+            path: path.into(),
+            included_from: Some(included_from),
+        })
+    }
+
+    // Locations of the .include directives leading to this file, innermost first
+    pub fn include_chain(&self) -> Vec<&Location> {
+        let mut chain = Vec::new();
+        let mut current = self.included_from.as_ref();
+        while let Some(location) = current {
+            chain.push(location);
+            current = location.file.included_from.as_ref();
+        }
+        chain
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: Rc<SourceFile>,
+    pub line_number: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file.path, self.line_number)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Line {
+    pub file: Rc<SourceFile>,
     pub line_number: usize,
     pub text: String,
+    // Number of nested macro expansions the line was produced by
+    pub macro_depth: usize,
 }
 
-#[derive(Debug)]
+impl Line {
+    pub fn location(&self) -> Location {
+        Location {
+            file: self.file.clone(),
+            line_number: self.line_number,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Token {
     pub token: String,
     pub position: usize,
@@ -18,18 +78,54 @@ pub struct TokenizedLine {
     pub tokens: Vec<Token>,
 }
 
-fn strip_comments(line: String) -> String {
-    match line.find("//") {
-        Some(location) => line[..location].into(),
-        None => line,
+// Tracks whether a position in a line is inside a character or string
+// literal, so that commas and comment markers in literals are kept.
+#[derive(Default)]
+pub(crate) struct QuoteState {
+    quote: Option<char>,
+    escaped: bool,
+}
+
+impl QuoteState {
+    // Returns true if the character is outside of any literal
+    pub(crate) fn update(&mut self, c: char) -> bool {
+        match self.quote {
+            Some(_) if self.escaped => self.escaped = false,
+            Some(_) if c == '\\' => self.escaped = true,
+            Some(quote) if c == quote => self.quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => self.quote = Some(c),
+            None => return true,
+        }
+        false
+    }
+}
+
+// Splits a line into code and the comment starting with //, if any
+pub(crate) fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut state = QuoteState::default();
+    let mut previous_slash = false;
+
+    for (index, c) in line.char_indices() {
+        let outside = state.update(c);
+        if outside && c == '/' && previous_slash {
+            return (&line[..index - 1], Some(&line[index - 1..]));
+        }
+        previous_slash = outside && c == '/';
     }
+
+    (line, None)
+}
+
+fn strip_comments(line: String) -> String {
+    split_comment(&line).0.into()
 }
 
 fn trim(line: &str) -> String {
     line.trim().into()
 }
 
-fn split_lines(reader: &mut BufRead) -> Vec<Line> {
+fn split_lines(reader: &mut dyn BufRead, file: &Rc<SourceFile>) -> Vec<Line> {
     let mut result = Vec::new();
 
     for (mut line_number, text_result) in reader.lines().enumerate() {
@@ -37,25 +133,62 @@ fn split_lines(reader: &mut BufRead) -> Vec<Line> {
 
         line_number += 1;
 
-        result.push(Line { line_number, text })
+        result.push(Line {
+            file: file.clone(),
+            line_number,
+            text,
+            macro_depth: 0,
+        })
     }
 
     result
 }
 
-fn tokenize_line(text: &str) -> Vec<Token> {
-    let replaced = text.replace(",", " ");
-    replaced
-        .split_whitespace()
-        .map(|s| Token {
-            token: s.into(),
-            position: 0,
-        })
-        .collect()
+fn make_token(text: &str, start: usize, end: usize) -> Option<Token> {
+    let token = text[start..end].trim();
+    if token.is_empty() {
+        return None;
+    }
+
+    let position = start + text[start..end].find(token).unwrap_or(0);
+    Some(Token {
+        token: token.into(),
+        position,
+    })
 }
 
-pub fn tokenize(reader: &mut BufRead) -> Vec<TokenizedLine> {
-    let lines = split_lines(reader);
+// The first word of a line is the mnemonic, the remaining text is split into
+// operands at commas. Operands may contain whitespace, e.g. `(END - START) / 4`.
+pub(crate) fn tokenize_line(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    let operands_start = text.find(char::is_whitespace).unwrap_or(text.len());
+    tokens.extend(make_token(text, 0, operands_start));
+
+    let mut depth = 0;
+    let mut state = QuoteState::default();
+    let mut start = operands_start;
+    for (index, c) in text.char_indices().skip_while(|(i, _)| *i < operands_start) {
+        if !state.update(c) {
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                tokens.extend(make_token(text, start, index));
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    tokens.extend(make_token(text, start, text.len()));
+
+    tokens
+}
+
+pub fn tokenize(reader: &mut dyn BufRead, file: &Rc<SourceFile>) -> Vec<TokenizedLine> {
+    let lines = split_lines(reader, file);
 
     let mut tokenized_lines = Vec::new();
 
@@ -92,36 +225,71 @@ mod tests {
     #[test]
     fn test_trim_no_change() {
         let s = "foobar";
-        assert_eq!(&trim(s.into()), s);
+        assert_eq!(&trim(s), s);
     }
 
     #[test]
     fn test_trim() {
         let s = "  foobar   \t";
-        assert_eq!(&trim(s.into()), "foobar");
+        assert_eq!(&trim(s), "foobar");
     }
 
     #[test]
     fn test_split_lines_empty() {
         let mut s = Cursor::new("abc\n\nghi");
-        let res = split_lines(&mut s);
+        let file = SourceFile::new("test.asm");
+        let res = split_lines(&mut s, &file);
 
         assert_eq!(
             res,
             vec![
                 Line {
+                    file: file.clone(),
                     line_number: 1,
-                    text: "abc".into()
+                    text: "abc".into(),
+                    macro_depth: 0
                 },
                 Line {
+                    file: file.clone(),
                     line_number: 2,
-                    text: "".into()
+                    text: "".into(),
+                    macro_depth: 0
                 },
                 Line {
+                    file: file.clone(),
                     line_number: 3,
-                    text: "ghi".into()
+                    text: "ghi".into(),
+                    macro_depth: 0
                 }
             ]
         );
     }
+
+    #[test]
+    fn test_strip_comments_in_literal() {
+        let s = "ldi r0, '/' // slash";
+        assert_eq!(&strip_comments(s.into()), "ldi r0, '/' ");
+        let s = ".include \"a//b.asm\"";
+        assert_eq!(&strip_comments(s.into()), s);
+    }
+
+    fn token_strings(text: &str) -> Vec<String> {
+        tokenize_line(text).into_iter().map(|t| t.token).collect()
+    }
+
+    #[test]
+    fn test_tokenize_line() {
+        assert_eq!(token_strings("halt"), vec!["halt"]);
+        assert_eq!(
+            token_strings("add r0,r1, r2"),
+            vec!["add", "r0", "r1", "r2"]
+        );
+        assert_eq!(
+            token_strings("ldi r0, (BUF_END - BUF) / 4"),
+            vec!["ldi", "r0", "(BUF_END - BUF) / 4"]
+        );
+        assert_eq!(tokenize_line("ldi r0,  label + 8")[2].position, 9);
+        assert_eq!(token_strings("ldi r0, ','"), vec!["ldi", "r0", "','"]);
+        assert_eq!(token_strings("ldi r0, '\\''"), vec!["ldi", "r0", "'\\''"]);
+    }
 }
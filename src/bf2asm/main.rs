@@ -1,5 +1,4 @@
 use std::env;
-use std::error::Error;
 use std::fmt;
 use std::fs::File;
 use std::io;
@@ -23,7 +22,7 @@ impl fmt::Display for BFError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             BFError::Argument(ref s) => write!(f, "ArgumentError: {}", s),
-            BFError::IO(ref e) => write!(f, "IOError: {}", e.description()),
+            BFError::IO(ref e) => write!(f, "IOError: {}", e),
             BFError::Parser(ref s) => write!(f, "ParserError: {}", s),
         }
     }
@@ -45,7 +44,7 @@ enum BFToken {
 }
 
 fn parse(file_path: &str) -> Result<Vec<BFToken>> {
-    fn push(stack: &mut Vec<Vec<BFToken>>, token: BFToken) -> Result<()> {
+    fn push(stack: &mut [Vec<BFToken>], token: BFToken) -> Result<()> {
         let peeked_vec = stack
             .last_mut()
             .ok_or_else(|| BFError::Parser("Mooh".to_string()))?;
@@ -71,7 +70,7 @@ fn parse(file_path: &str) -> Result<Vec<BFToken>> {
     }
 
     let path = Path::new(file_path);
-    let file = BufReader::new(File::open(&path)?);
+    let file = BufReader::new(File::open(path)?);
 
     let mut stack: Vec<Vec<BFToken>> = Vec::new();
     stack.push(Vec::new());
@@ -106,63 +105,62 @@ fn parse(file_path: &str) -> Result<Vec<BFToken>> {
     Ok(stack.pop().unwrap())
 }
 
-fn generate_recursive(tokens: Vec<BFToken>, mut level: u32) -> (String, u32) {
+// Loops use anonymous labels numbered by their nesting depth, so the next
+// label with the same number always belongs to the matching loop end.
+fn generate_recursive(tokens: Vec<BFToken>, depth: u32) -> String {
     let mut buffer = String::new();
 
     for token in tokens {
         match token {
             BFToken::Input => unimplemented!(),
             BFToken::Output => {
-                buffer.push_str("    stdb r1, CONSOLEIO_START\n");
+                buffer.push_str("    stdb    r1, CONSOLEIO_START\n");
             }
 
             BFToken::Loop(loop_tokens) => {
-                let label = format!("label_{}", level);
-
-                buffer.push_str(&format!("{}_before:\n", label));
-                buffer.push_str("    cmpi r1, 0\n");
-                buffer.push_str(&format!("    breq {}_after\n", label));
-
-                level += 1;
-                let (code, new_level) = generate_recursive(loop_tokens, level);
-                level = new_level;
-                buffer.push_str(&code);
-                buffer.push_str(&format!("    jmp {}_before\n", label));
-                buffer.push_str(&format!("{}_after:\n", label));
+                let before = depth * 2;
+                let after = depth * 2 + 1;
+
+                buffer.push_str(&format!("{}:\n", before));
+                buffer.push_str("    cmpi    r1, 0\n");
+                buffer.push_str(&format!("    breq    {}f\n", after));
+
+                buffer.push_str(&generate_recursive(loop_tokens, depth + 1));
+                buffer.push_str(&format!("    jmp     {}b\n", before));
+                buffer.push_str(&format!("{}:\n", after));
             }
 
             BFToken::ModPointer(value) => {
-                buffer.push_str("    stb r1, r0\n");
+                buffer.push_str("    stb     r1, r0\n");
 
                 if value > 0 {
-                    buffer.push_str(&format!("    addi r0, r0, {}\n", value));
+                    buffer.push_str(&format!("    addi    r0, r0, {}\n", value));
                 } else {
-                    buffer.push_str(&format!("    subi r0, r0, {}\n", -value));
+                    buffer.push_str(&format!("    subi    r0, r0, {}\n", -value));
                 }
 
-                buffer.push_str("    ldb r1, r0\n");
+                buffer.push_str("    ldb     r1, r0\n");
 
             }
 
             BFToken::ModValue(value) => {
                 if value > 0 {
-                    buffer.push_str(&format!("    addi r1, r1, {}\n", value));
+                    buffer.push_str(&format!("    addi    r1, r1, {}\n", value));
                 } else {
-                    buffer.push_str(&format!("    subi r1, r1, {}\n", -value));
+                    buffer.push_str(&format!("    subi    r1, r1, {}\n", -value));
                 }
             }
 
         }
     }
 
-    (buffer, level)
+    buffer
 }
 
 fn generate(tokens: Vec<BFToken>) -> String {
     let mut buffer = String::new();
-    buffer.push_str("    ldi r0, PROGRAM_END\n");
-    let (result, _) = generate_recursive(tokens, 0);
-    buffer.push_str(&result);
+    buffer.push_str("    ldi     r0, PROGRAM_END\n");
+    buffer.push_str(&generate_recursive(tokens, 0));
     buffer.push_str("    halt\n");
     buffer
 
@@ -198,3 +196,24 @@ fn main() {
         Err(e) => println!("{}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mycpu::assembler::format::format_source;
+
+    #[test]
+    fn test_generate_is_formatted() {
+        let program = vec![
+            BFToken::ModValue(3),
+            BFToken::Loop(vec![
+                BFToken::ModPointer(1),
+                BFToken::ModValue(-2),
+                BFToken::Loop(vec![BFToken::Output, BFToken::ModPointer(-1)]),
+            ]),
+        ];
+
+        let code = generate(program);
+        assert_eq!(format_source(&code), code);
+    }
+}
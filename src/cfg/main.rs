@@ -0,0 +1,54 @@
+extern crate mycpu;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use mycpu::analysis::cfg::ControlFlowGraph;
+use mycpu::common::loader::load;
+
+const USAGE: &str = "Usage: cfg [--calls] [--json] [-o OUTPUT] FILE\n\
+    \x20 Prints the control-flow graph of a program as Graphviz DOT, or the call graph\n\
+    \x20 with --calls. FILE is an executable, a HEX or S-record file or an assembly source.";
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut calls = false;
+    let mut json = false;
+    let mut output = None;
+    let mut input = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--calls" => calls = true,
+            "--json" => json = true,
+            "-o" => output = Some(iter.next().ok_or(USAGE)?),
+            _ if arg.starts_with('-') || input.is_some() => return Err(USAGE.into()),
+            _ => input = Some(arg),
+        }
+    }
+
+    let graph = ControlFlowGraph::build(&load(input.ok_or(USAGE)?)?);
+    let text = match (calls, json) {
+        (false, false) => graph.to_dot(),
+        (true, false) => graph.call_graph_dot(),
+        (false, true) => format!("{}\n", graph.to_json()),
+        (true, true) => format!("{}\n", graph.call_graph_json()),
+    };
+
+    match output {
+        Some(path) => fs::write(path, text).map_err(|e| format!("IOError: {}: {}", path, e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
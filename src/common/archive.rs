@@ -0,0 +1,148 @@
+use std::io;
+
+use crate::common::binary::{invalid_data, Reader, Writer};
+use crate::common::object::{Binding, ObjectFile};
+
+// Static libraries: a list of named object files. The linker only uses the
+// members that define a symbol that is still undefined.
+
+const MAGIC: &[u8; 4] = b"MYA\0";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub object: ObjectFile,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Archive {
+    pub members: Vec<Member>,
+}
+
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// Members are extracted into the current directory, so their names must not
+// be empty, absolute or contain a path
+pub fn is_valid_member_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['/', '\\', ':']) && !name.contains("..")
+}
+
+impl Archive {
+    // Adds the member, replacing a member with the same name
+    pub fn insert(&mut self, member: Member) {
+        match self.members.iter_mut().find(|m| m.name == member.name) {
+            Some(existing) => *existing = member,
+            None => self.members.push(member),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Member> {
+        let index = self.members.iter().position(|m| m.name == name)?;
+        Some(self.members.remove(index))
+    }
+
+    // Index of the first member defining the global symbol
+    pub fn find_definition(&self, symbol: &str) -> Option<usize> {
+        self.members.iter().position(|member| {
+            member
+                .object
+                .symbols
+                .iter()
+                .any(|s| s.binding == Binding::Global && s.name == symbol)
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u32(VERSION);
+
+        writer.u32(self.members.len() as u32);
+        for member in &self.members {
+            writer.string(&member.name);
+            writer.bytes(&member.object.to_bytes());
+        }
+
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Archive> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(4)? != MAGIC {
+            return Err(invalid_data("Not an archive"));
+        }
+        if reader.u32()? != VERSION {
+            return Err(invalid_data("Unsupported archive version"));
+        }
+
+        let mut archive = Archive::default();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            if !is_valid_member_name(&name) {
+                return Err(invalid_data(&format!("Invalid member name '{}'", name)));
+            }
+            let object = ObjectFile::from_bytes(&reader.bytes()?)
+                .map_err(|e| invalid_data(&format!("{}: {}", name, e)))?;
+            archive.members.push(Member { name, object });
+        }
+
+        if !reader.is_empty() {
+            return Err(invalid_data("Trailing data after archive"));
+        }
+
+        Ok(archive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::object::Symbol;
+
+    fn member(name: &str, symbol: &str) -> Member {
+        Member {
+            name: name.into(),
+            object: ObjectFile {
+                symbols: vec![Symbol {
+                    name: symbol.into(),
+                    binding: Binding::Global,
+                    section: Some(".text".into()),
+                    value: 0,
+                }],
+                ..ObjectFile::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_archive() {
+        let mut archive = Archive::default();
+        archive.insert(member("string.o", "strlen"));
+        archive.insert(member("math.o", "multiply"));
+        archive.insert(member("string.o", "strcmp"));
+
+        assert_eq!(archive.members.len(), 2);
+        assert_eq!(archive.find_definition("strcmp"), Some(0));
+        assert_eq!(archive.find_definition("strlen"), None);
+        assert_eq!(archive.find_definition("multiply"), Some(1));
+
+        let bytes = archive.to_bytes();
+        assert!(is_archive(&bytes));
+        assert_eq!(Archive::from_bytes(&bytes).unwrap(), archive);
+        assert!(Archive::from_bytes(&bytes[..bytes.len() - 2]).is_err());
+
+        for name in [
+            "", "/tmp/a.o", "../a.o", "lib/a.o", "lib\\a.o", "C:a.o", "..",
+        ] {
+            let mut archive = Archive::default();
+            archive.insert(member(name, "strlen"));
+            assert!(Archive::from_bytes(&archive.to_bytes()).is_err());
+        }
+
+        assert!(archive.remove("math.o").is_some());
+        assert!(archive.remove("math.o").is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+
+// Helpers for the binary file formats. All integers are stored big-endian
+// like instruction operands, strings and byte arrays are prefixed with their
+// length.
+
+#[derive(Debug, Default)]
+pub struct Writer {
+    pub bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn string(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.position < length {
+            return Err(invalid_data("Unexpected end of file"));
+        }
+        let taken = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn i64(&mut self) -> io::Result<i64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(bytes))
+    }
+
+    pub fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid_data("Invalid string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut writer = Writer::default();
+        writer.u8(7);
+        writer.u32(0xAABBCCDD);
+        writer.i64(-2);
+        writer.string("text");
+
+        let mut reader = Reader::new(&writer.bytes);
+        assert_eq!(reader.u8().unwrap(), 7);
+        assert_eq!(reader.u32().unwrap(), 0xAABBCCDD);
+        assert_eq!(reader.i64().unwrap(), -2);
+        assert_eq!(reader.string().unwrap(), "text");
+        assert!(reader.is_empty());
+        assert!(reader.u8().is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use std::fmt;
+use std::io;
+
+use crate::common::binary::{invalid_data, Reader, Writer};
+use crate::common::image::{ImageSymbol, Segment};
+
+// Debug information stored in the debug section of an executable. The line
+// table maps address ranges to the source line they were assembled from,
+// symbol ranges map them to the enclosing label.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineEntry {
+    pub address: u32,
+    pub size: u32,
+    // Index into the file table
+    pub file: u32,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolRange {
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub lines: Vec<LineEntry>,
+    pub symbols: Vec<SymbolRange>,
+}
+
+// Source file and line of an address
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation<'a> {
+    pub file: &'a str,
+    pub line: u32,
+}
+
+impl<'a> fmt::Display for SourceLocation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+impl DebugInfo {
+    // Adds the line of a range of addresses. Ranges continuing the previous
+    // one on the same line are merged.
+    pub fn add_line(&mut self, address: u32, size: u32, file: &str, line: u32) {
+        let file = match self.files.iter().position(|f| f == file) {
+            Some(index) => index as u32,
+            None => {
+                self.files.push(file.into());
+                self.files.len() as u32 - 1
+            }
+        };
+
+        if let Some(last) = self.lines.last_mut() {
+            if last.file == file && last.line == line && last.address + last.size == address {
+                last.size += size;
+                return;
+            }
+        }
+
+        self.lines.push(LineEntry {
+            address,
+            size,
+            file,
+            line,
+        });
+    }
+
+    // Adds a range for every label, which ends at the next label of the same
+    // segment. Local and anonymous labels are part of the range of the
+    // preceding label.
+    pub fn add_symbol_ranges(&mut self, segments: &[Segment], symbols: &[ImageSymbol]) {
+        for segment in segments {
+            let mut labels: Vec<&ImageSymbol> = symbols
+                .iter()
+                .filter(|symbol| !symbol.name.contains(['.', '@']))
+                .filter(|symbol| (segment.address..segment.end()).contains(&symbol.address))
+                .collect();
+            labels.sort_by_key(|symbol| symbol.address);
+
+            for (index, label) in labels.iter().enumerate() {
+                let end = labels
+                    .get(index + 1)
+                    .map_or(segment.end(), |next| next.address);
+                if end > label.address {
+                    self.symbols.push(SymbolRange {
+                        name: label.name.clone(),
+                        start: label.address,
+                        end,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn location(&self, address: u32) -> Option<SourceLocation<'_>> {
+        let entry = self
+            .lines
+            .iter()
+            .find(|entry| address >= entry.address && address - entry.address < entry.size)?;
+
+        Some(SourceLocation {
+            file: self.files.get(entry.file as usize)?,
+            line: entry.line,
+        })
+    }
+
+    // Innermost symbol range containing the address
+    pub fn symbol(&self, address: u32) -> Option<&SymbolRange> {
+        self.symbols
+            .iter()
+            .filter(|range| (range.start..range.end).contains(&address))
+            .min_by_key(|range| range.end - range.start)
+    }
+
+    // Describes an address like "main+0x8 (main.asm:12)", or returns an
+    // empty string if nothing is known about it.
+    pub fn describe(&self, address: u32) -> String {
+        let symbol = self.symbol(address).map(|range| {
+            if address == range.start {
+                range.name.clone()
+            } else {
+                format!("{}+0x{:X}", range.name, address - range.start)
+            }
+        });
+
+        match (symbol, self.location(address)) {
+            (Some(symbol), Some(location)) => format!("{} ({})", symbol, location),
+            (Some(symbol), None) => symbol,
+            (None, Some(location)) => location.to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+
+        writer.u32(self.files.len() as u32);
+        for file in &self.files {
+            writer.string(file);
+        }
+
+        writer.u32(self.lines.len() as u32);
+        for entry in &self.lines {
+            writer.u32(entry.address);
+            writer.u32(entry.size);
+            writer.u32(entry.file);
+            writer.u32(entry.line);
+        }
+
+        writer.u32(self.symbols.len() as u32);
+        for range in &self.symbols {
+            writer.string(&range.name);
+            writer.u32(range.start);
+            writer.u32(range.end);
+        }
+
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<DebugInfo> {
+        let mut reader = Reader::new(bytes);
+        let mut info = DebugInfo::default();
+
+        for _ in 0..reader.u32()? {
+            info.files.push(reader.string()?);
+        }
+
+        for _ in 0..reader.u32()? {
+            let entry = LineEntry {
+                address: reader.u32()?,
+                size: reader.u32()?,
+                file: reader.u32()?,
+                line: reader.u32()?,
+            };
+            if entry.file as usize >= info.files.len() {
+                return Err(invalid_data("Invalid file in line table"));
+            }
+            if entry.address.checked_add(entry.size).is_none() {
+                return Err(invalid_data("Invalid address range in line table"));
+            }
+            info.lines.push(entry);
+        }
+
+        for _ in 0..reader.u32()? {
+            let range = SymbolRange {
+                name: reader.string()?,
+                start: reader.u32()?,
+                end: reader.u32()?,
+            };
+            if range.start > range.end {
+                return Err(invalid_data("Invalid symbol range"));
+            }
+            info.symbols.push(range);
+        }
+
+        if !reader.is_empty() {
+            return Err(invalid_data("Trailing data after debug information"));
+        }
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debug_info() -> DebugInfo {
+        let mut info = DebugInfo::default();
+        info.add_line(0x10_0000, 8, "main.asm", 1);
+        info.add_line(0x10_0008, 8, "main.asm", 2);
+        info.add_line(0x10_0010, 8, "main.asm", 2);
+        info.add_line(0x10_0018, 8, "lib.asm", 7);
+        info.symbols = vec![
+            SymbolRange {
+                name: "main".into(),
+                start: 0x10_0000,
+                end: 0x10_0018,
+            },
+            SymbolRange {
+                name: "print".into(),
+                start: 0x10_0018,
+                end: 0x10_0020,
+            },
+        ];
+        info
+    }
+
+    #[test]
+    fn test_lookup() {
+        let info = debug_info();
+        assert_eq!(info.lines.len(), 3);
+        assert_eq!(info.files, vec!["main.asm", "lib.asm"]);
+
+        assert_eq!(info.location(0x10_0014).unwrap().to_string(), "main.asm:2");
+        assert_eq!(info.symbol(0x10_0018).unwrap().name, "print");
+        assert_eq!(info.describe(0x10_0008), "main+0x8 (main.asm:2)");
+        assert_eq!(info.describe(0x10_0018), "print (lib.asm:7)");
+        assert_eq!(info.describe(0x10_0020), "");
+    }
+
+    #[test]
+    fn test_symbol_ranges() {
+        let segment = Segment {
+            name: ".text".into(),
+            address: 0x10_0000,
+            data: Vec::new(),
+            size: 0x20,
+            permissions: 0,
+        };
+        let symbol = |name: &str, address| ImageSymbol {
+            name: name.into(),
+            address,
+        };
+        let symbols = vec![
+            symbol("print", 0x10_0010),
+            symbol("print.loop", 0x10_0018),
+            symbol("alias", 0x10_0000),
+            symbol("main", 0x10_0000),
+            symbol("END", 0x10_0020),
+        ];
+
+        let mut info = DebugInfo::default();
+        info.add_symbol_ranges(&[segment], &symbols);
+
+        let ranges: Vec<(&str, u32, u32)> = info
+            .symbols
+            .iter()
+            .map(|range| (range.name.as_str(), range.start, range.end))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                ("main", 0x10_0000, 0x10_0010),
+                ("print", 0x10_0010, 0x10_0020)
+            ]
+        );
+        assert_eq!(info.describe(0x10_0018), "print+0x8");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let info = debug_info();
+        assert_eq!(DebugInfo::from_bytes(&info.to_bytes()).unwrap(), info);
+        assert!(DebugInfo::from_bytes(&info.to_bytes()[..10]).is_err());
+
+        let mut overflowing = debug_info();
+        overflowing.lines[0].address = u32::MAX - 4;
+        assert!(DebugInfo::from_bytes(&overflowing.to_bytes()).is_err());
+        assert!(overflowing.location(u32::MAX).is_some());
+
+        let mut inverted = debug_info();
+        inverted.symbols[0].end = 0;
+        assert!(DebugInfo::from_bytes(&inverted.to_bytes()).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::common::generated::instruction::Instruction;
 use crate::common::util;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DecodedInstruction {
     pub instruction_type: Instruction,
     pub reg_1: u8,
@@ -1,56 +1,92 @@
 
 // AUTOMATICALLY GENERATED, DO NOT EDIT!
-use std::mem::transmute;
 
 #[repr(u8)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Instruction {
-    NOp,
-    Halt,
-    Increment,
-    Decrement,
-    Add,
-    Subtract,
-    Multiply,
-    Divide,
-    Compare,
-    CompareImmediate,
-    AddImmediate,
-    SubtractImmediate,
-    Or,
-    And,
-    XOr,
-    Negate,
-    Complement,
-    LoadImmediate,
-    Load,
-    LoadByte,
-    LoadDirect,
-    LoadDirectByte,
-    Store,
-    StoreByte,
-    StoreDirect,
-    StoreDirectByte,
-    Push,
-    Pop,
-    Jump,
-    Call,
-    Return,
-    BranchEqual,
-    BranchNotEqual,
-    Move,
-    Invalid,
+    NOp = 0x0,
+    Halt = 0x1,
+    Increment = 0x10,
+    Decrement = 0x11,
+    Add = 0x12,
+    Subtract = 0x13,
+    Multiply = 0x14,
+    Divide = 0x15,
+    Compare = 0x16,
+    CompareImmediate = 0x17,
+    AddImmediate = 0x18,
+    SubtractImmediate = 0x19,
+    Or = 0x20,
+    And = 0x21,
+    XOr = 0x22,
+    Negate = 0x23,
+    Complement = 0x24,
+    LoadImmediate = 0x30,
+    Load = 0x31,
+    LoadByte = 0x32,
+    LoadDirect = 0x33,
+    LoadDirectByte = 0x34,
+    Store = 0x35,
+    StoreByte = 0x36,
+    StoreDirect = 0x37,
+    StoreDirectByte = 0x38,
+    Push = 0x39,
+    Pop = 0x3a,
+    Jump = 0x40,
+    Call = 0x41,
+    Return = 0x42,
+    BranchEqual = 0x50,
+    BranchNotEqual = 0x51,
+    Move = 0x60,
+    Invalid = 0xff,
 
 }
 
-impl Into<u8> for Instruction {
-    fn into(self: Self) -> u8 {
-        self as u8
+impl From<Instruction> for u8 {
+    fn from(instruction: Instruction) -> u8 {
+        instruction as u8
     }
 }
 
 impl From<u8> for Instruction {
     fn from(value: u8) -> Instruction {
-        unsafe {transmute(value)}
+        match value {
+            0x0 => Instruction::NOp,
+            0x1 => Instruction::Halt,
+            0x10 => Instruction::Increment,
+            0x11 => Instruction::Decrement,
+            0x12 => Instruction::Add,
+            0x13 => Instruction::Subtract,
+            0x14 => Instruction::Multiply,
+            0x15 => Instruction::Divide,
+            0x16 => Instruction::Compare,
+            0x17 => Instruction::CompareImmediate,
+            0x18 => Instruction::AddImmediate,
+            0x19 => Instruction::SubtractImmediate,
+            0x20 => Instruction::Or,
+            0x21 => Instruction::And,
+            0x22 => Instruction::XOr,
+            0x23 => Instruction::Negate,
+            0x24 => Instruction::Complement,
+            0x30 => Instruction::LoadImmediate,
+            0x31 => Instruction::Load,
+            0x32 => Instruction::LoadByte,
+            0x33 => Instruction::LoadDirect,
+            0x34 => Instruction::LoadDirectByte,
+            0x35 => Instruction::Store,
+            0x36 => Instruction::StoreByte,
+            0x37 => Instruction::StoreDirect,
+            0x38 => Instruction::StoreDirectByte,
+            0x39 => Instruction::Push,
+            0x3a => Instruction::Pop,
+            0x40 => Instruction::Jump,
+            0x41 => Instruction::Call,
+            0x42 => Instruction::Return,
+            0x50 => Instruction::BranchEqual,
+            0x51 => Instruction::BranchNotEqual,
+            0x60 => Instruction::Move,
+
+            _ => Instruction::Invalid,
+        }
     }
 }
@@ -0,0 +1,190 @@
+use std::io;
+
+use crate::common::binary::invalid_data;
+use crate::common::image::Image;
+
+// Intel HEX files with 32 bit addresses. Only initialized data is written,
+// so images with distant segments stay small. The entry point is stored in a
+// start address record; segment names, permissions, symbols, debug
+// information and stack bounds are lost.
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+const BYTES_PER_RECORD: u32 = 16;
+
+fn record(output: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    bytes.extend_from_slice(data);
+
+    let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(checksum.wrapping_neg());
+
+    output.push(':');
+    for byte in bytes {
+        output.push_str(&format!("{:02X}", byte));
+    }
+    output.push('\n');
+}
+
+pub fn write(image: &Image) -> String {
+    let mut output = String::new();
+    let mut upper = None;
+
+    for segment in &image.segments {
+        let mut offset = 0;
+        while offset < segment.data.len() as u32 {
+            let address = segment.address + offset;
+
+            if upper != Some(address >> 16) {
+                upper = Some(address >> 16);
+                record(
+                    &mut output,
+                    EXTENDED_LINEAR_ADDRESS,
+                    0,
+                    &((address >> 16) as u16).to_be_bytes(),
+                );
+            }
+
+            // Records must not cross a 64 KiB boundary
+            let length = BYTES_PER_RECORD
+                .min(segment.data.len() as u32 - offset)
+                .min(0x1_0000 - (address & 0xFFFF));
+            let data = &segment.data[offset as usize..(offset + length) as usize];
+            record(&mut output, DATA, address as u16, data);
+
+            offset += length;
+        }
+    }
+
+    record(
+        &mut output,
+        START_LINEAR_ADDRESS,
+        0,
+        &image.entry.to_be_bytes(),
+    );
+    record(&mut output, END_OF_FILE, 0, &[]);
+    output
+}
+
+// Parses the hex digits of a record and verifies its checksum
+pub(crate) fn parse_hex_record(line: &str) -> Result<Vec<u8>, String> {
+    if !line.len().is_multiple_of(2) {
+        return Err("Odd number of hex digits".into());
+    }
+
+    (0..line.len())
+        .step_by(2)
+        .map(|index| {
+            line.get(index..index + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| "Invalid hex digits".to_string())
+        })
+        .collect()
+}
+
+pub fn read(text: &str) -> io::Result<Image> {
+    let mut chunks = Vec::new();
+    let mut base = 0u32;
+    let mut entry = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| invalid_data(&format!("line {}: {}", index + 1, message));
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with(':') {
+            return Err(error("Expected ':'"));
+        }
+
+        let bytes = parse_hex_record(&line[1..]).map_err(|e| error(&e))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("Invalid record length"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("Checksum mismatch"));
+        }
+
+        let address = u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        let data = &bytes[4..bytes.len() - 1];
+        let value = data
+            .iter()
+            .fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+
+        match (bytes[3], data.len()) {
+            (DATA, _) => chunks.push((base.wrapping_add(address), data.to_vec())),
+            (END_OF_FILE, 0) => break,
+            (EXTENDED_SEGMENT_ADDRESS, 2) => base = value << 4,
+            (EXTENDED_LINEAR_ADDRESS, 2) => base = value << 16,
+            (START_SEGMENT_ADDRESS, 4) => entry = Some((value >> 16 << 4) + (value & 0xFFFF)),
+            (START_LINEAR_ADDRESS, 4) => entry = Some(value),
+            _ => return Err(error("Invalid record")),
+        }
+    }
+
+    let entry = entry
+        .or_else(|| chunks.iter().map(|(address, _)| *address).min())
+        .unwrap_or(0);
+    Image::from_chunks(chunks, entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::image::{Segment, EXECUTE, READ, WRITE};
+
+    fn sparse_image() -> Image {
+        let segment = |address: u32, data: Vec<u8>| Segment {
+            name: format!("segment{}", if address < 0x20_0000 { 0 } else { 1 }),
+            address,
+            size: data.len() as u32,
+            data,
+            permissions: READ | WRITE | EXECUTE,
+        };
+
+        Image {
+            entry: 0x10_FFF8,
+            segments: vec![
+                segment(0x10_FFF0, (0..40).collect()),
+                segment(0xFFFF_0000, vec![0xAA; 3]),
+            ],
+            ..Image::default()
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let hex = write(&sparse_image());
+        let lines: Vec<&str> = hex.lines().collect();
+
+        assert_eq!(lines[0], ":020000040010EA");
+        assert!(lines[1].starts_with(":10FFF000000102"));
+        assert_eq!(lines[2], ":020000040011E9");
+        assert_eq!(lines.last(), Some(&":00000001FF"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let image = sparse_image();
+        assert_eq!(read(&write(&image)).unwrap(), image);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(read(":00000001FE").is_err());
+        assert!(read("00000001FF").is_err());
+        assert!(read(":0100000001").is_err());
+        assert!(read(":02000000AABB\n").is_err());
+
+        // Entry defaults to the lowest address
+        let image = read(":0400100001020304E2\n:00000001FF").unwrap();
+        assert_eq!(image.entry, 0x10);
+        assert_eq!(image.segments[0].data, vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,398 @@
+use std::io;
+
+use crate::common::binary::{invalid_data, Reader, Writer};
+
+// Executables as written by the assembler and the linker. The file starts
+// with a magic number and the ISA version, followed by the entry point, the
+// load segments, the optional symbol, debug and stack sections and a CRC-32
+// of all preceding bytes.
+
+const MAGIC: &[u8; 4] = b"MYX\0";
+pub const ISA_VERSION: u32 = 1;
+
+// Segment permissions
+pub const READ: u8 = 1;
+pub const WRITE: u8 = 2;
+pub const EXECUTE: u8 = 4;
+
+// Flags for the optional sections
+const HAS_SYMBOLS: u8 = 1;
+const HAS_DEBUG: u8 = 2;
+const HAS_STACK: u8 = 4;
+
+// A contiguous range of memory produced by the assembler. The segment
+// occupies size bytes, everything after data is filled with zeros.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub name: String,
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub size: u32,
+    pub permissions: u8,
+}
+
+impl Segment {
+    pub fn end(&self) -> u32 {
+        self.address + self.size
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSymbol {
+    pub name: String,
+    pub address: u32,
+}
+
+// Range of memory the stack may occupy. SP starts at high and must stay
+// between low and high.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackBounds {
+    pub low: u32,
+    pub high: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Image {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: Vec<ImageSymbol>,
+    pub debug: Option<Vec<u8>>,
+    pub stack: Option<StackBounds>,
+}
+
+impl Image {
+    pub fn segment(&self, name: &str) -> Option<&Segment> {
+        self.segments.iter().find(|segment| segment.name == name)
+    }
+
+    // Builds an image from data at arbitrary addresses, like the records of
+    // a HEX file. Adjacent chunks are merged into one segment. The formats
+    // do not store permissions, so all segments are readable, writable and
+    // executable.
+    pub fn from_chunks(mut chunks: Vec<(u32, Vec<u8>)>, entry: u32) -> io::Result<Image> {
+        chunks.sort_by_key(|(address, _)| *address);
+
+        let mut segments: Vec<Segment> = Vec::new();
+        for (address, data) in chunks {
+            if address.checked_add(data.len() as u32).is_none() {
+                return Err(invalid_data(&format!(
+                    "Data at 0x{:X} exceeds the address space",
+                    address
+                )));
+            }
+
+            if let Some(last) = segments.last_mut() {
+                if address < last.end() {
+                    return Err(invalid_data(&format!("Data at 0x{:X} overlaps", address)));
+                }
+                if address == last.end() {
+                    last.data.extend_from_slice(&data);
+                    last.size = last.data.len() as u32;
+                    continue;
+                }
+            }
+
+            segments.push(Segment {
+                name: format!("segment{}", segments.len()),
+                address,
+                size: data.len() as u32,
+                data,
+                permissions: READ | WRITE | EXECUTE,
+            });
+        }
+
+        let image = Image {
+            entry,
+            segments,
+            ..Image::default()
+        };
+        image.validate()?;
+        Ok(image)
+    }
+
+    // Checks that segments do not overlap, that the entry point is in an
+    // executable segment and that the stack bounds are ordered.
+    pub fn validate(&self) -> io::Result<()> {
+        for (index, segment) in self.segments.iter().enumerate() {
+            if segment.data.len() > segment.size as usize
+                || segment.address.checked_add(segment.size).is_none()
+            {
+                return Err(invalid_data(&format!("Invalid segment {}", segment.name)));
+            }
+
+            if let Some(other) = self.segments[..index]
+                .iter()
+                .find(|o| o.address < segment.end() && segment.address < o.end())
+            {
+                return Err(invalid_data(&format!(
+                    "Segments {} and {} overlap",
+                    other.name, segment.name
+                )));
+            }
+        }
+
+        let executable = self.segments.iter().any(|segment| {
+            segment.permissions & EXECUTE != 0
+                && (segment.address..segment.end()).contains(&self.entry)
+        });
+        if !executable {
+            return Err(invalid_data(&format!(
+                "Entry point 0x{:X} is not in an executable segment",
+                self.entry
+            )));
+        }
+
+        if let Some(stack) = &self.stack {
+            if stack.low > stack.high {
+                return Err(invalid_data(&format!(
+                    "Invalid stack bounds 0x{:X}-0x{:X}",
+                    stack.low, stack.high
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Flat memory contents from the lowest segment to the end of the last
+    // initialized data, with gaps filled with zeros. The entry point, symbols,
+    // stack bounds and permissions are not part of the output, and trailing
+    // uninitialized data is left out.
+    pub fn to_raw(&self) -> Vec<u8> {
+        let start = match self.segments.iter().map(|segment| segment.address).min() {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+
+        let mut bytes = Vec::new();
+        for segment in &self.segments {
+            let offset = (segment.address - start) as usize;
+            let end = offset + segment.data.len();
+            if bytes.len() < end {
+                bytes.resize(end, 0);
+            }
+            bytes[offset..end].copy_from_slice(&segment.data);
+        }
+        bytes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u32(ISA_VERSION);
+        writer.u32(self.entry);
+
+        writer.u32(self.segments.len() as u32);
+        for segment in &self.segments {
+            writer.string(&segment.name);
+            writer.u32(segment.address);
+            writer.u32(segment.size);
+            writer.u8(segment.permissions);
+            writer.bytes(&segment.data);
+        }
+
+        let mut flags = 0;
+        if !self.symbols.is_empty() {
+            flags |= HAS_SYMBOLS;
+        }
+        if self.debug.is_some() {
+            flags |= HAS_DEBUG;
+        }
+        if self.stack.is_some() {
+            flags |= HAS_STACK;
+        }
+        writer.u8(flags);
+
+        if !self.symbols.is_empty() {
+            writer.u32(self.symbols.len() as u32);
+            for symbol in &self.symbols {
+                writer.string(&symbol.name);
+                writer.u32(symbol.address);
+            }
+        }
+        if let Some(debug) = &self.debug {
+            writer.bytes(debug);
+        }
+        if let Some(stack) = &self.stack {
+            writer.u32(stack.low);
+            writer.u32(stack.high);
+        }
+
+        let checksum = crc32(&writer.bytes);
+        writer.u32(checksum);
+        writer.bytes
+    }
+
+    // Reads and validates an executable
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Image> {
+        if bytes.len() < 4 || !bytes.starts_with(MAGIC) {
+            return Err(invalid_data("Not an executable"));
+        }
+
+        let (contents, checksum) = bytes.split_at(bytes.len() - 4);
+        if Reader::new(checksum).u32()? != crc32(contents) {
+            return Err(invalid_data("Checksum mismatch"));
+        }
+
+        let mut reader = Reader::new(contents);
+        reader.take(4)?;
+        let version = reader.u32()?;
+        if version != ISA_VERSION {
+            return Err(invalid_data(&format!(
+                "Unsupported ISA version {}, expected {}",
+                version, ISA_VERSION
+            )));
+        }
+
+        let mut image = Image {
+            entry: reader.u32()?,
+            ..Image::default()
+        };
+
+        for _ in 0..reader.u32()? {
+            image.segments.push(Segment {
+                name: reader.string()?,
+                address: reader.u32()?,
+                size: reader.u32()?,
+                permissions: reader.u8()?,
+                data: reader.bytes()?,
+            });
+        }
+
+        let flags = reader.u8()?;
+        if flags & HAS_SYMBOLS != 0 {
+            for _ in 0..reader.u32()? {
+                image.symbols.push(ImageSymbol {
+                    name: reader.string()?,
+                    address: reader.u32()?,
+                });
+            }
+        }
+        if flags & HAS_DEBUG != 0 {
+            image.debug = Some(reader.bytes()?);
+        }
+        if flags & HAS_STACK != 0 {
+            image.stack = Some(StackBounds {
+                low: reader.u32()?,
+                high: reader.u32()?,
+            });
+        }
+
+        if !reader.is_empty() {
+            return Err(invalid_data("Trailing data after executable"));
+        }
+
+        image.validate()?;
+        Ok(image)
+    }
+}
+
+// CRC-32 as used by zlib and PNG
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Image {
+        Image {
+            entry: 0x10_0008,
+            segments: vec![
+                Segment {
+                    name: ".text".into(),
+                    address: 0x10_0000,
+                    data: vec![0; 16],
+                    size: 16,
+                    permissions: READ | EXECUTE,
+                },
+                Segment {
+                    name: ".bss".into(),
+                    address: 0x10_0010,
+                    data: Vec::new(),
+                    size: 64,
+                    permissions: READ | WRITE,
+                },
+            ],
+            symbols: vec![ImageSymbol {
+                name: "main".into(),
+                address: 0x10_0008,
+            }],
+            debug: None,
+            stack: None,
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let image = image();
+        assert_eq!(Image::from_bytes(&image.to_bytes()).unwrap(), image);
+
+        let stripped = Image {
+            symbols: Vec::new(),
+            debug: Some(vec![1, 2, 3]),
+            stack: Some(StackBounds {
+                low: 0x10_0050,
+                high: 0x10_0050,
+            }),
+            ..image
+        };
+        assert_eq!(Image::from_bytes(&stripped.to_bytes()).unwrap(), stripped);
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut image = image();
+        image.segments[0].data = vec![1; 16];
+        assert_eq!(image.to_raw(), vec![1; 16]);
+
+        image.segments[1].address = 0x10_0020;
+        image.segments[1].data = vec![2; 4];
+        let mut expected = vec![1; 16];
+        expected.extend_from_slice(&[0; 16]);
+        expected.extend_from_slice(&[2; 4]);
+        assert_eq!(image.to_raw(), expected);
+
+        assert!(Image::default().to_raw().is_empty());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut bytes = image().to_bytes();
+        bytes[12] ^= 1;
+        assert!(Image::from_bytes(&bytes).is_err());
+        assert!(Image::from_bytes(b"MYO\0").is_err());
+
+        let outside = Image {
+            entry: 0x10_0010,
+            ..image()
+        };
+        assert!(Image::from_bytes(&outside.to_bytes()).is_err());
+
+        let mut overlapping = image();
+        overlapping.segments[1].address = 0x10_0008;
+        assert!(overlapping.validate().is_err());
+
+        let mut inverted = image();
+        inverted.stack = Some(StackBounds {
+            low: 0x10_0050,
+            high: 0x10_0010,
+        });
+        assert!(inverted.validate().is_err());
+    }
+}
@@ -0,0 +1,22 @@
+use std::fs;
+
+use crate::assembler::codegen::assemble_file;
+use crate::common::image::Image;
+use crate::common::{ihex, srec};
+
+// Loads a program for the emulator and the analysis tools. Assembly sources
+// are assembled on the fly. Other files are loaded as Intel HEX or S-record
+// if they start like one, or as executable.
+pub fn load(path: &str) -> Result<Image, String> {
+    if path.ends_with(".asm") {
+        return assemble_file(path).map_err(|e| e.to_string());
+    }
+
+    let bytes = fs::read(path).map_err(|e| format!("IOError: {}: {}", path, e))?;
+    let image = match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(b':') => ihex::read(&String::from_utf8_lossy(&bytes)),
+        Some(b'S') => srec::read(&String::from_utf8_lossy(&bytes)),
+        _ => Image::from_bytes(&bytes),
+    };
+    image.map_err(|e| format!("{}: {}", path, e))
+}
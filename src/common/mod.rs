@@ -1,3 +1,12 @@
+pub mod archive;
+pub mod binary;
+pub mod debug;
 pub mod encoding;
 pub mod generated;
+pub mod ihex;
+pub mod image;
+pub mod json;
+pub mod loader;
+pub mod object;
+pub mod srec;
 pub mod util;
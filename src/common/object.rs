@@ -0,0 +1,206 @@
+use std::io;
+
+use crate::common::binary::{invalid_data, Reader, Writer};
+
+// Relocatable object files written by `asm -c` and combined by the linker.
+// Section contents start at offset 0, addresses are only assigned when
+// linking.
+
+const MAGIC: &[u8; 4] = b"MYO\0";
+const VERSION: u32 = 1;
+
+// Largest alignment of a section, the size of a page
+pub const MAX_ALIGNMENT: u32 = 4096;
+
+pub fn is_valid_alignment(alignment: u32) -> bool {
+    alignment.is_power_of_two() && alignment <= MAX_ALIGNMENT
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectSection {
+    pub name: String,
+    pub alignment: u32,
+    // Uninitialized sections have no data but a size
+    pub size: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    Local,
+    Global,
+    // Declared with .extern, defined by another object
+    Undefined,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub binding: Binding,
+    // Section the value is relative to, absolute values have none
+    pub section: Option<String>,
+    pub value: u32,
+}
+
+// Tells the linker to store the address of symbol plus addend as 32 bit
+// big-endian value at the offset of the section. The symbol is either the
+// name of a section of the same object or a symbol defined elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub section: String,
+    pub offset: u32,
+    pub symbol: String,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectFile {
+    pub sections: Vec<ObjectSection>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectFile {
+    pub fn section(&self, name: &str) -> Option<&ObjectSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u32(VERSION);
+
+        writer.u32(self.sections.len() as u32);
+        for section in &self.sections {
+            writer.string(&section.name);
+            writer.u32(section.alignment);
+            writer.u32(section.size);
+            writer.bytes(&section.data);
+        }
+
+        writer.u32(self.symbols.len() as u32);
+        for symbol in &self.symbols {
+            writer.string(&symbol.name);
+            writer.u8(match symbol.binding {
+                Binding::Local => 0,
+                Binding::Global => 1,
+                Binding::Undefined => 2,
+            });
+            writer.string(symbol.section.as_deref().unwrap_or(""));
+            writer.u32(symbol.value);
+        }
+
+        writer.u32(self.relocations.len() as u32);
+        for relocation in &self.relocations {
+            writer.string(&relocation.section);
+            writer.u32(relocation.offset);
+            writer.string(&relocation.symbol);
+            writer.i64(relocation.addend);
+        }
+
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<ObjectFile> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(4)? != MAGIC {
+            return Err(invalid_data("Not an object file"));
+        }
+        if reader.u32()? != VERSION {
+            return Err(invalid_data("Unsupported object file version"));
+        }
+
+        let mut object = ObjectFile::default();
+
+        for _ in 0..reader.u32()? {
+            let section = ObjectSection {
+                name: reader.string()?,
+                alignment: reader.u32()?,
+                size: reader.u32()?,
+                data: reader.bytes()?,
+            };
+            if !is_valid_alignment(section.alignment) {
+                return Err(invalid_data(&format!(
+                    "Invalid alignment {} of section {}",
+                    section.alignment, section.name
+                )));
+            }
+            object.sections.push(section);
+        }
+
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let binding = match reader.u8()? {
+                0 => Binding::Local,
+                1 => Binding::Global,
+                2 => Binding::Undefined,
+                _ => return Err(invalid_data("Invalid symbol binding")),
+            };
+            let section = Some(reader.string()?).filter(|section| !section.is_empty());
+            let value = reader.u32()?;
+            object.symbols.push(Symbol {
+                name,
+                binding,
+                section,
+                value,
+            });
+        }
+
+        for _ in 0..reader.u32()? {
+            object.relocations.push(Relocation {
+                section: reader.string()?,
+                offset: reader.u32()?,
+                symbol: reader.string()?,
+                addend: reader.i64()?,
+            });
+        }
+
+        if !reader.is_empty() {
+            return Err(invalid_data("Trailing data after object file"));
+        }
+
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let object = ObjectFile {
+            sections: vec![ObjectSection {
+                name: ".text".into(),
+                alignment: 8,
+                size: 8,
+                data: vec![0; 8],
+            }],
+            symbols: vec![
+                Symbol {
+                    name: "main".into(),
+                    binding: Binding::Global,
+                    section: Some(".text".into()),
+                    value: 0,
+                },
+                Symbol {
+                    name: "print".into(),
+                    binding: Binding::Undefined,
+                    section: None,
+                    value: 0,
+                },
+            ],
+            relocations: vec![Relocation {
+                section: ".text".into(),
+                offset: 4,
+                symbol: "print".into(),
+                addend: -4,
+            }],
+        };
+
+        let bytes = object.to_bytes();
+        assert_eq!(ObjectFile::from_bytes(&bytes).unwrap(), object);
+        assert!(ObjectFile::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(ObjectFile::from_bytes(b"MYX\0").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::io;
+
+use crate::common::binary::invalid_data;
+use crate::common::ihex::parse_hex_record;
+use crate::common::image::Image;
+
+// Motorola S-record files. Data is written as S3 records with 32 bit
+// addresses; S1 and S2 records are accepted as well. Like Intel HEX files
+// only the data and the entry point are stored.
+
+const BYTES_PER_RECORD: usize = 16;
+
+fn record(output: &mut String, kind: u8, address: &[u8], data: &[u8]) {
+    let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+    bytes.extend_from_slice(address);
+    bytes.extend_from_slice(data);
+
+    let checksum = !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(checksum);
+
+    output.push('S');
+    output.push(char::from(b'0' + kind));
+    for byte in bytes {
+        output.push_str(&format!("{:02X}", byte));
+    }
+    output.push('\n');
+}
+
+pub fn write(image: &Image) -> String {
+    let mut output = String::new();
+    record(&mut output, 0, &[0, 0], b"mycpu");
+
+    let mut count = 0u32;
+    for segment in &image.segments {
+        for (index, data) in segment.data.chunks(BYTES_PER_RECORD).enumerate() {
+            let address = segment.address + (index * BYTES_PER_RECORD) as u32;
+            record(&mut output, 3, &address.to_be_bytes(), data);
+            count += 1;
+        }
+    }
+
+    // The record count is optional and omitted if it does not fit
+    if count <= 0xFFFF {
+        record(&mut output, 5, &(count as u16).to_be_bytes(), &[]);
+    } else if count <= 0xFF_FFFF {
+        record(&mut output, 6, &count.to_be_bytes()[1..], &[]);
+    }
+
+    record(&mut output, 7, &image.entry.to_be_bytes(), &[]);
+    output
+}
+
+pub fn read(text: &str) -> io::Result<Image> {
+    let mut chunks = Vec::new();
+    let mut entry = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| invalid_data(&format!("line {}: {}", index + 1, message));
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.len() < 2 || !line.starts_with('S') {
+            return Err(error("Expected 'S'"));
+        }
+
+        let kind = line.as_bytes()[1];
+        let bytes = parse_hex_record(&line[2..]).map_err(|e| error(&e))?;
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error("Invalid record length"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0xFF {
+            return Err(error("Checksum mismatch"));
+        }
+
+        let address_length = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(error("Invalid record type")),
+        };
+        if bytes.len() < address_length + 2 {
+            return Err(error("Invalid record length"));
+        }
+
+        let address = bytes[1..=address_length]
+            .iter()
+            .fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+        let data = &bytes[address_length + 1..bytes.len() - 1];
+
+        match kind {
+            b'1' | b'2' | b'3' => chunks.push((address, data.to_vec())),
+            b'7' | b'8' | b'9' => entry = Some(address),
+            _ => {}
+        }
+    }
+
+    let entry = entry
+        .or_else(|| chunks.iter().map(|(address, _)| *address).min())
+        .unwrap_or(0);
+    Image::from_chunks(chunks, entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::image::{Segment, EXECUTE, READ, WRITE};
+
+    #[test]
+    fn test_roundtrip() {
+        let image = Image {
+            entry: 0x10_0000,
+            segments: vec![
+                Segment {
+                    name: "segment0".into(),
+                    address: 0x10_0000,
+                    data: (0..20).collect(),
+                    size: 20,
+                    permissions: READ | WRITE | EXECUTE,
+                },
+                Segment {
+                    name: "segment1".into(),
+                    address: 0x18_0000,
+                    data: vec![0xFF; 4],
+                    size: 4,
+                    permissions: READ | WRITE | EXECUTE,
+                },
+            ],
+            ..Image::default()
+        };
+
+        let srec = write(&image);
+        let lines: Vec<&str> = srec.lines().collect();
+        assert_eq!(lines[0], "S00800006D79637075C9");
+        assert_eq!(lines[4], "S5030003F9");
+        assert_eq!(lines[5], "S70500100000EA");
+
+        assert_eq!(read(&srec).unwrap(), image);
+    }
+
+    #[test]
+    fn test_s1_records() {
+        let image = read("S107001001020304DE\nS9030010EC\n").unwrap();
+        assert_eq!(image.entry, 0x10);
+        assert_eq!(image.segments[0].address, 0x10);
+        assert_eq!(image.segments[0].data, vec![1, 2, 3, 4]);
+
+        assert!(read("S107001001020304DF").is_err());
+        assert!(read("S4030000FC").is_err());
+        assert!(read("X107001001020304DE").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use crate::common::encoding::DecodedInstruction;
+use crate::common::generated::instruction::Instruction::*;
+use crate::emulator::constants::*;
+use crate::emulator::cpu::{Register, StepResult, CPU};
+use crate::emulator::icache::PAGE_SIZE;
+
+const MAX_BLOCK_LENGTH: usize = 64;
+
+// A straight-line sequence of pre-decoded instructions. Only the last
+// instruction of a block may change the program counter.
+struct Block {
+    instructions: Vec<DecodedInstruction>,
+    // Generations of the code pages the block was decoded from
+    pages: Vec<(u32, u64)>,
+}
+
+// Alternative to CPU::run that decodes basic blocks once and keeps them in a
+// block cache indexed by their start address in main memory. This is not a
+// compiler: every instruction of a block still goes through
+// CPU::execute_instruction, the block only saves the fetch, the instruction
+// cache lookup and the decode. A write to memory that code was decoded from
+// drops the blocks that overlap the written page; the block that did the
+// write stops after the write.
+pub struct BlockEngine {
+    blocks: Vec<Option<Block>>,
+    translated: Vec<usize>,
+    code_generation: u64,
+}
+
+impl Default for BlockEngine {
+    fn default() -> Self {
+        BlockEngine::new()
+    }
+}
+
+impl BlockEngine {
+    pub fn new() -> Self {
+        BlockEngine {
+            blocks: (0..MEMORY_SIZE / 8).map(|_| None).collect(),
+            translated: Vec::new(),
+            code_generation: 0,
+        }
+    }
+
+    pub fn run(&mut self, cpu: &mut CPU) -> StepResult {
+        self.run_for(cpu, u64::MAX)
+    }
+
+    // Executes at most the given number of instructions, with the same
+    // semantics as CPU::run_for.
+    pub fn run_for(&mut self, cpu: &mut CPU, mut cycles: u64) -> StepResult {
+        if cpu.is_halted() {
+            return StepResult::Halted;
+        }
+
+        while cycles > 0 {
+            self.sync(cpu);
+
+            let start = cpu.get_register(Register::PC);
+            let index = (start.wrapping_sub(MEMORY_START) / 8) as usize;
+            if !start.is_multiple_of(8) || index >= self.blocks.len() {
+                // Not in main memory, let the interpreter handle it
+                match cpu.step() {
+                    StepResult::Ok => {
+                        cycles -= 1;
+                        continue;
+                    }
+                    result => return result,
+                }
+            }
+
+            if self.blocks[index].is_none() {
+                match translate(cpu, start) {
+                    Some(block) => {
+                        self.blocks[index] = Some(block);
+                        self.translated.push(index);
+                    }
+                    // The first instruction can not be fetched, let the
+                    // interpreter report the fault
+                    None => return cpu.step(),
+                }
+            }
+
+            let block = self.blocks[index].as_ref().unwrap();
+            let mut pc = start;
+
+            for instruction in &block.instructions {
+                if cycles == 0 {
+                    return StepResult::Ok;
+                }
+                cycles -= 1;
+
+                cpu.set_register(Register::PC, pc.wrapping_add(8));
+                if let Err(fault) = cpu.execute_instruction(instruction) {
+                    cpu.set_register(Register::PC, pc);
+                    return StepResult::Fault(fault);
+                }
+                cpu.cycle_counter += 1;
+
+                if cpu.is_halted() {
+                    return StepResult::Halted;
+                }
+
+                // The instruction overwrote code, the rest of the block may
+                // be stale
+                if cpu.memory.code_generation() != self.code_generation {
+                    break;
+                }
+
+                pc = pc.wrapping_add(8);
+            }
+        }
+
+        StepResult::Ok
+    }
+
+    pub fn flush(&mut self) {
+        for index in self.translated.drain(..) {
+            self.blocks[index] = None;
+        }
+    }
+
+    // Drops the blocks decoded from pages that were written since
+    fn sync(&mut self, cpu: &CPU) {
+        let generation = cpu.memory.code_generation();
+        if generation == self.code_generation {
+            return;
+        }
+        self.code_generation = generation;
+
+        let blocks = &mut self.blocks;
+        self.translated.retain(|&index| {
+            let stale = blocks[index].as_ref().is_some_and(|block| {
+                block
+                    .pages
+                    .iter()
+                    .any(|&(page, generation)| cpu.memory.code_page_generation(page) != generation)
+            });
+            if stale {
+                blocks[index] = None;
+            }
+            !stale
+        });
+    }
+}
+
+fn ends_block(instruction: &DecodedInstruction) -> bool {
+    match instruction.instruction_type {
+        Jump | Call | Return | BranchEqual | BranchNotEqual | Halt | Invalid => true,
+        // Any other instruction writing to PC, e.g. mov pc, r0
+        _ => instruction.reg_1 == Register::PC as u8,
+    }
+}
+
+fn translate(cpu: &mut CPU, start: u32) -> Option<Block> {
+    let mut instructions = Vec::new();
+    let mut addr = start;
+
+    while instructions.len() < MAX_BLOCK_LENGTH {
+        let instruction = match cpu.memory.fetch_instruction(addr) {
+            Ok(instruction) => instruction,
+            Err(_) => break,
+        };
+
+        instructions.push(instruction);
+        if ends_block(&instruction) {
+            break;
+        }
+        addr = addr.wrapping_add(8);
+    }
+
+    if instructions.is_empty() {
+        return None;
+    }
+
+    let end = start.wrapping_add(8 * instructions.len() as u32 - 1);
+    let pages = (start / PAGE_SIZE..=end / PAGE_SIZE)
+        .map(|page| {
+            let page = page * PAGE_SIZE;
+            (page, cpu.memory.code_page_generation(page))
+        })
+        .collect();
+    Some(Block {
+        instructions,
+        pages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::codegen::assemble_file;
+    use crate::common::generated::instruction::Instruction;
+    use crate::emulator::fault::Fault;
+    use crate::emulator::memory::{AddressSpace, Memory};
+
+    fn create_cpu(bytes: &[u8]) -> CPU {
+        let mut memory = AddressSpace::default();
+        memory.write_all(bytes, MEMORY_START).unwrap();
+        CPU::new(memory)
+    }
+
+    fn encode(program: &[(Instruction, u8, u8, u8, u32)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &(instruction, reg_1, reg_2, reg_3, operand) in program {
+            bytes.extend_from_slice(
+                &DecodedInstruction::new(instruction, reg_1, reg_2, reg_3, operand).encode(),
+            );
+        }
+        bytes
+    }
+
+    // Runs the program on both the interpreter and the block engine and
+    // checks that they end up in the same state.
+    fn compare_engines(bytes: &[u8], cycles: u64) -> CPU {
+        let mut interpreted = create_cpu(bytes);
+        let mut translated = create_cpu(bytes);
+
+        let expected = interpreted.run_for(cycles);
+        let result = BlockEngine::new().run_for(&mut translated, cycles);
+
+        assert_eq!(result, expected);
+        assert_eq!(translated.regs, interpreted.regs);
+        assert_eq!(translated.cycle_counter, interpreted.cycle_counter);
+        assert_eq!(
+            translated.memory.read_all(MEMORY_START, 0x1000),
+            interpreted.memory.read_all(MEMORY_START, 0x1000)
+        );
+        assert_eq!(
+            translated.memory.read_all(MEMORY_END - 0xFFF, 0x1000),
+            interpreted.memory.read_all(MEMORY_END - 0xFFF, 0x1000)
+        );
+
+        translated
+    }
+
+    #[test]
+    fn test_testdata_program() {
+        let image = assemble_file("testdata/test.asm").unwrap();
+        let cpu = compare_engines(&image.segments[0].data, 1000);
+        assert!(cpu.is_halted());
+    }
+
+    #[test]
+    fn test_loop_with_calls() {
+        let bytes = encode(&[
+            (LoadImmediate, Register::SP as u8, 0, 0, MEMORY_END - 3),
+            (LoadImmediate, 0, 0, 0, 10),
+            // loop
+            (CompareImmediate, 0, 0, 0, 0),
+            (BranchEqual, 0, 0, 0, MEMORY_START + 0x38),
+            (Call, 0, 0, 0, MEMORY_START + 0x40),
+            (Decrement, 0, 0, 0, 0),
+            (Jump, 0, 0, 0, MEMORY_START + 0x10),
+            (Halt, 0, 0, 0, 0),
+            // function
+            (Push, 0, 0, 0, 0),
+            (Add, 1, 1, 0, 0),
+            (StoreDirect, 1, 0, 0, MEMORY_START + 0x800),
+            (Pop, 0, 0, 0, 0),
+            (Return, 0, 0, 0, 0),
+        ]);
+
+        let cpu = compare_engines(&bytes, 10_000);
+        assert_eq!(cpu.get_register(Register::R1), 55);
+    }
+
+    #[test]
+    fn test_run_for_stops_inside_block() {
+        let bytes = encode(&[
+            (Increment, 0, 0, 0, 0),
+            (Increment, 0, 0, 0, 0),
+            (Increment, 0, 0, 0, 0),
+            (Jump, 0, 0, 0, MEMORY_START),
+        ]);
+
+        for cycles in 0..10 {
+            compare_engines(&bytes, cycles);
+        }
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // Overwrites the instruction following the store with a halt
+        let bytes = encode(&[
+            (Increment, 0, 0, 0, 0),
+            (LoadImmediate, 1, 0, 0, 0x0100_0000),
+            (StoreDirect, 1, 0, 0, MEMORY_START + 0x18),
+            (Increment, 0, 0, 0, 0),
+            (Jump, 0, 0, 0, MEMORY_START),
+        ]);
+
+        let cpu = compare_engines(&bytes, 100);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.get_register(Register::R0), 1);
+    }
+
+    #[test]
+    fn test_write_drops_blocks_of_page() {
+        let mut bytes = encode(&[
+            (LoadImmediate, Register::SP as u8, 0, 0, MEMORY_END - 3),
+            (Call, 0, 0, 0, MEMORY_START + 0x1000),
+            (Halt, 0, 0, 0, 0),
+        ]);
+        bytes.resize(0x1000, 0);
+        // Writes to its own page
+        bytes.extend(encode(&[
+            (StoreDirect, 0, 0, 0, MEMORY_START + 0x1800),
+            (Return, 0, 0, 0, 0),
+        ]));
+
+        let mut cpu = create_cpu(&bytes);
+        let mut engine = BlockEngine::new();
+        assert_eq!(engine.run(&mut cpu), StepResult::Halted);
+
+        assert!(engine.blocks[0].is_some());
+        assert!(engine.blocks[2].is_some());
+        assert!(engine.blocks[0x1000 / 8].is_none());
+        assert!(engine.blocks[0x1008 / 8].is_some());
+    }
+
+    #[test]
+    fn test_write_to_pc_ends_block() {
+        let bytes = encode(&[
+            (LoadImmediate, 0, 0, 0, MEMORY_START + 0x18),
+            (Move, Register::PC as u8, 0, 0, 0),
+            (Increment, 1, 0, 0, 0),
+            (Halt, 0, 0, 0, 0),
+        ]);
+
+        let cpu = compare_engines(&bytes, 100);
+        assert_eq!(cpu.get_register(Register::R1), 0);
+    }
+
+    #[test]
+    fn test_fault() {
+        let bytes = encode(&[
+            (Increment, 0, 0, 0, 0),
+            (Divide, 0, 0, 1, 0),
+            (Halt, 0, 0, 0, 0),
+        ]);
+
+        let cpu = compare_engines(&bytes, 100);
+        assert_eq!(cpu.get_register(Register::PC), MEMORY_START + 8);
+        assert_eq!(cpu.cycle_counter, 1);
+
+        let mut cpu = create_cpu(&bytes);
+        assert_eq!(
+            BlockEngine::new().run(&mut cpu),
+            StepResult::Fault(Fault::DivisionByZero)
+        );
+    }
+}
@@ -5,3 +5,24 @@ pub const MEMORY_END: u32 = MEMORY_START + MEMORY_SIZE - 1;
 pub const CONSOLEIO_SIZE: u32 = 8;
 pub const CONSOLEIO_START: u32 = 0x8_0000;
 pub const CONSOLEIO_END: u32 = CONSOLEIO_START + CONSOLEIO_SIZE - 1;
+
+pub struct MemoryRegion {
+    pub name: &'static str,
+    pub start: u32,
+    pub size: u32,
+}
+
+// All memory regions of the machine. The assembler defines NAME_START and
+// NAME_END symbols for each of them.
+pub const MEMORY_MAP: [MemoryRegion; 2] = [
+    MemoryRegion {
+        name: "MEMORY",
+        start: MEMORY_START,
+        size: MEMORY_SIZE,
+    },
+    MemoryRegion {
+        name: "CONSOLEIO",
+        start: CONSOLEIO_START,
+        size: CONSOLEIO_SIZE,
+    },
+];
@@ -1,12 +1,15 @@
 use std::num::Wrapping;
 
 use crate::common::encoding::DecodedInstruction;
-use crate::common::generated::instruction::Instruction::*;
+use crate::common::generated::instruction::Instruction::{self, *};
+use crate::common::image::StackBounds;
 use crate::emulator::constants::*;
+use crate::emulator::fault::Fault;
+use crate::emulator::history::UndoRecord;
 use crate::emulator::memory::AddressSpace;
 use crate::emulator::memory::Memory;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Register {
     R0 = 0,
     R1,
@@ -29,6 +32,32 @@ pub enum Register {
     SR,
 }
 
+// Registers read and written by an instruction with the given register
+// operands
+pub fn registers(instruction: Instruction, regs: [u8; 3]) -> (Vec<u8>, Vec<u8>) {
+    let [r1, r2, r3] = regs;
+    let sp = Register::SP as u8;
+    let sr = Register::SR as u8;
+    let pc = Register::PC as u8;
+
+    match instruction {
+        Increment | Decrement | Negate | Complement => (vec![r1], vec![r1]),
+        Add | Subtract | Multiply | Divide | And | Or | XOr => (vec![r2, r3], vec![r1]),
+        Compare => (vec![r1, r2], vec![sr]),
+        CompareImmediate => (vec![r1], vec![sr]),
+        AddImmediate | SubtractImmediate | Load | LoadByte | Move => (vec![r2], vec![r1]),
+        LoadImmediate | LoadDirect | LoadDirectByte => (vec![], vec![r1]),
+        Store | StoreByte => (vec![r1, r2], vec![]),
+        StoreDirect | StoreDirectByte => (vec![r1], vec![]),
+        Push => (vec![r1, sp], vec![sp]),
+        Pop => (vec![sp], vec![r1, sp]),
+        Call | Return => (vec![sp, pc], vec![sp, pc]),
+        Jump => (vec![], vec![pc]),
+        BranchEqual | BranchNotEqual => (vec![sr], vec![pc]),
+        NOp | Halt | Invalid => (vec![], vec![]),
+    }
+}
+
 #[derive(Debug)]
 pub enum StatusBit {
     Zero = 0,
@@ -36,11 +65,21 @@ pub enum StatusBit {
     Carry = 2,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    Ok,
+    Halted,
+    Fault(Fault),
+}
+
 pub struct CPU {
     pub regs: [Wrapping<u32>; 19],
     pub memory: AddressSpace,
     halt: bool,
+    // Number of retired instructions
     pub cycle_counter: u64,
+    entry: u32,
+    stack: Option<StackBounds>,
 }
 
 impl CPU {
@@ -53,12 +92,71 @@ impl CPU {
             memory,
             halt: false,
             cycle_counter: 0,
+            entry: MEMORY_START,
+            stack: None,
         };
 
-        cpu.regs[Register::PC as usize] = Wrapping(MEMORY_START);
+        cpu.reset();
         cpu
     }
 
+    // Resets registers, halt state and cycle counter. Memory is left as is,
+    // so a loaded program can be run again.
+    pub fn reset(&mut self) {
+        self.regs = [Wrapping(0u32); 19];
+        self.regs[Register::PC as usize] = Wrapping(self.entry);
+        if let Some(stack) = self.stack {
+            self.regs[Register::SP as usize] = Wrapping(stack.high);
+        }
+        self.halt = false;
+        self.cycle_counter = 0;
+    }
+
+    // Sets the address execution starts at, now and after every reset
+    pub fn set_entry_point(&mut self, entry: u32) {
+        self.entry = entry;
+        self.regs[Register::PC as usize] = Wrapping(entry);
+    }
+
+    // Limits the stack to the given bounds. Pushes below the lower bound and
+    // pops above the upper bound fault instead of corrupting memory. SP starts
+    // at the upper bound, now and after every reset.
+    pub fn set_stack_bounds(&mut self, stack: Option<StackBounds>) {
+        self.stack = stack;
+        if let Some(stack) = stack {
+            self.regs[Register::SP as usize] = Wrapping(stack.high);
+        }
+    }
+
+    // Addresses of the call instructions whose return addresses are on the
+    // stack, innermost first. Without stack bounds the stack is assumed to
+    // end at the top of main memory. Any word on the stack that points after
+    // a call instruction is taken as a return address.
+    pub fn backtrace(&self) -> Vec<u32> {
+        let top = self.stack.map_or(MEMORY_END + 1, |stack| stack.high);
+        let mut calls = Vec::new();
+        let mut addr = self.get_register(Register::SP);
+
+        while addr.checked_add(4).is_some_and(|end| end <= top) {
+            let value = match self.memory.read_doubleword(addr) {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+            let call = value.wrapping_sub(8);
+            if value >= 8
+                && self
+                    .memory
+                    .read_instruction(call)
+                    .is_ok_and(|bytes| DecodedInstruction::decode(bytes).instruction_type == Call)
+            {
+                calls.push(call);
+            }
+            addr += 4;
+        }
+
+        calls
+    }
+
     fn set_status_bit(&mut self, bit: StatusBit, set: bool) {
         let mut value = self.regs[Register::SR as usize].0;
 
@@ -73,50 +171,133 @@ impl CPU {
         self.regs[Register::SR as usize] = Wrapping(value);
     }
 
-    fn get_status_bit(&self, bit: StatusBit) -> bool {
+    pub fn get_status_bit(&self, bit: StatusBit) -> bool {
         let value = self.regs[Register::SR as usize].0;
         let mask = 0x1u32 << bit as u32;
 
         (value & mask) != 0
     }
 
-    fn get_register(self: &Self, reg: Register) -> u32 {
+    pub fn get_register(&self, reg: Register) -> u32 {
         self.regs[reg as usize].0
     }
 
-    #[allow(unused)]
-    fn set_register(self: &mut Self, reg: Register, value: u32) {
+    pub fn set_register(&mut self, reg: Register, value: u32) {
         self.regs[reg as usize] = Wrapping(value);
     }
 
-    pub fn print_state(&self) {
-        eprintln!("Registers:");
-        eprintln!("{:#?}", self.regs);
+    pub fn state(&self) -> String {
+        format!("Registers:\n{:#?}", self.regs)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt
+    }
+
+    // Runs until the CPU halts or faults.
+    pub fn run(&mut self) -> StepResult {
+        self.run_until(|_| false)
+    }
+
+    // Executes at most the given number of instructions. Returns Ok if the
+    // CPU is still running afterwards.
+    pub fn run_for(&mut self, cycles: u64) -> StepResult {
+        for _ in 0..cycles {
+            match self.step() {
+                StepResult::Ok => {}
+                result => return result,
+            }
+        }
+        StepResult::Ok
+    }
+
+    // Executes instructions until the predicate, which is checked before
+    // every instruction, returns true.
+    pub fn run_until<F>(&mut self, mut predicate: F) -> StepResult
+    where
+        F: FnMut(&CPU) -> bool,
+    {
+        while !predicate(self) {
+            match self.step() {
+                StepResult::Ok => {}
+                result => return result,
+            }
+        }
+        StepResult::Ok
     }
 
-    pub fn run(self: &mut Self) {
-        // Init
+    // Executes a single instruction. On a fault, PC is left pointing at the
+    // faulting instruction.
+    pub fn step(&mut self) -> StepResult {
+        if self.halt {
+            return StepResult::Halted;
+        }
+
+        let pc = self.get_register(Register::PC);
+        let result = self
+            .load_instruction()
+            .and_then(|decoded_instruction| self.execute_instruction(&decoded_instruction));
 
-        while !self.halt {
-            let instruction = self.load_instruction();
-            let decoded_instruction = DecodedInstruction::decode(instruction);
+        // Only instructions that completed are counted
+        if result.is_ok() {
             self.cycle_counter += 1;
-            self.execute_instruction(&decoded_instruction);
         }
+
+        match result {
+            Ok(()) if self.halt => StepResult::Halted,
+            Ok(()) => StepResult::Ok,
+            Err(fault) => {
+                self.set_register(Register::PC, pc);
+                StepResult::Fault(fault)
+            }
+        }
+    }
+
+    // Executes a single instruction and returns the information needed to
+    // revert it. Memory writes are only captured while journaling is enabled
+    // on the address space.
+    pub fn step_recorded(&mut self) -> (StepResult, UndoRecord) {
+        let regs = self.regs;
+        let halted = self.halt;
+        let cycle_counter = self.cycle_counter;
+
+        let result = self.step();
+
+        let record = UndoRecord {
+            regs,
+            halted,
+            cycle_counter,
+            writes: self.memory.take_journal(),
+        };
+        (result, record)
+    }
+
+    pub fn undo(&mut self, record: &UndoRecord) {
+        self.memory.undo_writes(&record.writes);
+        self.regs = record.regs;
+        self.halt = record.halted;
+        self.cycle_counter = record.cycle_counter;
     }
 
-    fn load_instruction(self: &mut Self) -> &[u8] {
+    fn load_instruction(&mut self) -> Result<DecodedInstruction, Fault> {
         let pc = self.regs[Register::PC as usize].0;
         self.regs[Register::PC as usize] += Wrapping(8);
 
-        self.memory.read_instruction(pc)
+        self.memory.fetch_instruction(pc)
     }
 
-    fn execute_instruction(self: &mut Self, d: &DecodedInstruction) {
+    pub(crate) fn execute_instruction(&mut self, d: &DecodedInstruction) -> Result<(), Fault> {
         let reg_1 = d.reg_1 as usize;
         let reg_2 = d.reg_2 as usize;
         let reg_3 = d.reg_3 as usize;
 
+        if [reg_1, reg_2, reg_3]
+            .iter()
+            .any(|&reg| reg >= self.regs.len())
+        {
+            return Err(Fault::InvalidInstruction(d.instruction_type.into()));
+        }
+
         match d.instruction_type {
             NOp => {}
             Halt => self.halt(),
@@ -126,7 +307,12 @@ impl CPU {
             Add => self.regs[reg_1] = self.regs[reg_2] + self.regs[reg_3],
             Subtract => self.regs[reg_1] = self.regs[reg_2] - self.regs[reg_3],
             Multiply => self.regs[reg_1] = self.regs[reg_2] * self.regs[reg_3],
-            Divide => self.regs[reg_1] = self.regs[reg_2] / self.regs[reg_3],
+            Divide => {
+                if self.regs[reg_3].0 == 0 {
+                    return Err(Fault::DivisionByZero);
+                }
+                self.regs[reg_1] = self.regs[reg_2] / self.regs[reg_3]
+            }
             Compare => {
                 let l = self.regs[reg_1].0;
                 let r = self.regs[reg_2].0;
@@ -148,26 +334,28 @@ impl CPU {
             Complement => self.regs[reg_1] = !self.regs[reg_1],
 
             LoadImmediate => self.regs[reg_1] = Wrapping(d.operand),
-            Load => self.regs[reg_1] = Wrapping(self.memory.read_doubleword(self.regs[reg_2].0)),
+            Load => self.regs[reg_1] = Wrapping(self.memory.read_doubleword(self.regs[reg_2].0)?),
             LoadByte => {
-                self.regs[reg_1] = Wrapping(u32::from(self.memory.read(self.regs[reg_2].0)))
+                self.regs[reg_1] = Wrapping(u32::from(self.memory.read(self.regs[reg_2].0)?))
             }
-            LoadDirect => self.regs[reg_1] = Wrapping(self.memory.read_doubleword(d.operand)),
-            LoadDirectByte => self.regs[reg_1] = Wrapping(u32::from(self.memory.read(d.operand))),
+            LoadDirect => self.regs[reg_1] = Wrapping(self.memory.read_doubleword(d.operand)?),
+            LoadDirectByte => self.regs[reg_1] = Wrapping(u32::from(self.memory.read(d.operand)?)),
             Store => self
                 .memory
-                .write_doubleword(self.regs[reg_2].0, self.regs[reg_1].0),
+                .write_doubleword(self.regs[reg_2].0, self.regs[reg_1].0)?,
             StoreByte => self
                 .memory
-                .write(self.regs[reg_2].0, self.regs[reg_1].0 as u8),
-            StoreDirect => self.memory.write_doubleword(d.operand, self.regs[reg_1].0),
-            StoreDirectByte => self.memory.write(d.operand, self.regs[reg_1].0 as u8),
-            Push => self.push(reg_1),
-            Pop => self.pop(reg_1),
+                .write(self.regs[reg_2].0, self.regs[reg_1].0 as u8)?,
+            StoreDirect => self
+                .memory
+                .write_doubleword(d.operand, self.regs[reg_1].0)?,
+            StoreDirectByte => self.memory.write(d.operand, self.regs[reg_1].0 as u8)?,
+            Push => self.push(reg_1)?,
+            Pop => self.pop(reg_1)?,
 
             Jump => self.regs[Register::PC as usize] = Wrapping(d.operand),
-            Call => self.call(d.operand),
-            Return => self.return_from_call(),
+            Call => self.call(d.operand)?,
+            Return => self.return_from_call()?,
 
             BranchEqual => {
                 if self.get_status_bit(StatusBit::Zero) {
@@ -183,35 +371,57 @@ impl CPU {
 
             Move => self.regs[reg_1] = self.regs[reg_2],
 
-            Invalid => panic!("Invalid instruction {:?}", d.instruction_type),
+            Invalid => return Err(Fault::InvalidInstruction(d.instruction_type.into())),
+        }
+
+        Ok(())
+    }
+
+    // Faults if moving SP by the given number of bytes leaves the stack
+    fn check_stack(&self, offset: i64) -> Result<(), Fault> {
+        let stack = match self.stack {
+            Some(stack) => stack,
+            None => return Ok(()),
+        };
+
+        let sp = self.get_register(Register::SP);
+        let moved = i64::from(sp) + offset;
+        if moved < i64::from(stack.low) {
+            Err(Fault::StackOverflow(sp))
+        } else if moved > i64::from(stack.high) {
+            Err(Fault::StackUnderflow(sp))
+        } else {
+            Ok(())
         }
     }
 
-    fn push(&mut self, register: usize) {
-        self.regs[Register::SP as usize] -= Wrapping(4);
-        self.memory
-            .write_doubleword(self.regs[Register::SP as usize].0, self.regs[register].0);
+    fn push(&mut self, register: usize) -> Result<(), Fault> {
+        self.check_stack(-4)?;
+        let sp = self.regs[Register::SP as usize] - Wrapping(4);
+        self.memory.write_doubleword(sp.0, self.regs[register].0)?;
+        self.regs[Register::SP as usize] = sp;
+        Ok(())
     }
 
-    fn pop(&mut self, register: usize) {
-        self.regs[register] = Wrapping(
-            self.memory
-                .read_doubleword(self.regs[Register::SP as usize].0),
-        );
-        self.regs[Register::SP as usize] += Wrapping(4);
+    fn pop(&mut self, register: usize) -> Result<(), Fault> {
+        self.check_stack(4)?;
+        let sp = self.regs[Register::SP as usize];
+        self.regs[register] = Wrapping(self.memory.read_doubleword(sp.0)?);
+        self.regs[Register::SP as usize] = sp + Wrapping(4);
+        Ok(())
     }
 
-    fn call(&mut self, address: u32) {
-        self.push(Register::PC as usize);
+    fn call(&mut self, address: u32) -> Result<(), Fault> {
+        self.push(Register::PC as usize)?;
         self.regs[Register::PC as usize] = Wrapping(address);
+        Ok(())
     }
 
-    fn return_from_call(&mut self) {
-        self.pop(Register::PC as usize);
+    fn return_from_call(&mut self) -> Result<(), Fault> {
+        self.pop(Register::PC as usize)
     }
 
-    fn halt(self: &mut Self) {
-        eprintln!("Halting CPU at PC=0x{:X}", self.get_register(Register::PC));
+    fn halt(&mut self) {
         self.halt = true;
     }
 
@@ -255,70 +465,80 @@ mod tests {
     #[test]
     fn test_load_immediate() {
         let mut cpu = create_cpu();
-        cpu.execute_instruction(&DecodedInstruction::new(LoadImmediate, 0, 0, 0, 1337));
+        cpu.execute_instruction(&DecodedInstruction::new(LoadImmediate, 0, 0, 0, 1337))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 1337);
     }
 
     #[test]
     fn test_increment() {
         let mut cpu = cpu_arith_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(Increment, 1, 0, 0, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Increment, 1, 0, 0, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R1), 11);
     }
 
     #[test]
     fn test_decrement() {
         let mut cpu = cpu_arith_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(Decrement, 1, 0, 0, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Decrement, 1, 0, 0, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R1), 9);
     }
 
     #[test]
     fn test_add() {
         let mut cpu = cpu_arith_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(Add, 0, 1, 2, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Add, 0, 1, 2, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 15);
     }
 
     #[test]
     fn test_subtract() {
         let mut cpu = cpu_arith_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(Subtract, 0, 1, 2, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Subtract, 0, 1, 2, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 5);
     }
 
     #[test]
     fn test_multiply() {
         let mut cpu = cpu_arith_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(Multiply, 0, 1, 2, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Multiply, 0, 1, 2, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 50);
     }
 
     #[test]
     fn test_divide() {
         let mut cpu = cpu_arith_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(Divide, 0, 1, 2, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Divide, 0, 1, 2, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 2);
     }
 
     #[test]
     fn test_or() {
         let mut cpu = cpu_binary_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(Or, 0, 1, 2, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Or, 0, 1, 2, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 0b1111);
     }
 
     #[test]
     fn test_and() {
         let mut cpu = cpu_binary_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(And, 0, 1, 2, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(And, 0, 1, 2, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 0b1000);
     }
 
     #[test]
     fn test_xor() {
         let mut cpu = cpu_binary_prep();
-        cpu.execute_instruction(&DecodedInstruction::new(XOr, 0, 1, 2, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(XOr, 0, 1, 2, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 0b0111);
     }
 
@@ -326,7 +546,8 @@ mod tests {
     fn test_negate() {
         let mut cpu = create_cpu();
         cpu.set_register(R0, 0b00000000_00000001);
-        cpu.execute_instruction(&DecodedInstruction::new(Negate, 0, 0, 0, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Negate, 0, 0, 0, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 0b11111111_11111111_11111111_11111111);
     }
 
@@ -334,7 +555,8 @@ mod tests {
     fn test_complement() {
         let mut cpu = create_cpu();
         cpu.set_register(R0, 0b00000000_00000001);
-        cpu.execute_instruction(&DecodedInstruction::new(Complement, 0, 0, 0, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Complement, 0, 0, 0, 0))
+            .unwrap();
         assert_eq!(cpu.get_register(R0), 0b11111111_11111111_11111111_11111110);
     }
 
@@ -343,7 +565,8 @@ mod tests {
         let mut cpu = create_cpu();
         cpu.set_register(R0, 10);
         cpu.set_register(R1, 10);
-        cpu.execute_instruction(&DecodedInstruction::new(Compare, 0, 1, 0, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Compare, 0, 1, 0, 0))
+            .unwrap();
         assert!(cpu.get_status_bit(StatusBit::Zero));
         assert!(!cpu.get_status_bit(StatusBit::Carry));
         assert!(!cpu.get_status_bit(StatusBit::Negative));
@@ -354,7 +577,8 @@ mod tests {
         let mut cpu = create_cpu();
         cpu.set_register(R0, 10);
         cpu.set_register(R1, 11);
-        cpu.execute_instruction(&DecodedInstruction::new(Compare, 0, 1, 0, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Compare, 0, 1, 0, 0))
+            .unwrap();
         assert!(!cpu.get_status_bit(StatusBit::Zero));
         assert!(cpu.get_status_bit(StatusBit::Carry));
         assert!(cpu.get_status_bit(StatusBit::Negative));
@@ -365,7 +589,8 @@ mod tests {
         let mut cpu = create_cpu();
         cpu.set_register(R0, 0xfffffff6); // -10
         cpu.set_register(R1, 11);
-        cpu.execute_instruction(&DecodedInstruction::new(Compare, 0, 1, 0, 0));
+        cpu.execute_instruction(&DecodedInstruction::new(Compare, 0, 1, 0, 0))
+            .unwrap();
         assert!(!cpu.get_status_bit(StatusBit::Zero));
         assert!(!cpu.get_status_bit(StatusBit::Carry));
         assert!(cpu.get_status_bit(StatusBit::Negative));
@@ -386,10 +611,12 @@ mod tests {
     #[test]
     fn test_branch_equal() {
         let mut cpu = create_cpu();
-        cpu.execute_instruction(&DecodedInstruction::new(BranchEqual, 0, 0, 0, 0xCAFEBABE));
+        cpu.execute_instruction(&DecodedInstruction::new(BranchEqual, 0, 0, 0, 0xCAFEBABE))
+            .unwrap();
         assert_ne!(cpu.get_register(Register::PC), 0xCAFEBABE);
         cpu.set_status_bit(StatusBit::Zero, true);
-        cpu.execute_instruction(&DecodedInstruction::new(BranchEqual, 0, 0, 0, 0xCAFEBABE));
+        cpu.execute_instruction(&DecodedInstruction::new(BranchEqual, 0, 0, 0, 0xCAFEBABE))
+            .unwrap();
 
         assert_eq!(cpu.get_register(Register::PC), 0xCAFEBABE);
     }
@@ -404,7 +631,8 @@ mod tests {
             0,
             0,
             0xCAFEBABE,
-        ));
+        ))
+        .unwrap();
 
         assert_ne!(cpu.get_register(Register::PC), 0xCAFEBABE);
 
@@ -415,7 +643,212 @@ mod tests {
             0,
             0,
             0xCAFEBABE,
-        ));
+        ))
+        .unwrap();
         assert_eq!(cpu.get_register(Register::PC), 0xCAFEBABE);
     }
+
+    fn load_program(cpu: &mut CPU, program: &[DecodedInstruction]) {
+        let mut bytes = Vec::new();
+        for instruction in program {
+            bytes.extend_from_slice(&instruction.encode());
+        }
+        cpu.memory.write_all(&bytes, MEMORY_START).unwrap();
+    }
+
+    #[test]
+    fn test_step_until_halt() {
+        let mut cpu = create_cpu();
+        load_program(
+            &mut cpu,
+            &[
+                DecodedInstruction::new(LoadImmediate, 0, 0, 0, 42),
+                DecodedInstruction::new(Halt, 0, 0, 0, 0),
+            ],
+        );
+
+        assert_eq!(cpu.step(), StepResult::Ok);
+        assert_eq!(cpu.get_register(R0), 42);
+        assert_eq!(cpu.step(), StepResult::Halted);
+        assert_eq!(cpu.step(), StepResult::Halted);
+        assert_eq!(cpu.cycle_counter, 2);
+    }
+
+    #[test]
+    fn test_run_for() {
+        let mut cpu = create_cpu();
+        load_program(
+            &mut cpu,
+            &[
+                DecodedInstruction::new(Increment, 0, 0, 0, 0),
+                DecodedInstruction::new(Jump, 0, 0, 0, MEMORY_START),
+            ],
+        );
+
+        assert_eq!(cpu.run_for(10), StepResult::Ok);
+        assert_eq!(cpu.get_register(R0), 5);
+        assert_eq!(cpu.cycle_counter, 10);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut cpu = create_cpu();
+        load_program(
+            &mut cpu,
+            &[
+                DecodedInstruction::new(Increment, 0, 0, 0, 0),
+                DecodedInstruction::new(Jump, 0, 0, 0, MEMORY_START),
+            ],
+        );
+
+        assert_eq!(
+            cpu.run_until(|cpu| cpu.get_register(R0) == 3),
+            StepResult::Ok
+        );
+        assert_eq!(cpu.get_register(PC), MEMORY_START + 8);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut cpu = create_cpu();
+        load_program(&mut cpu, &[DecodedInstruction::new(Halt, 0, 0, 0, 0)]);
+
+        assert_eq!(cpu.run(), StepResult::Halted);
+        cpu.reset();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.get_register(PC), MEMORY_START);
+        assert_eq!(cpu.cycle_counter, 0);
+        assert_eq!(cpu.run(), StepResult::Halted);
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let mut cpu = create_cpu();
+        load_program(
+            &mut cpu,
+            &[
+                DecodedInstruction::new(Increment, 0, 0, 0, 0),
+                DecodedInstruction::new(Halt, 0, 0, 0, 0),
+            ],
+        );
+
+        cpu.set_entry_point(MEMORY_START + 8);
+        assert_eq!(cpu.run(), StepResult::Halted);
+        assert_eq!(cpu.get_register(R0), 0);
+
+        cpu.reset();
+        assert_eq!(cpu.get_register(PC), MEMORY_START + 8);
+    }
+
+    #[test]
+    fn test_fault_invalid_instruction() {
+        let mut cpu = create_cpu();
+        load_program(&mut cpu, &[DecodedInstruction::new(Invalid, 0, 0, 0, 0)]);
+
+        assert_eq!(
+            cpu.run(),
+            StepResult::Fault(Fault::InvalidInstruction(0xFF))
+        );
+        assert_eq!(cpu.get_register(PC), MEMORY_START);
+    }
+
+    #[test]
+    fn test_fault_invalid_address() {
+        let mut cpu = create_cpu();
+        load_program(
+            &mut cpu,
+            &[DecodedInstruction::new(LoadDirect, 0, 0, 0, 0x10)],
+        );
+
+        assert_eq!(cpu.step(), StepResult::Fault(Fault::InvalidAddress(0x10)));
+        assert_eq!(cpu.cycle_counter, 0);
+    }
+
+    #[test]
+    fn test_fault_division_by_zero() {
+        let mut cpu = cpu_arith_prep();
+        let result = cpu.execute_instruction(&DecodedInstruction::new(Divide, 0, 1, 3, 0));
+        assert_eq!(result, Err(Fault::DivisionByZero));
+    }
+
+    #[test]
+    fn test_fault_pc_out_of_memory() {
+        let mut cpu = create_cpu();
+        cpu.set_register(PC, 0);
+        assert_eq!(cpu.step(), StepResult::Fault(Fault::InvalidAddress(0)));
+    }
+
+    #[test]
+    fn test_fault_stack_overflow() {
+        let top = MEMORY_START + 0x1000;
+        let mut cpu = create_cpu();
+        cpu.set_stack_bounds(Some(StackBounds {
+            low: top - 8,
+            high: top,
+        }));
+        load_program(
+            &mut cpu,
+            &[
+                DecodedInstruction::new(LoadImmediate, SP as u8, 0, 0, top),
+                DecodedInstruction::new(Call, 0, 0, 0, MEMORY_START + 0x10),
+                DecodedInstruction::new(Call, 0, 0, 0, MEMORY_START + 0x10),
+            ],
+        );
+
+        assert_eq!(cpu.run(), StepResult::Fault(Fault::StackOverflow(top - 8)));
+        assert_eq!(cpu.get_register(PC), MEMORY_START + 0x10);
+        assert_eq!(
+            cpu.backtrace(),
+            vec![MEMORY_START + 0x10, MEMORY_START + 0x8]
+        );
+    }
+
+    #[test]
+    fn test_fault_stack_underflow() {
+        let top = MEMORY_START + 0x1000;
+        let mut cpu = create_cpu();
+        cpu.set_stack_bounds(Some(StackBounds {
+            low: top - 8,
+            high: top,
+        }));
+        load_program(
+            &mut cpu,
+            &[
+                DecodedInstruction::new(LoadImmediate, SP as u8, 0, 0, top),
+                DecodedInstruction::new(Push, 0, 0, 0, 0),
+                DecodedInstruction::new(Pop, 0, 0, 0, 0),
+                DecodedInstruction::new(Return, 0, 0, 0, 0),
+            ],
+        );
+
+        assert_eq!(cpu.get_register(SP), top);
+        assert_eq!(cpu.run(), StepResult::Fault(Fault::StackUnderflow(top)));
+        assert_eq!(cpu.get_register(PC), MEMORY_START + 0x18);
+        assert!(cpu.backtrace().is_empty());
+
+        // Without bounds the return jumps to whatever is above the stack
+        cpu.reset();
+        assert_eq!(cpu.get_register(SP), top);
+        cpu.set_stack_bounds(None);
+        assert_eq!(cpu.run_for(4), StepResult::Ok);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut cpu = create_cpu();
+        load_program(
+            &mut cpu,
+            &[
+                DecodedInstruction::new(Increment, 0, 0, 0, 0),
+                DecodedInstruction::new(LoadImmediate, 1, 0, 0, 0x0100_0000),
+                DecodedInstruction::new(StoreDirect, 1, 0, 0, MEMORY_START),
+                DecodedInstruction::new(Jump, 0, 0, 0, MEMORY_START),
+            ],
+        );
+
+        // The first instruction is decoded and cached before it is
+        // overwritten with a halt instruction
+        assert_eq!(cpu.run_for(100), StepResult::Halted);
+        assert_eq!(cpu.get_register(R0), 1);
+    }
 }
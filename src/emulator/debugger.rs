@@ -0,0 +1,413 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::{BufRead, Write};
+
+use crate::assembler::parser::{parse_literal, parse_numeric_literal};
+use crate::common::debug::DebugInfo;
+use crate::emulator::cpu::{Register, StepResult, CPU};
+use crate::emulator::fault::Fault;
+use crate::emulator::history::History;
+
+pub const DEFAULT_HISTORY_CAPACITY: usize = 1_000_000;
+
+#[derive(Debug, PartialEq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(u32),
+    Watchpoint(u32),
+    Halted,
+    Fault(Fault),
+    StartOfHistory,
+}
+
+pub struct Debugger {
+    pub cpu: CPU,
+    breakpoints: BTreeSet<u32>,
+    watchpoints: BTreeSet<u32>,
+    history: History,
+    debug: Option<DebugInfo>,
+}
+
+impl Debugger {
+    pub fn new(mut cpu: CPU, history_capacity: usize) -> Self {
+        cpu.memory.set_journaling(true);
+
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            history: History::new(history_capacity),
+            debug: None,
+        }
+    }
+
+    // Debug information of the program, used to show source locations and
+    // to accept label names in place of addresses
+    pub fn set_debug_info(&mut self, debug: DebugInfo) {
+        self.debug = Some(debug);
+    }
+
+    // Formats an address together with its label and source line, if known
+    pub fn describe(&self, addr: u32) -> String {
+        let description = self
+            .debug
+            .as_ref()
+            .map(|debug| debug.describe(addr))
+            .unwrap_or_default();
+
+        if description.is_empty() {
+            format!("0x{:X}", addr)
+        } else {
+            format!("0x{:X} {}", addr, description)
+        }
+    }
+
+    fn parse_address(&self, arg: &str) -> Option<u32> {
+        parse_numeric_literal(arg).or_else(|| {
+            let debug = self.debug.as_ref()?;
+            let range = debug.symbols.iter().find(|range| range.name == arg)?;
+            Some(range.start)
+        })
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u32) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u32) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    // Watchpoints trigger whenever an instruction writes to the given byte
+    // of main memory, in both execution directions.
+    pub fn add_watchpoint(&mut self, addr: u32) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u32) -> bool {
+        self.watchpoints.remove(&addr)
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.cpu.regs[Register::PC as usize].0
+    }
+
+    pub fn step(&mut self) -> StopReason {
+        if self.cpu.is_halted() {
+            return StopReason::Halted;
+        }
+
+        let (result, record) = self.cpu.step_recorded();
+        let watched = self
+            .watchpoints
+            .iter()
+            .find(|&&addr| record.wrote_to(addr))
+            .cloned();
+        self.history.push(record);
+
+        let pc = self.pc();
+        if let StepResult::Fault(fault) = result {
+            StopReason::Fault(fault)
+        } else if let Some(addr) = watched {
+            StopReason::Watchpoint(addr)
+        } else if result == StepResult::Halted {
+            StopReason::Halted
+        } else if self.breakpoints.contains(&pc) {
+            StopReason::Breakpoint(pc)
+        } else {
+            StopReason::Step
+        }
+    }
+
+    pub fn step_back(&mut self) -> StopReason {
+        let record = match self.history.pop() {
+            Some(record) => record,
+            None => return StopReason::StartOfHistory,
+        };
+
+        self.cpu.undo(&record);
+
+        let pc = self.pc();
+        let watched = self
+            .watchpoints
+            .iter()
+            .find(|&&addr| record.wrote_to(addr))
+            .cloned();
+
+        if let Some(addr) = watched {
+            StopReason::Watchpoint(addr)
+        } else if self.breakpoints.contains(&pc) {
+            StopReason::Breakpoint(pc)
+        } else {
+            StopReason::Step
+        }
+    }
+
+    pub fn resume(&mut self) -> StopReason {
+        loop {
+            match self.step() {
+                StopReason::Step => continue,
+                reason => return reason,
+            }
+        }
+    }
+
+    pub fn reverse_resume(&mut self) -> StopReason {
+        loop {
+            match self.step_back() {
+                StopReason::Step => continue,
+                reason => return reason,
+            }
+        }
+    }
+
+    pub fn run_interactive(
+        &mut self,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        loop {
+            write!(output, "({}) ", self.describe(self.pc()))?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let command = match words.first() {
+                Some(command) => *command,
+                None => continue,
+            };
+            let argument = words.get(1).and_then(|arg| self.parse_address(arg));
+
+            let reason = match (command, argument) {
+                ("s", _) | ("step", _) | ("bs", _) | ("back", _) => {
+                    // Counts are plain numbers, not addresses
+                    let count = match words.get(1) {
+                        None => 1,
+                        Some(arg) => match parse_literal(arg) {
+                            Ok(count) if !arg.starts_with('-') => count,
+                            _ => {
+                                writeln!(output, "Invalid count '{}'", arg)?;
+                                continue;
+                            }
+                        },
+                    };
+                    if matches!(command, "bs" | "back") {
+                        self.repeat(count, Debugger::step_back)
+                    } else {
+                        self.repeat(count, Debugger::step)
+                    }
+                }
+                ("c", _) | ("continue", _) => self.resume(),
+                ("rc", _) | ("reverse-continue", _) => self.reverse_resume(),
+                ("b", Some(addr)) | ("break", Some(addr)) => {
+                    self.add_breakpoint(addr);
+                    continue;
+                }
+                ("db", Some(addr)) | ("delete", Some(addr)) => {
+                    if !self.remove_breakpoint(addr) {
+                        writeln!(output, "No breakpoint at 0x{:X}", addr)?;
+                    }
+                    continue;
+                }
+                ("w", Some(addr)) | ("watch", Some(addr)) => {
+                    self.add_watchpoint(addr);
+                    continue;
+                }
+                ("dw", Some(addr)) | ("unwatch", Some(addr)) => {
+                    if !self.remove_watchpoint(addr) {
+                        writeln!(output, "No watchpoint at 0x{:X}", addr)?;
+                    }
+                    continue;
+                }
+                ("r", _) | ("regs", _) => {
+                    writeln!(output, "{:#?}", self.cpu.regs)?;
+                    continue;
+                }
+                ("bt", _) | ("backtrace", _) => {
+                    let pc = self.pc();
+                    for (index, addr) in std::iter::once(pc).chain(self.cpu.backtrace()).enumerate()
+                    {
+                        writeln!(output, "#{} {}", index, self.describe(addr))?;
+                    }
+                    continue;
+                }
+                ("q", _) | ("quit", _) => return Ok(()),
+                _ => {
+                    writeln!(
+                        output,
+                        "Commands: s|step [n], bs|back [n], c|continue, rc|reverse-continue, \
+                         b|break ADDR|LABEL, db|delete ADDR|LABEL, w|watch ADDR, dw|unwatch ADDR, \
+                         r|regs, bt|backtrace, q|quit"
+                    )?;
+                    continue;
+                }
+            };
+
+            match reason {
+                StopReason::Step => {}
+                StopReason::Breakpoint(addr) => {
+                    writeln!(output, "Breakpoint at {}", self.describe(addr))?
+                }
+                StopReason::Watchpoint(addr) => {
+                    writeln!(output, "Watchpoint 0x{:X} written", addr)?
+                }
+                StopReason::Halted => writeln!(output, "CPU halted")?,
+                StopReason::Fault(fault) => writeln!(output, "{}", fault)?,
+                StopReason::StartOfHistory => writeln!(output, "Reached start of history")?,
+            }
+        }
+    }
+
+    fn repeat<F>(&mut self, count: u32, mut func: F) -> StopReason
+    where
+        F: FnMut(&mut Debugger) -> StopReason,
+    {
+        let mut reason = StopReason::Step;
+        for _ in 0..count {
+            reason = func(self);
+            if reason != StopReason::Step {
+                break;
+            }
+        }
+        reason
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::debug::SymbolRange;
+    use crate::common::encoding::DecodedInstruction;
+    use crate::common::generated::instruction::Instruction;
+    use crate::common::generated::instruction::Instruction::*;
+    use crate::emulator::constants::*;
+    use crate::emulator::memory::{AddressSpace, Memory};
+
+    fn create_debugger(program: Vec<(Instruction, u8, u32)>) -> Debugger {
+        let mut memory = AddressSpace::default();
+        let mut bytes = Vec::new();
+        for (instruction, reg, operand) in program {
+            bytes.extend_from_slice(
+                &DecodedInstruction::new(instruction, reg, 0, 0, operand).encode(),
+            );
+        }
+        memory.write_all(&bytes, MEMORY_START).unwrap();
+
+        Debugger::new(CPU::new(memory), DEFAULT_HISTORY_CAPACITY)
+    }
+
+    #[test]
+    fn test_step_back_restores_registers() {
+        let mut debugger = create_debugger(vec![
+            (LoadImmediate, 0, 10),
+            (Increment, 0, 0),
+            (Halt, 0, 0),
+        ]);
+
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.cpu.regs[0].0, 11);
+
+        assert_eq!(debugger.step_back(), StopReason::Step);
+        assert_eq!(debugger.cpu.regs[0].0, 10);
+        assert_eq!(debugger.pc(), MEMORY_START + 8);
+
+        assert_eq!(debugger.step_back(), StopReason::Step);
+        assert_eq!(debugger.cpu.regs[0].0, 0);
+        assert_eq!(debugger.step_back(), StopReason::StartOfHistory);
+    }
+
+    #[test]
+    fn test_step_back_restores_memory() {
+        let addr = MEMORY_START + 0x100;
+        let mut debugger = create_debugger(vec![
+            (LoadImmediate, 0, 0xAABBCCDD),
+            (StoreDirect, 0, addr),
+            (Halt, 0, 0),
+        ]);
+        debugger.add_watchpoint(addr);
+
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.step(), StopReason::Watchpoint(addr));
+        assert_eq!(debugger.cpu.memory.read_doubleword(addr), Ok(0xAABBCCDD));
+
+        assert_eq!(debugger.step_back(), StopReason::Watchpoint(addr));
+        assert_eq!(debugger.cpu.memory.read_doubleword(addr), Ok(0));
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let mut debugger = create_debugger(vec![(LoadImmediate, 0, 1), (Halt, 0, 0)]);
+        assert_eq!(debugger.describe(MEMORY_START), "0x100000");
+
+        let mut debug = DebugInfo::default();
+        debug.add_line(MEMORY_START, 16, "main.asm", 3);
+        debug.symbols.push(SymbolRange {
+            name: "main".into(),
+            start: MEMORY_START,
+            end: MEMORY_START + 16,
+        });
+        debugger.set_debug_info(debug);
+
+        assert_eq!(
+            debugger.describe(MEMORY_START + 8),
+            "0x100008 main+0x8 (main.asm:3)"
+        );
+        assert_eq!(debugger.parse_address("main"), Some(MEMORY_START));
+        assert_eq!(debugger.parse_address("0x10"), Some(0x10));
+        assert_eq!(debugger.parse_address("missing"), None);
+    }
+
+    #[test]
+    fn test_step_count() {
+        let mut debugger = create_debugger(vec![
+            (LoadImmediate, 0, 1),
+            (LoadImmediate, 1, 2),
+            (LoadImmediate, 2, 3),
+            (Halt, 0, 0),
+        ]);
+        let mut debug = DebugInfo::default();
+        debug.symbols.push(SymbolRange {
+            name: "main".into(),
+            start: MEMORY_START,
+            end: MEMORY_START + 32,
+        });
+        debugger.set_debug_info(debug);
+
+        let mut input = "s 0x2\ns main\ns -1\nbs\nq\n".as_bytes();
+        let mut output = Vec::new();
+        debugger.run_interactive(&mut input, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Invalid count 'main'"));
+        assert!(output.contains("Invalid count '-1'"));
+        assert_eq!(debugger.pc(), MEMORY_START + 8);
+    }
+
+    #[test]
+    fn test_reverse_resume_stops_at_breakpoint() {
+        let mut debugger = create_debugger(vec![
+            (LoadImmediate, 0, 1),
+            (LoadImmediate, 1, 2),
+            (LoadImmediate, 2, 3),
+            (Halt, 0, 0),
+        ]);
+
+        assert_eq!(debugger.resume(), StopReason::Halted);
+        assert!(debugger.cpu.is_halted());
+
+        debugger.add_breakpoint(MEMORY_START + 8);
+        assert_eq!(
+            debugger.reverse_resume(),
+            StopReason::Breakpoint(MEMORY_START + 8)
+        );
+        assert!(!debugger.cpu.is_halted());
+        assert_eq!(debugger.cpu.regs[0].0, 1);
+        assert_eq!(debugger.cpu.regs[1].0, 0);
+        assert_eq!(debugger.cpu.cycle_counter, 1);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::emulator::constants::CONSOLEIO_SIZE;
+use crate::emulator::fault::Fault;
 use crate::emulator::memory::*;
 
 #[derive(Default)]
@@ -6,42 +8,43 @@ pub struct ConsoleIO {
 }
 
 impl Memory for ConsoleIO {
-    fn read(&self, _addr: u32) -> u8 {
-        unimplemented!();
+    fn read(&self, addr: u32) -> Result<u8> {
+        Err(Fault::UnsupportedAccess(addr))
     }
 
-    fn read_doubleword(&self, _addr: u32) -> u32 {
-        unimplemented!();
+    fn read_doubleword(&self, addr: u32) -> Result<u32> {
+        Err(Fault::UnsupportedAccess(addr))
     }
 
-    fn read_all(&self, addr: u32, number: u32) -> Vec<u8> {
-        check_alignment(addr, 4);
+    fn read_all(&self, addr: u32, number: u32) -> Result<Vec<u8>> {
+        check_alignment(addr, 4)?;
         let res = vec![0u8; number as usize];
-        res
+        Ok(res)
     }
 
-    fn write(&mut self, addr: u32, value: u8) {
+    fn write(&mut self, addr: u32, value: u8) -> Result<()> {
         match addr - self.offset {
             0 => print!("{}", value as char),
-            _ => panic!("ConsoleIO: Invalid memory access at offset 0x{:X}", addr),
+            _ => return Err(Fault::InvalidAddress(addr)),
         }
+        Ok(())
     }
 
-    fn write_doubleword(&mut self, addr: u32, value: u32) {
+    fn write_doubleword(&mut self, addr: u32, value: u32) -> Result<()> {
         let byte = ((value & 0xFF_00_00_00) >> 24) as u8;
-        self.write(addr - self.offset, byte);
+        self.write(addr, byte)
     }
 
-    fn write_all(&mut self, _bytes: &[u8], _offset: u32) {
-        unimplemented!();
+    fn write_all(&mut self, _bytes: &[u8], addr: u32) -> Result<()> {
+        Err(Fault::UnsupportedAccess(addr))
     }
 
-    fn read_instruction(&self, _addr: u32) -> &[u8] {
-        unimplemented!()
+    fn read_instruction(&self, addr: u32) -> Result<&[u8]> {
+        Err(Fault::UnsupportedAccess(addr))
     }
 
     fn size(&self) -> u32 {
-        8
+        CONSOLEIO_SIZE
     }
 }
 
@@ -1,6 +1,5 @@
-use crate::emulator::memory::{
-    address_to_index, check_alignment, read_doubleword, write_doubleword, Memory,
-};
+use crate::emulator::fault::Fault;
+use crate::emulator::memory::{address_to_index, check_alignment, Memory, Result};
 
 pub struct MainMemory {
     data: Vec<u8>,
@@ -8,41 +7,51 @@ pub struct MainMemory {
 }
 
 impl Memory for MainMemory {
-    fn read(&self, addr: u32) -> u8 {
-        self.data[address_to_index(addr - self.offset)]
+    #[inline]
+    fn read(&self, addr: u32) -> Result<u8> {
+        Ok(self.data[self.index(addr, 1)?])
     }
 
-    fn read_doubleword(&self, addr: u32) -> u32 {
-        read_doubleword(|byte_addr| self.read(byte_addr), addr)
+    #[inline]
+    fn read_doubleword(&self, addr: u32) -> Result<u32> {
+        check_alignment(addr, 4)?;
+        let index = self.index(addr, 4)?;
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.data[index..index + 4]);
+        Ok(u32::from_be_bytes(bytes))
     }
 
-    fn read_all(&self, addr: u32, number: u32) -> Vec<u8> {
-        check_alignment(addr, 4);
-        let index = address_to_index(addr - self.offset);
-        let mut res = vec![0u8; number as usize];
-        res.copy_from_slice(&self.data[index..index + 8]);
-        res
+    fn read_all(&self, addr: u32, number: u32) -> Result<Vec<u8>> {
+        check_alignment(addr, 4)?;
+        let index = self.index(addr, number)?;
+        Ok(self.data[index..index + number as usize].to_vec())
     }
 
-    fn write(&mut self, addr: u32, value: u8) {
-        let index = address_to_index(addr - self.offset);
+    #[inline]
+    fn write(&mut self, addr: u32, value: u8) -> Result<()> {
+        let index = self.index(addr, 1)?;
         self.data[index] = value;
+        Ok(())
     }
 
-    fn write_doubleword(&mut self, addr: u32, value: u32) {
-        // No offset needed, because write() applies it as well
-        write_doubleword(|byte_addr, byte| self.write(byte_addr, byte), addr, value);
+    #[inline]
+    fn write_doubleword(&mut self, addr: u32, value: u32) -> Result<()> {
+        check_alignment(addr, 4)?;
+        let index = self.index(addr, 4)?;
+        self.data[index..index + 4].copy_from_slice(&value.to_be_bytes());
+        Ok(())
     }
 
-    fn write_all(&mut self, bytes: &[u8], addr: u32) {
-        let index = address_to_index(addr - self.offset);
+    fn write_all(&mut self, bytes: &[u8], addr: u32) -> Result<()> {
+        let index = self.index(addr, bytes.len() as u32)?;
         self.data[index..index + bytes.len()].copy_from_slice(bytes);
+        Ok(())
     }
 
-    fn read_instruction(&self, addr: u32) -> &[u8] {
-        check_alignment(addr, 8);
-        let index = address_to_index(addr - self.offset);
-        &self.data[index..index + 8]
+    fn read_instruction(&self, addr: u32) -> Result<&[u8]> {
+        check_alignment(addr, 8)?;
+        let index = self.index(addr, 8)?;
+        Ok(&self.data[index..index + 8])
     }
 
     fn size(&self) -> u32 {
@@ -57,6 +66,21 @@ impl MainMemory {
             data: vec![0; size as usize],
         }
     }
+
+    // Index of addr into data, checking that number bytes starting at addr
+    // lie within this memory.
+    #[inline]
+    fn index(&self, addr: u32, number: u32) -> Result<usize> {
+        let relative = addr
+            .checked_sub(self.offset)
+            .ok_or(Fault::InvalidAddress(addr))?;
+        let index = address_to_index(relative);
+
+        if index + number as usize > self.data.len() {
+            return Err(Fault::InvalidAddress(addr));
+        }
+        Ok(index)
+    }
 }
 
 #[cfg(test)]
@@ -66,14 +90,14 @@ mod tests {
     #[test]
     fn test_write() {
         let mut mem = MainMemory::new(0, 8);
-        mem.write(7, 10);
+        mem.write(7, 10).unwrap();
         assert_eq!(mem.data, [0, 0, 0, 0, 0, 0, 0, 10]);
     }
 
     #[test]
     fn test_write_doubleword() {
         let mut mem = MainMemory::new(0, 8);
-        mem.write_doubleword(4, 0xAABBCCDD);
+        mem.write_doubleword(4, 0xAABBCCDD).unwrap();
         assert_eq!(mem.data, [0, 0, 0, 0, 0xAA, 0xBB, 0xCC, 0xDD]);
     }
 
@@ -83,7 +107,7 @@ mod tests {
             offset: 0,
             data: vec![0, 0, 10, 20, 0, 0, 0, 0],
         };
-        assert_eq!(mem.read_all(0, 8), vec![0, 0, 10, 20, 0, 0, 0, 0]);
+        assert_eq!(mem.read_all(0, 8).unwrap(), vec![0, 0, 10, 20, 0, 0, 0, 0]);
     }
 
     #[test]
@@ -92,10 +116,10 @@ mod tests {
             offset: 0,
             data: vec![0, 0, 0, 0, 0xAA, 0xBB, 0xCC, 0xDD],
         };
-        assert_eq!(mem.read(4), 0xAA);
-        assert_eq!(mem.read(5), 0xBB);
-        assert_eq!(mem.read(6), 0xCC);
-        assert_eq!(mem.read(7), 0xDD);
+        assert_eq!(mem.read(4).unwrap(), 0xAA);
+        assert_eq!(mem.read(5).unwrap(), 0xBB);
+        assert_eq!(mem.read(6).unwrap(), 0xCC);
+        assert_eq!(mem.read(7).unwrap(), 0xDD);
     }
 
     #[test]
@@ -104,6 +128,14 @@ mod tests {
             offset: 0,
             data: vec![0, 0, 0, 0, 0xAA, 0xBB, 0xCC, 0xDD],
         };
-        assert_eq!(mem.read_doubleword(4), 0xAABBCCDD);
+        assert_eq!(mem.read_doubleword(4).unwrap(), 0xAABBCCDD);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut mem = MainMemory::new(8, 8);
+        assert_eq!(mem.read(7), Err(Fault::InvalidAddress(7)));
+        assert_eq!(mem.write(16, 0), Err(Fault::InvalidAddress(16)));
+        assert_eq!(mem.read_doubleword(6), Err(Fault::UnalignedAccess(6)));
     }
 }
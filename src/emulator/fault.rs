@@ -0,0 +1,29 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    InvalidInstruction(u8),
+    UnalignedAccess(u32),
+    InvalidAddress(u32),
+    UnsupportedAccess(u32),
+    DivisionByZero,
+    // SP before the push or pop that would leave the stack bounds
+    StackOverflow(u32),
+    StackUnderflow(u32),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::InvalidInstruction(opcode) => write!(f, "Invalid instruction 0x{:X}", opcode),
+            Fault::UnalignedAccess(addr) => write!(f, "Unaligned memory access at 0x{:X}", addr),
+            Fault::InvalidAddress(addr) => write!(f, "Invalid memory access at 0x{:X}", addr),
+            Fault::UnsupportedAccess(addr) => {
+                write!(f, "Unsupported device access at 0x{:X}", addr)
+            }
+            Fault::DivisionByZero => write!(f, "Division by zero"),
+            Fault::StackOverflow(sp) => write!(f, "Stack overflow at SP=0x{:X}", sp),
+            Fault::StackUnderflow(sp) => write!(f, "Stack underflow at SP=0x{:X}", sp),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::num::Wrapping;
+
+use crate::emulator::cpu::Register;
+
+// State of the CPU before an instruction was executed, together with the
+// previous contents of all main memory bytes the instruction overwrote.
+#[derive(Debug, Clone)]
+pub struct UndoRecord {
+    pub regs: [Wrapping<u32>; 19],
+    pub halted: bool,
+    pub cycle_counter: u64,
+    pub writes: Vec<(u32, u8)>,
+}
+
+impl UndoRecord {
+    pub fn pc(&self) -> u32 {
+        self.regs[Register::PC as usize].0
+    }
+
+    pub fn wrote_to(&self, addr: u32) -> bool {
+        self.writes.iter().any(|&(byte_addr, _)| byte_addr == addr)
+    }
+}
+
+// Bounded undo log. Once the capacity is reached, the oldest records are
+// dropped, so only the most recent instructions can be reverted.
+pub struct History {
+    records: VecDeque<UndoRecord>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            records: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, record: UndoRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn pop(&mut self) -> Option<UndoRecord> {
+        self.records.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(pc: u32) -> UndoRecord {
+        let mut regs = [Wrapping(0u32); 19];
+        regs[Register::PC as usize] = Wrapping(pc);
+        UndoRecord {
+            regs,
+            halted: false,
+            cycle_counter: 0,
+            writes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_pop_returns_latest() {
+        let mut history = History::new(4);
+        history.push(record(0));
+        history.push(record(8));
+        assert_eq!(history.pop().unwrap().pc(), 8);
+        assert_eq!(history.pop().unwrap().pc(), 0);
+        assert!(history.pop().is_none());
+    }
+
+    #[test]
+    fn test_capacity_drops_oldest() {
+        let mut history = History::new(2);
+        history.push(record(0));
+        history.push(record(8));
+        history.push(record(16));
+        assert_eq!(history.pop().unwrap().pc(), 16);
+        assert_eq!(history.pop().unwrap().pc(), 8);
+        assert!(history.pop().is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::common::encoding::DecodedInstruction;
+
+pub const PAGE_SIZE: u32 = 4096;
+const ENTRIES_PER_PAGE: usize = (PAGE_SIZE / 8) as usize;
+
+// Cache of decoded instructions for a contiguous memory region, keyed by the
+// instruction address. Pages that contain cached instructions are marked as
+// code pages; any write to a code page drops all cached entries of that page,
+// so self-modifying code is decoded again. Every page counts how often that
+// happened, which tells users of decoded code which pages went stale.
+pub struct InstructionCache {
+    start: u32,
+    entries: Vec<Option<DecodedInstruction>>,
+    code_pages: Vec<bool>,
+    generations: Vec<u64>,
+}
+
+impl InstructionCache {
+    pub fn new(start: u32, size: u32) -> Self {
+        InstructionCache {
+            start,
+            entries: vec![None; (size / 8) as usize],
+            code_pages: vec![false; size.div_ceil(PAGE_SIZE) as usize],
+            generations: vec![0; size.div_ceil(PAGE_SIZE) as usize],
+        }
+    }
+
+    // Number of times code on the page containing addr was invalidated
+    pub fn generation(&self, addr: u32) -> u64 {
+        let page = (addr.wrapping_sub(self.start) / PAGE_SIZE) as usize;
+        self.generations.get(page).copied().unwrap_or(0)
+    }
+
+    #[inline]
+    pub fn get(&self, addr: u32) -> Option<DecodedInstruction> {
+        if !addr.is_multiple_of(8) {
+            return None;
+        }
+
+        let index = (addr.wrapping_sub(self.start) / 8) as usize;
+        self.entries.get(index).and_then(|entry| *entry)
+    }
+
+    pub fn insert(&mut self, addr: u32, instruction: DecodedInstruction) {
+        if !addr.is_multiple_of(8) {
+            return;
+        }
+
+        let relative = addr.wrapping_sub(self.start);
+        let index = (relative / 8) as usize;
+        if let Some(entry) = self.entries.get_mut(index) {
+            *entry = Some(instruction);
+            self.code_pages[(relative / PAGE_SIZE) as usize] = true;
+        }
+    }
+
+    // Returns true if the write hit a code page.
+    pub fn invalidate(&mut self, addr: u32, number: u32) -> bool {
+        if number == 0 {
+            return false;
+        }
+
+        let mut hit = false;
+
+        let first = addr.wrapping_sub(self.start) / PAGE_SIZE;
+        let last = addr.wrapping_sub(self.start).saturating_add(number - 1) / PAGE_SIZE;
+
+        for page in first..=last {
+            let page = page as usize;
+            if page >= self.code_pages.len() {
+                break;
+            }
+
+            if self.code_pages[page] {
+                hit = true;
+                self.code_pages[page] = false;
+                self.generations[page] += 1;
+                let begin = page * ENTRIES_PER_PAGE;
+                let end = (begin + ENTRIES_PER_PAGE).min(self.entries.len());
+                for entry in &mut self.entries[begin..end] {
+                    *entry = None;
+                }
+            }
+        }
+
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::generated::instruction::Instruction;
+
+    fn instruction() -> DecodedInstruction {
+        DecodedInstruction::new(Instruction::Increment, 1, 0, 0, 0)
+    }
+
+    #[test]
+    fn test_get_inserted() {
+        let mut cache = InstructionCache::new(0x1000, 0x2000);
+        cache.insert(0x1008, instruction());
+        assert_eq!(cache.get(0x1008), Some(instruction()));
+        assert_eq!(cache.get(0x1000), None);
+        assert_eq!(cache.get(0x100C), None);
+        assert_eq!(cache.get(0x8), None);
+    }
+
+    #[test]
+    fn test_write_invalidates_page() {
+        let mut cache = InstructionCache::new(0x1000, 0x2000);
+        cache.insert(0x1008, instruction());
+        cache.insert(0x2000, instruction());
+
+        assert!(cache.invalidate(0x1FFC, 4));
+        assert_eq!(cache.get(0x1008), None);
+        assert_eq!(cache.get(0x2000), Some(instruction()));
+        assert_eq!(cache.generation(0x1008), 1);
+        assert_eq!(cache.generation(0x2000), 0);
+
+        assert!(cache.invalidate(0x1FFC, 8));
+        assert_eq!(cache.get(0x2000), None);
+        assert!(!cache.invalidate(0x1FFC, 8));
+    }
+}
@@ -1,33 +1,132 @@
 extern crate mycpu;
 
 use std::env;
+use std::io;
+use std::process;
 use std::time::SystemTime;
 
-use mycpu::assembler::codegen::assemble_file;
-use mycpu::emulator::cpu::CPU;
-use mycpu::emulator::memory::{AddressSpace, Memory};
+use mycpu::assembler::parser::parse_numeric_literal;
+use mycpu::common::debug::DebugInfo;
+use mycpu::common::image::StackBounds;
+use mycpu::common::loader::load;
+use mycpu::emulator::block::BlockEngine;
+use mycpu::emulator::cpu::{Register, StepResult, CPU};
+use mycpu::emulator::debugger::{Debugger, DEFAULT_HISTORY_CAPACITY};
+use mycpu::emulator::memory::AddressSpace;
+
+// Label and source line of an address, separated by a space, if known
+fn source_location(debug_info: &DebugInfo, addr: u32) -> String {
+    let location = debug_info.describe(addr);
+    if location.is_empty() {
+        location
+    } else {
+        format!(" {}", location)
+    }
+}
+
+// Parses LOW:HIGH as given to --stack=
+fn parse_stack_bounds(bounds: &str) -> Option<StackBounds> {
+    let (low, high) = bounds.split_once(':')?;
+    let low = parse_numeric_literal(low)?;
+    let high = parse_numeric_literal(high)?;
+    if low > high {
+        return None;
+    }
+    Some(StackBounds { low, high })
+}
 
 fn main() {
     let args = env::args().collect::<Vec<String>>();
-    let bytes = assemble_file(&args[1]).unwrap();
+    let debug = args.iter().any(|arg| arg == "--debug");
+    let block_engine = args.iter().any(|arg| arg == "--engine=block");
+    let path = args
+        .iter()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap();
+    let image = match load(path) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
     let mut memory = AddressSpace::default();
 
-    memory.write_all(bytes.as_slice(), 0x10_0000);
+    if let Err(fault) = memory.load_image(&image) {
+        eprintln!("{}: Can not load image: {}", path, fault);
+        process::exit(1);
+    }
+
+    // Bounds given on the command line override those of the executable
+    let stack = match args.iter().find_map(|arg| arg.strip_prefix("--stack=")) {
+        Some(bounds) => match parse_stack_bounds(bounds) {
+            Some(stack) => Some(stack),
+            None => {
+                eprintln!("Invalid stack bounds '{}', expected LOW:HIGH", bounds);
+                process::exit(1);
+            }
+        },
+        None => image.stack,
+    };
 
     let mut cpu = CPU::new(memory);
+    cpu.set_entry_point(image.entry);
+    cpu.set_stack_bounds(stack);
+
+    // Images without debug information only show raw addresses
+    let debug_info = image
+        .debug
+        .as_ref()
+        .and_then(|bytes| DebugInfo::from_bytes(bytes).ok())
+        .unwrap_or_default();
+
+    if debug {
+        let mut debugger = Debugger::new(cpu, DEFAULT_HISTORY_CAPACITY);
+        debugger.set_debug_info(debug_info);
+        let stdin = io::stdin();
+        debugger
+            .run_interactive(&mut stdin.lock(), &mut io::stdout())
+            .unwrap();
+        eprintln!("{}", debugger.cpu.state());
+        return;
+    }
+
     let before = SystemTime::now();
-    cpu.run();
+    let result = if block_engine {
+        BlockEngine::new().run(&mut cpu)
+    } else {
+        cpu.run()
+    };
     let after = SystemTime::now();
-    cpu.print_state();
 
-    let elapsed = after.duration_since(before).unwrap().as_secs();
+    let pc = cpu.get_register(Register::PC);
+    let location = source_location(&debug_info, pc);
+    match result {
+        StepResult::Fault(fault) => {
+            eprintln!("CPU fault at PC=0x{:X}{}: {}", pc, location, fault);
+            eprintln!("Backtrace:");
+            for (index, addr) in std::iter::once(pc).chain(cpu.backtrace()).enumerate() {
+                eprintln!(
+                    "  #{} 0x{:X}{}",
+                    index,
+                    addr,
+                    source_location(&debug_info, addr)
+                );
+            }
+        }
+        _ => eprintln!("Halting CPU at PC=0x{:X}{}", pc, location),
+    }
+    eprintln!("{}", cpu.state());
+
+    let elapsed = after.duration_since(before).unwrap().as_secs_f64();
     eprintln!(
         "Executed {} instructions in {:?} seconds",
         cpu.cycle_counter, elapsed
     );
     eprintln!(
         "Frequency: {} MHz",
-        cpu.cycle_counter as f64 / elapsed as f64 / 1000.0 / 1000.0
+        cpu.cycle_counter as f64 / elapsed / 1000.0 / 1000.0
     );
 }
@@ -1,96 +1,108 @@
-use crate::common::util;
+use crate::common::encoding::DecodedInstruction;
+use crate::common::image::Image;
 use crate::emulator::constants::*;
 use crate::emulator::device::consoleio::ConsoleIO;
 use crate::emulator::device::mainmemory::MainMemory;
+use crate::emulator::fault::Fault;
+use crate::emulator::icache::InstructionCache;
+
+pub type Result<T> = std::result::Result<T, Fault>;
 
 pub trait Memory {
-    fn read(&self, addr: u32) -> u8;
-    fn read_doubleword(&self, addr: u32) -> u32;
-    fn read_all(&self, addr: u32, number: u32) -> Vec<u8>;
-    fn write(&mut self, addr: u32, value: u8);
-    fn write_doubleword(&mut self, addr: u32, value: u32);
-    fn write_all(&mut self, bytes: &[u8], offset: u32);
+    fn read(&self, addr: u32) -> Result<u8>;
+    fn read_doubleword(&self, addr: u32) -> Result<u32>;
+    fn read_all(&self, addr: u32, number: u32) -> Result<Vec<u8>>;
+    fn write(&mut self, addr: u32, value: u8) -> Result<()>;
+    fn write_doubleword(&mut self, addr: u32, value: u32) -> Result<()>;
+    fn write_all(&mut self, bytes: &[u8], offset: u32) -> Result<()>;
 
-    fn read_instruction(&self, addr: u32) -> &[u8];
+    fn read_instruction(&self, addr: u32) -> Result<&[u8]>;
 
     fn size(&self) -> u32;
 }
 
-pub fn check_alignment(addr: u32, align: u32) {
-    if addr % align != 0 {
-        panic!("Unaligned memory access at {:x}", addr);
+pub fn check_alignment(addr: u32, align: u32) -> Result<()> {
+    if !addr.is_multiple_of(align) {
+        return Err(Fault::UnalignedAccess(addr));
     }
+    Ok(())
 }
 
 pub fn address_to_index(addr: u32) -> usize {
-    (addr as usize)
-}
-
-pub fn read_doubleword<F>(read_func: F, addr: u32) -> u32
-where
-    F: Fn(u32) -> u8,
-{
-    check_alignment(addr, 4);
-    util::bytes_to_u32(
-        read_func(addr),
-        read_func(addr + 1),
-        read_func(addr + 2),
-        read_func(addr + 3),
-    )
+    addr as usize
 }
 
-pub fn write_doubleword<F>(mut write_func: F, addr: u32, value: u32)
-where
-    F: FnMut(u32, u8),
-{
-    check_alignment(addr, 4);
-
-    let bytes = util::u32_to_bytes(value);
-
-    write_func(addr, bytes[0]);
-    write_func(addr + 1, bytes[1]);
-    write_func(addr + 2, bytes[2]);
-    write_func(addr + 3, bytes[3]);
+// A memory mapped device occupying the addresses start..=end.
+struct MappedDevice {
+    start: u32,
+    end: u32,
+    device: Box<dyn Memory>,
 }
 
+// The address space of the CPU. Accesses to main memory are dispatched
+// statically and take a direct path into the RAM buffer; only accesses
+// outside of main memory are looked up in the table of memory mapped
+// devices.
 pub struct AddressSpace {
     memory: MainMemory,
-    console: ConsoleIO,
+    devices: Vec<MappedDevice>,
+    journal: Option<Vec<(u32, u8)>>,
+    icache: InstructionCache,
+    code_generation: u64,
 }
 
 impl Memory for AddressSpace {
-    fn read(&self, addr: u32) -> u8 {
-        let device = self.device_for_address(addr);
-        device.read(addr)
+    fn read(&self, addr: u32) -> Result<u8> {
+        if is_main_memory(addr) {
+            return self.memory.read(addr);
+        }
+        self.device_for_address(addr)?.read(addr)
     }
 
-    fn read_doubleword(&self, addr: u32) -> u32 {
-        let device = self.device_for_address(addr);
-        device.read_doubleword(addr)
+    fn read_doubleword(&self, addr: u32) -> Result<u32> {
+        if is_main_memory(addr) {
+            return self.memory.read_doubleword(addr);
+        }
+        self.device_for_address(addr)?.read_doubleword(addr)
     }
 
-    fn read_all(&self, addr: u32, number: u32) -> Vec<u8> {
-        let device = self.device_for_address(addr);
-        device.read_all(addr, number)
+    fn read_all(&self, addr: u32, number: u32) -> Result<Vec<u8>> {
+        if is_main_memory(addr) {
+            return self.memory.read_all(addr, number);
+        }
+        self.device_for_address(addr)?.read_all(addr, number)
     }
 
-    fn write(&mut self, addr: u32, value: u8) {
-        let device = self.device_for_address_mut(addr);
-        device.write(addr, value);
+    fn write(&mut self, addr: u32, value: u8) -> Result<()> {
+        if is_main_memory(addr) {
+            self.before_write(addr, 1);
+            return self.memory.write(addr, value);
+        }
+        self.device_for_address_mut(addr)?.write(addr, value)
     }
 
-    fn write_doubleword(&mut self, addr: u32, value: u32) {
-        let device = self.device_for_address_mut(addr);
-        device.write_doubleword(addr, value);
+    fn write_doubleword(&mut self, addr: u32, value: u32) -> Result<()> {
+        if is_main_memory(addr) {
+            self.before_write(addr, 4);
+            return self.memory.write_doubleword(addr, value);
+        }
+        self.device_for_address_mut(addr)?
+            .write_doubleword(addr, value)
     }
 
-    fn write_all(&mut self, bytes: &[u8], addr: u32) {
-        let device = self.device_for_address_mut(addr);
-        device.write_all(bytes, addr);
+    fn write_all(&mut self, bytes: &[u8], addr: u32) -> Result<()> {
+        if is_main_memory(addr) {
+            self.before_write(addr, bytes.len() as u32);
+            return self.memory.write_all(bytes, addr);
+        }
+        self.device_for_address_mut(addr)?.write_all(bytes, addr)
     }
 
-    fn read_instruction(&self, addr: u32) -> &[u8] {
-        self.memory.read_instruction(addr)
+    fn read_instruction(&self, addr: u32) -> Result<&[u8]> {
+        if is_main_memory(addr) {
+            return self.memory.read_instruction(addr);
+        }
+        self.device_for_address(addr)?.read_instruction(addr)
     }
 
     fn size(&self) -> u32 {
@@ -101,27 +113,241 @@ impl Memory for AddressSpace {
 
 impl Default for AddressSpace {
     fn default() -> Self {
-        AddressSpace {
+        let mut address_space = AddressSpace {
             memory: MainMemory::new(MEMORY_START, MEMORY_SIZE),
-            console: ConsoleIO::new(CONSOLEIO_START),
-        }
+            devices: Vec::new(),
+            journal: None,
+            icache: InstructionCache::new(MEMORY_START, MEMORY_SIZE),
+            code_generation: 0,
+        };
+
+        address_space
+            .map_device(CONSOLEIO_START, Box::new(ConsoleIO::new(CONSOLEIO_START)))
+            .unwrap();
+        address_space
     }
 }
 
+#[inline]
+fn is_main_memory(addr: u32) -> bool {
+    (MEMORY_START..=MEMORY_END).contains(&addr)
+}
+
 impl AddressSpace {
-    fn device_for_address(&self, addr: u32) -> &Memory {
-        match addr {
-            MEMORY_START...MEMORY_END => &self.memory,
-            CONSOLEIO_START...CONSOLEIO_END => &self.console,
-            _ => panic!("Invalid memory access add 0x{:X}", addr),
+    // Maps a device at the given address. The device receives absolute
+    // addresses and occupies device.size() bytes, which must be at least one
+    // and fit below the end of the address space.
+    pub fn map_device(&mut self, start: u32, device: Box<dyn Memory>) -> Result<()> {
+        let end = device
+            .size()
+            .checked_sub(1)
+            .and_then(|last| start.checked_add(last))
+            .ok_or(Fault::InvalidAddress(start))?;
+        self.devices.push(MappedDevice { start, end, device });
+        Ok(())
+    }
+
+    // Writes all segments of an image, zero-filling the part of each
+    // segment that is not stored in the image.
+    pub fn load_image(&mut self, image: &Image) -> Result<()> {
+        for segment in &image.segments {
+            let mut bytes = segment.data.clone();
+            bytes.resize(segment.size as usize, 0);
+            self.write_all(&bytes, segment.address)?;
+        }
+        Ok(())
+    }
+
+    // While journaling, the previous contents of every main memory byte that
+    // gets written are recorded, so that the writes can be undone later.
+    // Writes to devices have side effects and are not recorded.
+    pub fn set_journaling(&mut self, enabled: bool) {
+        self.journal = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn take_journal(&mut self) -> Vec<(u32, u8)> {
+        match &mut self.journal {
+            Some(journal) => std::mem::take(journal),
+            None => Vec::new(),
         }
     }
 
-    fn device_for_address_mut(&mut self, addr: u32) -> &mut Memory {
-        match addr {
-            MEMORY_START...MEMORY_END => &mut self.memory,
-            CONSOLEIO_START...CONSOLEIO_END => &mut self.console,
-            _ => panic!("Invalid memory access add 0x{:X}", addr),
+    pub fn undo_writes(&mut self, writes: &[(u32, u8)]) {
+        for &(addr, value) in writes.iter().rev() {
+            // Journaled addresses are always valid main memory addresses
+            let _ = self.memory.write(addr, value);
+            self.invalidate_code(addr, 1);
         }
     }
+
+    // Incremented whenever a write modifies memory that instructions have
+    // been fetched from, so that translated code can be discarded.
+    pub fn code_generation(&self) -> u64 {
+        self.code_generation
+    }
+
+    // Like code_generation, but only counts writes to the code page that
+    // contains addr
+    pub fn code_page_generation(&self, addr: u32) -> u64 {
+        self.icache.generation(addr)
+    }
+
+    #[inline]
+    fn before_write(&mut self, addr: u32, number: u32) {
+        self.record(addr, number);
+        self.invalidate_code(addr, number);
+    }
+
+    fn invalidate_code(&mut self, addr: u32, number: u32) {
+        if self.icache.invalidate(addr, number) {
+            self.code_generation += 1;
+        }
+    }
+
+    // Fetches and decodes the instruction at addr, using the decoded
+    // instruction cache for instructions in main memory.
+    #[inline]
+    pub fn fetch_instruction(&mut self, addr: u32) -> Result<DecodedInstruction> {
+        if let Some(instruction) = self.icache.get(addr) {
+            return Ok(instruction);
+        }
+
+        let instruction = DecodedInstruction::decode(self.read_instruction(addr)?);
+        self.icache.insert(addr, instruction);
+        Ok(instruction)
+    }
+
+    fn record(&mut self, addr: u32, number: u32) {
+        if let Some(journal) = &mut self.journal {
+            for byte_addr in addr..addr.saturating_add(number) {
+                if let Ok(value) = self.memory.read(byte_addr) {
+                    journal.push((byte_addr, value));
+                }
+            }
+        }
+    }
+
+    fn device_for_address(&self, addr: u32) -> Result<&dyn Memory> {
+        self.devices
+            .iter()
+            .find(|mapped| (mapped.start..=mapped.end).contains(&addr))
+            .map(|mapped| mapped.device.as_ref())
+            .ok_or(Fault::InvalidAddress(addr))
+    }
+
+    fn device_for_address_mut(&mut self, addr: u32) -> Result<&mut dyn Memory> {
+        match self
+            .devices
+            .iter_mut()
+            .find(|mapped| (mapped.start..=mapped.end).contains(&addr))
+        {
+            Some(mapped) => Ok(mapped.device.as_mut()),
+            None => Err(Fault::InvalidAddress(addr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::image::{Segment, READ, WRITE};
+
+    #[test]
+    fn test_main_memory_doubleword() {
+        let mut memory = AddressSpace::default();
+        memory
+            .write_doubleword(MEMORY_START + 4, 0xAABBCCDD)
+            .unwrap();
+        assert_eq!(memory.read_doubleword(MEMORY_START + 4), Ok(0xAABBCCDD));
+        assert_eq!(memory.read(MEMORY_START + 4), Ok(0xAA));
+        assert_eq!(memory.read(MEMORY_START + 7), Ok(0xDD));
+    }
+
+    #[test]
+    fn test_main_memory_end() {
+        let mut memory = AddressSpace::default();
+        assert_eq!(memory.write_doubleword(MEMORY_END - 3, 1), Ok(()));
+        assert_eq!(
+            memory.read_doubleword(MEMORY_END + 1),
+            Err(Fault::InvalidAddress(MEMORY_END + 1))
+        );
+        assert_eq!(
+            memory.read_doubleword(MEMORY_START + 2),
+            Err(Fault::UnalignedAccess(MEMORY_START + 2))
+        );
+    }
+
+    #[test]
+    fn test_memory_map() {
+        // The regions the assembler knows about are the ones that are mapped
+        let memory = AddressSpace::default();
+        let mut mapped = vec![(MEMORY_START, memory.memory.size())];
+        mapped.extend(
+            memory
+                .devices
+                .iter()
+                .map(|mapped| (mapped.start, mapped.end - mapped.start + 1)),
+        );
+        let regions: Vec<(u32, u32)> = MEMORY_MAP
+            .iter()
+            .map(|region| (region.start, region.size))
+            .collect();
+        assert_eq!(mapped, regions);
+    }
+
+    #[test]
+    fn test_load_image() {
+        let image = Image {
+            segments: vec![
+                Segment {
+                    name: ".text".into(),
+                    address: MEMORY_START,
+                    data: vec![1, 2, 3, 4],
+                    size: 4,
+                    permissions: READ,
+                },
+                Segment {
+                    name: ".bss".into(),
+                    address: MEMORY_START + 8,
+                    data: Vec::new(),
+                    size: 8,
+                    permissions: READ | WRITE,
+                },
+            ],
+            ..Image::default()
+        };
+
+        let mut memory = AddressSpace::default();
+        memory
+            .write_doubleword(MEMORY_START + 12, 0xFFFF_FFFF)
+            .unwrap();
+        memory.load_image(&image).unwrap();
+        assert_eq!(memory.read_doubleword(MEMORY_START), Ok(0x0102_0304));
+        assert_eq!(memory.read_doubleword(MEMORY_START + 12), Ok(0));
+    }
+
+    #[test]
+    fn test_mapped_device() {
+        let mut memory = AddressSpace::default();
+        memory
+            .map_device(0x1000, Box::new(MainMemory::new(0x1000, 16)))
+            .unwrap();
+
+        memory.write_doubleword(0x100C, 0x12345678).unwrap();
+        assert_eq!(memory.read_doubleword(0x100C), Ok(0x12345678));
+        assert_eq!(memory.read(0x1010), Err(Fault::InvalidAddress(0x1010)));
+        assert_eq!(
+            memory.read(CONSOLEIO_START),
+            Err(Fault::UnsupportedAccess(CONSOLEIO_START))
+        );
+
+        assert_eq!(
+            memory.map_device(0x2000, Box::new(MainMemory::new(0x2000, 0))),
+            Err(Fault::InvalidAddress(0x2000))
+        );
+        assert_eq!(
+            memory.map_device(0xFFFF_FFF8, Box::new(MainMemory::new(0xFFFF_FFF8, 16))),
+            Err(Fault::InvalidAddress(0xFFFF_FFF8))
+        );
+    }
 }
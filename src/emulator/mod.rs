@@ -1,4 +1,9 @@
+pub mod block;
 pub mod constants;
 pub mod cpu;
+pub mod debugger;
 pub mod device;
+pub mod fault;
+pub mod history;
+pub mod icache;
 pub mod memory;
@@ -0,0 +1,66 @@
+extern crate mycpu;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+use mycpu::assembler::format::format_source;
+
+const USAGE: &str = "Usage: asmfmt [--check] [FILE]...\n\
+    \x20 Formats the files in place, or stdin to stdout if no files are given.\n\
+    \x20 With --check, lists the files that are not formatted and fails if there are any.";
+
+fn with_path(path: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+// Returns whether all files were formatted already
+fn run(files: &[&String], check: bool) -> io::Result<bool> {
+    if files.is_empty() {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        let formatted = format_source(&text);
+        if !check {
+            io::stdout().write_all(formatted.as_bytes())?;
+        }
+        return Ok(formatted == text);
+    }
+
+    let mut unchanged = true;
+    for path in files {
+        let text = fs::read_to_string(path).map_err(|e| with_path(path, e))?;
+        let formatted = format_source(&text);
+        if formatted == text {
+            continue;
+        }
+
+        unchanged = false;
+        if check {
+            println!("{}", path);
+        } else {
+            fs::write(path, formatted).map_err(|e| with_path(path, e))?;
+        }
+    }
+    Ok(unchanged)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+
+    if files.iter().any(|arg| arg.starts_with('-')) {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+
+    match run(&files, check) {
+        Ok(unchanged) if check && !unchanged => process::exit(1),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
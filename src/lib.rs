@@ -1,3 +1,6 @@
+pub mod analysis;
 pub mod assembler;
 pub mod common;
 pub mod emulator;
+pub mod linker;
+pub mod lsp;
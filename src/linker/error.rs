@@ -0,0 +1,41 @@
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, LinkError>;
+
+// Errors refer to the object file they occurred in, and to the line for
+// linker scripts.
+#[derive(Debug)]
+pub enum LinkError {
+    IO(io::Error),
+    Script(usize, String),
+    UndefinedSymbol(String, String),
+    DuplicateSymbol(String, String),
+    Relocation(String, String),
+    Layout(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkError::IO(ref e) => write!(f, "IOError: {}", e),
+            LinkError::Script(line, ref s) => write!(f, "line {}: ScriptError: {}", line, s),
+            LinkError::UndefinedSymbol(ref object, ref s) => {
+                write!(f, "{}: Undefined symbol '{}'", object, s)
+            }
+            LinkError::DuplicateSymbol(ref object, ref s) => {
+                write!(f, "{}: Duplicate symbol '{}'", object, s)
+            }
+            LinkError::Relocation(ref object, ref s) => {
+                write!(f, "{}: RelocationError: {}", object, s)
+            }
+            LinkError::Layout(ref s) => write!(f, "LayoutError: {}", s),
+        }
+    }
+}
+
+impl From<io::Error> for LinkError {
+    fn from(err: io::Error) -> LinkError {
+        LinkError::IO(err)
+    }
+}
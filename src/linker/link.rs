@@ -0,0 +1,567 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::assembler::parser::Section;
+use crate::common::archive::Archive;
+use crate::common::debug::DebugInfo;
+use crate::common::image::{Image, ImageSymbol, Segment};
+use crate::common::object::{is_valid_alignment, Binding, ObjectFile};
+use crate::emulator::constants::MEMORY_START;
+use crate::linker::error::{LinkError, Result};
+use crate::linker::script::Script;
+
+// Address of every input section, keyed by object index and section
+type InputBases = HashMap<(usize, Section), u32>;
+
+struct OutputSection {
+    section: Section,
+    start: u32,
+    end: u32,
+    data: Vec<u8>,
+}
+
+pub enum Input {
+    Object(String, ObjectFile),
+    Archive(String, Archive),
+}
+
+// Returns the objects to link. Objects are always used, archive members only
+// if they define a symbol that is undefined at that point. Like traditional
+// linkers, an archive only resolves references of the inputs before it and
+// of its own members, so libraries go after the objects using them.
+pub fn select_objects(inputs: Vec<Input>) -> Vec<(String, ObjectFile)> {
+    let mut objects = Vec::new();
+    let mut defined = HashSet::new();
+    let mut undefined = BTreeSet::new();
+
+    let mut add = |path: String, object: ObjectFile, undefined: &mut BTreeSet<String>| {
+        for symbol in &object.symbols {
+            match symbol.binding {
+                Binding::Global => {
+                    defined.insert(symbol.name.clone());
+                    undefined.remove(&symbol.name);
+                }
+                Binding::Undefined if !defined.contains(&symbol.name) => {
+                    undefined.insert(symbol.name.clone());
+                }
+                _ => {}
+            }
+        }
+        objects.push((path, object));
+    };
+
+    for input in inputs {
+        match input {
+            Input::Object(path, object) => add(path, object, &mut undefined),
+            Input::Archive(path, archive) => {
+                let mut used = vec![false; archive.members.len()];
+                while let Some(index) = undefined
+                    .iter()
+                    .filter_map(|symbol| archive.find_definition(symbol))
+                    .find(|&index| !used[index])
+                {
+                    used[index] = true;
+                    let member = &archive.members[index];
+                    let name = format!("{}({})", path, member.name);
+                    add(name, member.object.clone(), &mut undefined);
+                }
+            }
+        }
+    }
+
+    objects
+}
+
+// Combines the objects into one image. Input sections with the same name are
+// concatenated in the order of the objects and placed as given by the
+// script.
+pub fn link(objects: &[(String, ObjectFile)], script: &Script) -> Result<Image> {
+    let (mut outputs, bases) = place_sections(objects, script)?;
+    let symbols = global_symbols(objects, &outputs, &bases)?;
+
+    for (index, (path, object)) in objects.iter().enumerate() {
+        for relocation in &object.relocations {
+            let error = |message: String| LinkError::Relocation(path.clone(), message);
+
+            let base = match Section::from_name(&relocation.symbol) {
+                Some(section) => bases.get(&(index, section)).cloned(),
+                None => symbols.get(&relocation.symbol).cloned(),
+            }
+            .ok_or_else(|| LinkError::UndefinedSymbol(path.clone(), relocation.symbol.clone()))?;
+
+            let value = i64::from(base) + relocation.addend;
+            if value < -(1 << 31) || value > i64::from(u32::MAX) {
+                return Err(error(format!(
+                    "Value {} of '{}' does not fit into 32 bits",
+                    value, relocation.symbol
+                )));
+            }
+
+            let section = Section::from_name(&relocation.section)
+                .ok_or_else(|| error(format!("Unknown section '{}'", relocation.section)))?;
+            // The field must lie within the initialized data of the input
+            // section, which only exists if the object has that section
+            let input = object
+                .section(&relocation.section)
+                .ok_or_else(|| error(format!("Object has no section '{}'", relocation.section)))?;
+            let in_range = relocation
+                .offset
+                .checked_add(4)
+                .is_some_and(|end| end as usize <= input.data.len());
+            if !in_range {
+                return Err(error(format!(
+                    "Offset {} is outside of section {}",
+                    relocation.offset, relocation.section
+                )));
+            }
+
+            // Sections the script does not place have no address
+            let base = bases.get(&(index, section));
+            let output = outputs.iter_mut().find(|output| output.section == section);
+            let (base, output) = match (base, output) {
+                (Some(&base), Some(output)) => (base, output),
+                _ => {
+                    return Err(error(format!(
+                        "Section {} is not placed",
+                        relocation.section
+                    )))
+                }
+            };
+            let position = (base - output.start) as usize + relocation.offset as usize;
+            output.data[position..position + 4].copy_from_slice(&(value as u32).to_be_bytes());
+        }
+    }
+
+    let segments: Vec<Segment> = outputs
+        .into_iter()
+        .filter(|output| output.end > output.start)
+        .map(|output| Segment {
+            name: output.section.name().into(),
+            address: output.start,
+            data: output.data,
+            size: output.end - output.start,
+            permissions: output.section.permissions(),
+        })
+        .collect();
+
+    let entry = match &script.entry {
+        None => symbols["TEXT_START"],
+        Some(name) => *symbols
+            .get(name)
+            .ok_or_else(|| LinkError::Layout(format!("Entry point '{}' is not defined", name)))?,
+    };
+
+    // Objects carry no line information, only the symbol ranges are known
+    let symbols = image_symbols(objects, &bases)?;
+    let mut debug = DebugInfo::default();
+    debug.add_symbol_ranges(&segments, &symbols);
+
+    Ok(Image {
+        entry,
+        segments,
+        symbols,
+        debug: Some(debug.to_bytes()),
+        stack: script.stack,
+    })
+}
+
+// Returns the output sections and the address of every input section
+fn place_sections(
+    objects: &[(String, ObjectFile)],
+    script: &Script,
+) -> Result<(Vec<OutputSection>, InputBases)> {
+    for (path, object) in objects {
+        for input in &object.sections {
+            if Section::from_name(&input.name).is_none() {
+                return Err(LinkError::Layout(format!(
+                    "{}: Unknown section '{}'",
+                    path, input.name
+                )));
+            }
+            if !is_valid_alignment(input.alignment) {
+                return Err(LinkError::Layout(format!(
+                    "{}: Invalid alignment {} of section {}",
+                    path, input.alignment, input.name
+                )));
+            }
+        }
+    }
+
+    let mut outputs: Vec<OutputSection> = Vec::new();
+    let mut bases = HashMap::new();
+    let mut previous_end = MEMORY_START;
+
+    for placement in &script.placements {
+        let section = placement.section;
+        let start = match placement.address {
+            Some(address) => address,
+            None => align_up(previous_end, 8)?,
+        };
+
+        let mut counter = start;
+        let mut data = Vec::new();
+
+        for (index, (_, object)) in objects.iter().enumerate() {
+            let input = match object.section(section.name()) {
+                Some(input) => input,
+                None => continue,
+            };
+
+            counter = align_up(counter, input.alignment)?;
+            bases.insert((index, section), counter);
+
+            // Only initialized data is stored, the rest is zero-filled when
+            // the image is loaded
+            if !input.data.is_empty() {
+                data.resize((counter - start) as usize, 0);
+                data.extend_from_slice(&input.data);
+            }
+
+            counter = counter.checked_add(input.size).ok_or_else(|| {
+                LinkError::Layout(format!(
+                    "Section {} exceeds the address space",
+                    section.name()
+                ))
+            })?;
+        }
+
+        if let Some(other) = outputs
+            .iter()
+            .filter(|o| o.end > o.start && counter > start)
+            .find(|o| o.start < counter && start < o.end)
+        {
+            return Err(LinkError::Layout(format!(
+                "Sections {} and {} overlap",
+                other.section.name(),
+                section.name()
+            )));
+        }
+
+        outputs.push(OutputSection {
+            section,
+            start,
+            end: counter,
+            data,
+        });
+        previous_end = counter;
+    }
+
+    Ok((outputs, bases))
+}
+
+// Addresses of all global symbols, and of the symbols describing the layout
+// that the assembler leaves to the linker.
+fn global_symbols(
+    objects: &[(String, ObjectFile)],
+    outputs: &[OutputSection],
+    bases: &InputBases,
+) -> Result<HashMap<String, u32>> {
+    let mut symbols = HashMap::new();
+
+    let text = outputs.iter().find(|o| o.section == Section::Text).unwrap();
+    let program_end = outputs
+        .iter()
+        .filter(|o| o.end > o.start || o.section == Section::Text)
+        .map(|o| o.end)
+        .max()
+        .unwrap_or(MEMORY_START);
+
+    symbols.insert(String::from("PROGRAM_START"), text.start);
+    symbols.insert(String::from("PROGRAM_END"), program_end);
+
+    for output in outputs {
+        let prefix = output.section.symbol_prefix();
+        symbols.insert(format!("{}_START", prefix), output.start);
+        symbols.insert(format!("{}_END", prefix), output.end);
+    }
+
+    for (index, (path, object)) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            if symbol.binding != Binding::Global {
+                continue;
+            }
+
+            let base = match &symbol.section {
+                None => 0,
+                Some(name) => Section::from_name(name)
+                    .and_then(|section| bases.get(&(index, section)))
+                    .cloned()
+                    .ok_or_else(|| {
+                        LinkError::Layout(format!("{}: Unknown section '{}'", path, name))
+                    })?,
+            };
+
+            let address = base.checked_add(symbol.value).ok_or_else(|| {
+                LinkError::Layout(format!(
+                    "{}: Symbol '{}' exceeds the address space",
+                    path, symbol.name
+                ))
+            })?;
+            if symbols.insert(symbol.name.clone(), address).is_some() {
+                return Err(LinkError::DuplicateSymbol(
+                    path.clone(),
+                    symbol.name.clone(),
+                ));
+            }
+        }
+    }
+
+    Ok(symbols)
+}
+
+// Addresses of all labels, for debugging
+fn image_symbols(objects: &[(String, ObjectFile)], bases: &InputBases) -> Result<Vec<ImageSymbol>> {
+    let mut symbols = Vec::new();
+
+    for (index, (path, object)) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            let section = symbol
+                .section
+                .as_ref()
+                .and_then(|name| Section::from_name(name));
+            if let Some(base) = section.and_then(|section| bases.get(&(index, section))) {
+                let address = base.checked_add(symbol.value).ok_or_else(|| {
+                    LinkError::Layout(format!(
+                        "{}: Symbol '{}' exceeds the address space",
+                        path, symbol.name
+                    ))
+                })?;
+                symbols.push(ImageSymbol {
+                    name: symbol.name.clone(),
+                    address,
+                });
+            }
+        }
+    }
+
+    Ok(symbols)
+}
+
+fn align_up(address: u32, alignment: u32) -> Result<u32> {
+    match address % alignment {
+        0 => Ok(address),
+        remainder => address.checked_add(alignment - remainder).ok_or_else(|| {
+            LinkError::Layout(format!("Address 0x{:08X} can not be aligned", address))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::codegen::assemble_object;
+    use crate::common::archive::Member;
+    use crate::common::encoding::DecodedInstruction;
+    use crate::common::object::Relocation;
+    use std::io::Cursor;
+
+    fn object(path: &str, source: &str) -> (String, ObjectFile) {
+        (
+            path.into(),
+            assemble_object(&mut Cursor::new(source)).unwrap(),
+        )
+    }
+
+    fn operand(image: &Image, index: usize) -> u32 {
+        let text = &image.segment(".text").unwrap().data;
+        DecodedInstruction::decode(&text[index * 8..]).operand
+    }
+
+    fn main_object() -> (String, ObjectFile) {
+        object(
+            "main.o",
+            ".extern print, counter\n\
+             .global main\n\
+             main:\n\
+             ldi sp, MEMORY_END\n\
+             ldi r0, message\n\
+             call print\n\
+             ldi r1, counter + 4\n\
+             halt\n\
+             .rodata\n\
+             message:\n\
+             .asciz \"hi\"\n",
+        )
+    }
+
+    fn library_object() -> (String, ObjectFile) {
+        object(
+            "print.o",
+            ".global print, counter\n\
+             print:\n\
+             ldi r2, counter\n\
+             ld r3, r2\n\
+             inc r3\n\
+             st r3, r2\n\
+             ret\n\
+             .data\n\
+             counter:\n\
+             .word 41, counter\n",
+        )
+    }
+
+    #[test]
+    fn test_link() {
+        let image = link(&[main_object(), library_object()], &Script::default()).unwrap();
+
+        let text = image.segment(".text").unwrap();
+        let rodata = image.segment(".rodata").unwrap();
+        let data = image.segment(".data").unwrap();
+
+        assert_eq!(image.entry, MEMORY_START);
+        assert_eq!(text.address, MEMORY_START);
+        assert_eq!(text.size, 80);
+        assert_eq!(rodata.address, MEMORY_START + 80);
+        assert_eq!(data.address, MEMORY_START + 88);
+
+        assert_eq!(operand(&image, 1), rodata.address);
+        assert_eq!(operand(&image, 2), MEMORY_START + 40);
+        assert_eq!(operand(&image, 3), data.address + 4);
+        assert_eq!(operand(&image, 5), data.address);
+        assert_eq!(data.data[4..], data.address.to_be_bytes());
+    }
+
+    #[test]
+    fn test_run_linked_program() {
+        use crate::emulator::cpu::{Register, StepResult, CPU};
+        use crate::emulator::memory::{AddressSpace, Memory};
+
+        let script = Script::parse(".text\n.data 0x180000\nENTRY main").unwrap();
+        let image = link(&[main_object(), library_object()], &script).unwrap();
+
+        let image = Image::from_bytes(&image.to_bytes()).unwrap();
+        assert!(image.symbols.iter().any(|s| s.name == "print"));
+
+        let mut memory = AddressSpace::default();
+        memory.load_image(&image).unwrap();
+        let mut cpu = CPU::new(memory);
+        cpu.set_entry_point(image.entry);
+
+        assert_eq!(cpu.run(), StepResult::Halted);
+        assert_eq!(cpu.get_register(Register::R1), 0x18_0004);
+        assert_eq!(cpu.memory.read_doubleword(0x18_0000), Ok(42));
+    }
+
+    #[test]
+    fn test_select_archive_members() {
+        let mut archive = Archive::default();
+        for (name, source) in &[
+            ("unused.o", ".global unused\nunused:\nret\n"),
+            (
+                "print.o",
+                ".extern putc\n.global print\nprint:\ncall putc\nret\n",
+            ),
+            ("putc.o", ".global putc\nputc:\nret\n"),
+        ] {
+            archive.insert(Member {
+                name: name.to_string(),
+                object: object(name, source).1,
+            });
+        }
+
+        let program = || {
+            Input::Object(
+                "main.o".into(),
+                object("main.o", ".extern print\ncall print\nhalt").1,
+            )
+        };
+
+        let objects = select_objects(vec![
+            program(),
+            Input::Archive("runtime.a".into(), archive.clone()),
+        ]);
+        let names: Vec<&str> = objects.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["main.o", "runtime.a(print.o)", "runtime.a(putc.o)"]
+        );
+
+        let image = link(&objects, &Script::default()).unwrap();
+        assert_eq!(image.segment(".text").unwrap().size, 40);
+        assert_eq!(operand(&image, 0), MEMORY_START + 16);
+        assert_eq!(operand(&image, 2), MEMORY_START + 32);
+
+        // Archives only resolve symbols of the inputs before them
+        let objects = select_objects(vec![Input::Archive("runtime.a".into(), archive), program()]);
+        assert_eq!(objects.len(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        match link(&[main_object()], &Script::default()) {
+            Err(LinkError::UndefinedSymbol(path, name)) => {
+                assert_eq!(path, "main.o");
+                assert_eq!(name, "print");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        match link(&[library_object(), library_object()], &Script::default()) {
+            Err(LinkError::DuplicateSymbol(_, name)) => assert_eq!(name, "print"),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let script = Script::parse(".text\n.rodata 0x100008").unwrap();
+        match link(&[main_object(), library_object()], &script) {
+            Err(LinkError::Layout(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_malformed_object() {
+        let relocation = |offset| Relocation {
+            section: ".text".into(),
+            offset,
+            symbol: "PROGRAM_START".into(),
+            addend: 0,
+        };
+        let malformed =
+            |object: ObjectFile| match link(&[("bad.o".into(), object)], &Script::default()) {
+                Err(LinkError::Relocation(path, _)) => assert_eq!(path, "bad.o"),
+                result => panic!("unexpected result {:?}", result),
+            };
+
+        // Relocation in a section the object does not have
+        malformed(ObjectFile {
+            sections: vec![],
+            symbols: vec![],
+            relocations: vec![relocation(0)],
+        });
+
+        // Relocation past the end of the section
+        let (_, object) = main_object();
+        for offset in [
+            object.section(".text").unwrap().data.len() as u32 - 2,
+            u32::MAX - 1,
+        ] {
+            let mut object = object.clone();
+            object.relocations = vec![relocation(offset)];
+            malformed(object);
+        }
+
+        // Alignments that are not a power of two or would place the
+        // section past the end of memory
+        for alignment in [0, 12, 0x8000_0000] {
+            let (_, mut object) = library_object();
+            object.sections[0].alignment = alignment;
+            assert!(ObjectFile::from_bytes(&object.to_bytes()).is_err());
+            match link(&[("bad.o".into(), object)], &Script::default()) {
+                Err(LinkError::Layout(_)) => {}
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+        let script = Script::parse(".text 0xFFFFFFF9").unwrap();
+        match link(&[main_object()], &script) {
+            Err(LinkError::Layout(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        // Symbol past the end of the address space
+        let (_, mut object) = library_object();
+        object.symbols[0].value = u32::MAX;
+        match link(&[("bad.o".into(), object)], &Script::default()) {
+            Err(LinkError::Layout(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+extern crate mycpu;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+use mycpu::common::archive::{is_archive, Archive};
+use mycpu::common::object::ObjectFile;
+use mycpu::linker::error::{LinkError, Result};
+use mycpu::linker::link::{link, select_objects, Input};
+use mycpu::linker::script::Script;
+
+const USAGE: &str = "Usage: link [-T SCRIPT] [-e SYMBOL] [-s] [-o OUTPUT] FILE.o|LIBRARY.a...";
+
+fn usage() -> LinkError {
+    LinkError::IO(io::Error::new(io::ErrorKind::InvalidInput, USAGE))
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| LinkError::IO(io::Error::new(e.kind(), format!("{}: {}", path, e))))
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let mut script = Script::default();
+    let mut entry = None;
+    let mut strip = false;
+    let mut output = "a.bin";
+    let mut inputs = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-T" {
+            let path = iter.next().ok_or_else(usage)?;
+            let text = String::from_utf8_lossy(&read(path)?).into_owned();
+            script = Script::parse(&text)?;
+        } else if arg == "-e" {
+            entry = Some(iter.next().ok_or_else(usage)?.clone());
+        } else if arg == "-s" {
+            strip = true;
+        } else if arg == "-o" {
+            output = iter.next().ok_or_else(usage)?;
+        } else {
+            let bytes = read(arg)?;
+            let invalid =
+                |e: io::Error| LinkError::IO(io::Error::new(e.kind(), format!("{}: {}", arg, e)));
+
+            inputs.push(if is_archive(&bytes) {
+                Input::Archive(arg.clone(), Archive::from_bytes(&bytes).map_err(invalid)?)
+            } else {
+                Input::Object(
+                    arg.clone(),
+                    ObjectFile::from_bytes(&bytes).map_err(invalid)?,
+                )
+            });
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err(usage());
+    }
+    if entry.is_some() {
+        script.entry = entry;
+    }
+
+    let mut image = link(&select_objects(inputs), &script)?;
+    if strip {
+        image.symbols.clear();
+        image.debug = None;
+    }
+
+    fs::write(output, image.to_bytes())
+        .map_err(|e| LinkError::IO(io::Error::new(e.kind(), format!("{}: {}", output, e))))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
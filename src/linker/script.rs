@@ -0,0 +1,165 @@
+use crate::assembler::parser::{parse_literal, Section, SECTIONS};
+use crate::common::image::StackBounds;
+use crate::emulator::constants::MEMORY_START;
+use crate::linker::error::{LinkError, Result};
+
+// A linker script lists the output sections in the order they are placed,
+// each optionally followed by its address:
+//
+//     .text 0x100000
+//     .rodata
+//     .data 0x180000
+//     .bss
+//
+// Sections without an address follow the previous section, the first one
+// starts at MEMORY_START. Sections that are not listed follow the listed
+// ones. A line `ENTRY symbol` sets the entry point, which is the start of
+// .text by default. A line `STACK low high` stores stack bounds in the
+// executable, which the emulator enforces. Text after // is ignored.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub section: Section,
+    pub address: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub placements: Vec<Placement>,
+    pub entry: Option<String>,
+    pub stack: Option<StackBounds>,
+}
+
+impl Default for Script {
+    fn default() -> Script {
+        let placements = SECTIONS
+            .iter()
+            .map(|&section| Placement {
+                section,
+                address: if section == Section::Text {
+                    Some(MEMORY_START)
+                } else {
+                    None
+                },
+            })
+            .collect();
+
+        Script {
+            placements,
+            entry: None,
+            stack: None,
+        }
+    }
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script> {
+        let mut placements: Vec<Placement> = Vec::new();
+        let mut entry = None;
+        let mut stack = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| LinkError::Script(index + 1, message);
+
+            let line = line.split("//").next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            let (name, address) = match words.as_slice() {
+                [] => continue,
+                ["ENTRY", symbol] => {
+                    entry = Some(symbol.to_string());
+                    continue;
+                }
+                ["STACK", low, high] => {
+                    let low = parse_literal(low).map_err(error)?;
+                    let high = parse_literal(high).map_err(error)?;
+                    if low > high {
+                        return Err(error("Stack bounds must be 'STACK LOW HIGH'".into()));
+                    }
+                    stack = Some(StackBounds { low, high });
+                    continue;
+                }
+                [name] => (*name, None),
+                [name, address] => (*name, Some(parse_literal(address).map_err(error)?)),
+                _ => return Err(error("Expected 'SECTION [ADDRESS]'".into())),
+            };
+
+            let section = Section::from_name(name)
+                .ok_or_else(|| error(format!("Unknown section '{}'", name)))?;
+            if placements.iter().any(|p| p.section == section) {
+                return Err(error(format!("Section {} is placed twice", name)));
+            }
+
+            placements.push(Placement { section, address });
+        }
+
+        for &section in SECTIONS.iter() {
+            if !placements.iter().any(|p| p.section == section) {
+                placements.push(Placement {
+                    section,
+                    address: None,
+                });
+            }
+        }
+
+        Ok(Script {
+            placements,
+            entry,
+            stack,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let script = Script::parse(
+            "// data lives in the upper half\n\
+             .data 0x180000\n\
+             .bss\n\
+             ENTRY main\n\
+             STACK 0x1F0000 0x1FFFFC\n\
+             .text  // code follows data\n",
+        )
+        .unwrap();
+
+        let placements: Vec<(Section, Option<u32>)> = script
+            .placements
+            .iter()
+            .map(|p| (p.section, p.address))
+            .collect();
+
+        assert_eq!(
+            placements,
+            vec![
+                (Section::Data, Some(0x18_0000)),
+                (Section::BSS, None),
+                (Section::Text, None),
+                (Section::ROData, None),
+            ]
+        );
+        assert_eq!(script.entry, Some("main".into()));
+        assert_eq!(
+            script.stack,
+            Some(StackBounds {
+                low: 0x1F_0000,
+                high: 0x1F_FFFC
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        match Script::parse(".text\n.data 0x100 extra") {
+            Err(LinkError::Script(line, _)) => assert_eq!(line, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(Script::parse(".heap").is_err());
+        assert!(Script::parse(".text\n.text").is_err());
+        assert!(Script::parse(".text zero").is_err());
+        assert!(Script::parse("STACK 0x200000 0x100000").is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use crate::assembler::codegen::{assemble_object_source, parse_source, Options};
+use crate::assembler::expression::{is_identifier_char, is_identifier_start};
+use crate::assembler::generated::matcher::MNEMONICS;
+use crate::assembler::include;
+use crate::assembler::lint::lint;
+use crate::assembler::parser::parse_register_name;
+use crate::assembler::tokenizer::{tokenize, QuoteState, SourceFile, TokenizedLine};
+
+// Source analysis for the language server. Lines and columns are zero-based
+// like positions in the protocol.
+
+// A label or constant in the source, either where it is defined or where it
+// is used
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occurrence {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    // Local labels are qualified with the preceding global label
+    pub name: String,
+    pub definition: bool,
+}
+
+impl Occurrence {
+    fn contains(&self, path: &str, line: usize, column: usize) -> bool {
+        self.path == path
+            && self.line == line
+            && (self.column..=self.column + self.length).contains(&column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub message: String,
+    // Lint warnings, everything else is an error
+    pub warning: bool,
+}
+
+// Occurrences of all symbols in a file and the files it includes
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub occurrences: Vec<Occurrence>,
+}
+
+impl Analysis {
+    pub fn new(path: &str, text: &str, include_paths: &[PathBuf]) -> Analysis {
+        let file = SourceFile::new(path);
+        let tokenize_text = || tokenize(&mut Cursor::new(text), &file);
+
+        // Broken includes are reported as diagnostics, the file itself can
+        // still be analyzed
+        let lines =
+            include::expand(tokenize_text(), include_paths).unwrap_or_else(|_| tokenize_text());
+
+        let mut analysis = Analysis::default();
+        let mut global = None;
+        for line in &lines {
+            analysis.add_line(line, &mut global);
+        }
+        analysis
+    }
+
+    fn add_line(&mut self, line: &TokenizedLine, global: &mut Option<String>) {
+        let text = &line.line.text;
+        let indent = text.len() - text.trim_start().len();
+        let first = &line.tokens[0];
+
+        let mut add = |name: &str, column: usize, definition: bool, global: &Option<String>| {
+            let qualified = match global {
+                Some(global) if name.starts_with('.') => format!("{}{}", global, name),
+                _ => name.to_string(),
+            };
+            self.occurrences.push(Occurrence {
+                path: line.line.file.path.clone(),
+                line: line.line.line_number - 1,
+                column: indent + column,
+                length: name.len(),
+                name: qualified,
+                definition,
+            });
+        };
+
+        if let Some(label) = first.token.strip_suffix(':') {
+            if label.chars().all(|c| c.is_ascii_digit()) {
+                return;
+            }
+            add(label, first.position, true, global);
+            if !label.starts_with('.') {
+                *global = Some(label.into());
+            }
+            return;
+        }
+
+        let operands = match first.token.as_str() {
+            ".include" | ".macro" | ".endm" | ".section" => return,
+            ".equ" | ".set" if line.tokens.len() > 1 => {
+                let name = &line.tokens[1];
+                add(&name.token, name.position, true, global);
+                &line.tokens[2..]
+            }
+            _ => &line.tokens[1..],
+        };
+
+        for token in operands {
+            for (offset, name) in identifiers(&token.token) {
+                if parse_register_name(name).is_none() {
+                    add(name, token.position + offset, false, global);
+                }
+            }
+        }
+    }
+
+    pub fn at(&self, path: &str, line: usize, column: usize) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|occurrence| occurrence.contains(path, line, column))
+    }
+
+    pub fn definition(&self, name: &str) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|occurrence| occurrence.definition && occurrence.name == name)
+    }
+
+    pub fn references(&self, name: &str) -> Vec<&Occurrence> {
+        self.occurrences
+            .iter()
+            .filter(|occurrence| occurrence.name == name)
+            .collect()
+    }
+
+    // Names of all defined symbols, local labels without qualification
+    pub fn symbols(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .occurrences
+            .iter()
+            .filter(|occurrence| occurrence.definition)
+            .map(|occurrence| match occurrence.name.find('.') {
+                Some(index) if index > 0 => occurrence.name[index..].to_string(),
+                _ => occurrence.name.clone(),
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+// Identifiers in an operand and their offsets, skipping numbers, literals
+// and macro parameters
+fn identifiers(operand: &str) -> Vec<(usize, &str)> {
+    let mut result = Vec::new();
+    let mut state = QuoteState::default();
+    let mut start: Option<(usize, bool)> = None;
+
+    for (index, c) in operand
+        .char_indices()
+        .chain(std::iter::once((operand.len(), ' ')))
+    {
+        let outside = state.update(c);
+
+        if let Some((word_start, skip)) = start {
+            if outside && is_identifier_char(c) {
+                continue;
+            }
+            if !skip {
+                result.push((word_start, &operand[word_start..index]));
+            }
+            start = None;
+        }
+
+        if outside && (is_identifier_start(c) || c.is_ascii_digit() || c == '\\') {
+            start = Some((index, !is_identifier_start(c)));
+        }
+    }
+
+    result
+}
+
+// The word around a position of a line, as used for hover
+pub fn word_at(text: &str, line: usize, column: usize) -> Option<&str> {
+    let line = text.lines().nth(line)?;
+    let column = column.min(line.len());
+    if !line.is_char_boundary(column) {
+        return None;
+    }
+
+    let is_word = |c: char| is_identifier_char(c);
+    let start = line[..column]
+        .rfind(|c| !is_word(c))
+        .map_or(0, |index| index + 1);
+    let end = line[start..]
+        .find(|c| !is_word(c))
+        .map_or(line.len(), |index| start + index);
+
+    if start < end {
+        Some(&line[start..end])
+    } else {
+        None
+    }
+}
+
+// Markdown description of a mnemonic and all its operand combinations
+pub fn describe_mnemonic(keyword: &str) -> Option<String> {
+    let descriptions: Vec<String> = MNEMONICS
+        .iter()
+        .filter(|mnemonic| mnemonic.keyword == keyword)
+        .map(|mnemonic| {
+            let registers = mnemonic.operands.iter().filter(|&&o| o == "reg").count();
+            let operands = mnemonic.operands.len() - registers;
+            let kind = match mnemonic.opcode {
+                Some(opcode) => format!("opcode 0x{:02X}", opcode),
+                None => "pseudo-instruction".into(),
+            };
+
+            format!(
+                "`{} {}`\n\n{} ({}), {} register{}, {} other operand{}",
+                keyword,
+                mnemonic.operands.join(", "),
+                mnemonic.description,
+                kind,
+                registers,
+                if registers == 1 { "" } else { "s" },
+                operands,
+                if operands == 1 { "" } else { "s" },
+            )
+        })
+        .collect();
+
+    if descriptions.is_empty() {
+        None
+    } else {
+        Some(descriptions.join("\n\n---\n\n"))
+    }
+}
+
+// Assembles the file into an object, so that external symbols are allowed,
+// and reports the first error. Errors in included files are reported on the
+// line of the .include directive. Files without errors are linted.
+pub fn diagnostics(path: &str, text: &str, include_paths: &[PathBuf]) -> Vec<Diagnostic> {
+    let options = Options {
+        include_paths: include_paths.to_vec(),
+        ..Options::default()
+    };
+
+    let error = match assemble_object_source(path, &mut Cursor::new(text), &options) {
+        Ok(_) => return lint_warnings(path, text, &options),
+        Err(error) => error,
+    };
+
+    let message = error.to_string();
+    let (line, message) = match error.location() {
+        Some(location) => {
+            let line = std::iter::once(location)
+                .chain(location.file.include_chain())
+                .find(|location| location.file.path == path)
+                .map_or(0, |location| location.line_number - 1);
+            let prefix = format!("{}: ", location);
+            let message = message.strip_prefix(&prefix).unwrap_or(&message);
+            (line, message.to_string())
+        }
+        None => (0, message),
+    };
+
+    vec![Diagnostic {
+        line,
+        message,
+        warning: false,
+    }]
+}
+
+// Warnings in the file itself, those in included files are reported when
+// the included file is opened
+fn lint_warnings(path: &str, text: &str, options: &Options) -> Vec<Diagnostic> {
+    let statements = match parse_source(path, &mut Cursor::new(text), options) {
+        Ok(statements) => statements,
+        Err(_) => return Vec::new(),
+    };
+
+    lint(&statements)
+        .into_iter()
+        .filter(|warning| warning.line.file.path == path)
+        .map(|warning| Diagnostic {
+            line: warning.line.line_number - 1,
+            message: format!("{} [{}]", warning.message, warning.lint.name()),
+            warning: true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+.equ COUNT, 3
+main:
+    ldi r1, COUNT // counter
+.loop:
+    dec r1
+    bne r1, r0, .loop
+    call print
+    halt
+print:
+.loop:
+    jmp .loop
+";
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(
+            identifiers("(END - 0x10) / 'a' + .x"),
+            vec![(1, "END"), (21, ".x")]
+        );
+        assert_eq!(identifiers("\\count + 1f"), vec![]);
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let analysis = Analysis::new("main.asm", SOURCE, &[]);
+
+        let count = analysis.at("main.asm", 2, 13).unwrap();
+        assert_eq!(count.name, "COUNT");
+        assert_eq!(analysis.definition("COUNT").unwrap().line, 0);
+
+        let local = analysis.at("main.asm", 5, 17).unwrap();
+        assert_eq!(local.name, "main.loop");
+        let references: Vec<usize> = analysis
+            .references("main.loop")
+            .iter()
+            .map(|occurrence| occurrence.line)
+            .collect();
+        assert_eq!(references, vec![3, 5]);
+
+        assert_eq!(analysis.definition("print.loop").unwrap().line, 9);
+        assert!(analysis.at("main.asm", 2, 9).is_none());
+        assert_eq!(analysis.symbols(), vec![".loop", "COUNT", "main", "print"]);
+    }
+
+    #[test]
+    fn test_hover() {
+        assert_eq!(word_at(SOURCE, 5, 6), Some("bne"));
+        assert_eq!(word_at(SOURCE, 5, 17), Some(".loop"));
+        assert_eq!(word_at(SOURCE, 1, 3), Some("main"));
+        assert_eq!(word_at(SOURCE, 1, 5), None);
+
+        let ldi = describe_mnemonic("ldi").unwrap();
+        assert!(ldi.contains("`ldi reg, op`"));
+        assert!(ldi.contains("1 register, 1 other operand"));
+        assert!(describe_mnemonic("not").unwrap().contains("---"));
+        assert!(describe_mnemonic("main").is_none());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let warnings: Vec<(usize, bool)> = diagnostics("main.asm", SOURCE, &[])
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.warning))
+            .collect();
+        assert_eq!(warnings, vec![(1, true), (5, true)]);
+
+        let diagnostics = diagnostics("main.asm", "nop\nldi r1, missing\n", &[]);
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                line: 1,
+                message: "Undefined symbol 'missing'".into(),
+                warning: false,
+            }]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+extern crate mycpu;
+
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+use mycpu::lsp::protocol::{read_message, write_message};
+use mycpu::lsp::server::Server;
+
+const USAGE: &str = "Usage: asm-lsp [-I DIR]...";
+
+// Speaks the language server protocol over stdin and stdout
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut include_paths = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-I" => match iter.next() {
+                Some(path) => include_paths.push(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(1);
+                }
+            },
+            // Editors commonly pass --stdio
+            "--stdio" => {}
+            _ => match arg.strip_prefix("-I") {
+                Some(path) => include_paths.push(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(1);
+                }
+            },
+        }
+    }
+
+    let mut server = Server::new(include_paths);
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => process::exit(server.exit_code()),
+            Err(e) => {
+                eprintln!("asm-lsp: {}", e);
+                continue;
+            }
+        };
+
+        if message.get("method").as_str() == Some("exit") {
+            process::exit(server.exit_code());
+        }
+
+        for reply in server.handle(&message) {
+            if let Err(e) = write_message(&mut output, &reply) {
+                eprintln!("asm-lsp: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod analysis;
+pub mod protocol;
+pub mod server;
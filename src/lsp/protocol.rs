@@ -0,0 +1,77 @@
+use std::io::{self, BufRead, Write};
+
+use crate::common::binary::invalid_data;
+use crate::common::json::Json;
+
+// Messages are JSON preceded by a header with their length in bytes:
+//
+//     Content-Length: 42\r\n
+//     \r\n
+//     {"jsonrpc":"2.0","method":"initialized",...}
+
+// Reads the next message, or returns None at the end of the input
+pub fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| invalid_data("Invalid Content-Length"))?,
+            );
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+
+    let text = String::from_utf8(body).map_err(|_| invalid_data("Message is not UTF-8"))?;
+    Json::parse(&text).map(Some).map_err(|e| invalid_data(&e))
+}
+
+pub fn write_message(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_roundtrip() {
+        let first = Json::object(vec![("id", 1usize.into()), ("method", "ä".into())]);
+        let second = Json::object(vec![("method", "exit".into())]);
+
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &first).unwrap();
+        write_message(&mut bytes, &second).unwrap();
+
+        let mut input = Cursor::new(bytes);
+        assert_eq!(read_message(&mut input).unwrap(), Some(first));
+        assert_eq!(read_message(&mut input).unwrap(), Some(second));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+
+        let mut invalid = Cursor::new("Content-Length: x\r\n\r\n{}");
+        assert!(read_message(&mut invalid).is_err());
+    }
+}
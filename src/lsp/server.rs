@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::assembler::generated::matcher::MNEMONICS;
+use crate::assembler::parser::REGISTER_NAMES;
+use crate::common::json::Json;
+use crate::lsp::analysis::{describe_mnemonic, diagnostics, word_at, Analysis, Occurrence};
+
+// Error codes of JSON-RPC and the language server protocol
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// Text document sync kind, the client always sends the full text
+const FULL_SYNC: usize = 1;
+
+// Diagnostic severities
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+
+// Completion item kinds
+const KIND_VARIABLE: usize = 6;
+const KIND_KEYWORD: usize = 14;
+const KIND_REFERENCE: usize = 18;
+
+// Language server for assembly files. Open documents are kept in memory,
+// included files are read from disk.
+#[derive(Debug, Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    include_paths: Vec<PathBuf>,
+    shutdown: bool,
+}
+
+pub fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+
+    // Decode percent escapes like %20
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = path
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn position(line: usize, character: usize) -> Json {
+    Json::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+fn location(occurrence: &Occurrence) -> Json {
+    let start = position(occurrence.line, occurrence.column);
+    let end = position(occurrence.line, occurrence.column + occurrence.length);
+
+    Json::object(vec![
+        ("uri", path_to_uri(&occurrence.path).into()),
+        ("range", Json::object(vec![("start", start), ("end", end)])),
+    ])
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn error_response(id: &Json, code: i64, message: &str) -> Json {
+    let error = Json::object(vec![
+        ("code", Json::Number(code as f64)),
+        ("message", message.into()),
+    ]);
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        ("error", error),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn completion_item(label: &str, kind: usize, detail: &str) -> Json {
+    Json::object(vec![
+        ("label", label.into()),
+        ("kind", kind.into()),
+        ("detail", detail.into()),
+    ])
+}
+
+impl Server {
+    pub fn new(include_paths: Vec<PathBuf>) -> Server {
+        Server {
+            include_paths,
+            ..Server::default()
+        }
+    }
+
+    // Exit code once the client sends exit, which is only successful after
+    // a shutdown request
+    pub fn exit_code(&self) -> i32 {
+        if self.shutdown {
+            0
+        } else {
+            1
+        }
+    }
+
+    // Handles a request or notification and returns the messages to send
+    // to the client
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+        let id = message.get("id");
+
+        // Notifications have no id and never get a response
+        if id.is_null() {
+            return self.notify(method, params);
+        }
+
+        if self.shutdown {
+            return vec![error_response(id, INVALID_REQUEST, "Server is shut down")];
+        }
+
+        let result = match method {
+            "initialize" => Some(self.initialize()),
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Json::Null)
+            }
+            "textDocument/definition" => self.with_position(params, Server::definition),
+            "textDocument/references" => self.with_position(params, |server, uri, line, column| {
+                let declaration = params.get("context").get("includeDeclaration");
+                server.references(uri, line, column, *declaration != Json::Bool(false))
+            }),
+            "textDocument/hover" => self.with_position(params, Server::hover),
+            "textDocument/completion" => self.with_position(params, Server::completion),
+            _ => {
+                return vec![error_response(
+                    id,
+                    METHOD_NOT_FOUND,
+                    &format!("Unknown method '{}'", method),
+                )]
+            }
+        };
+
+        match result {
+            Some(result) => vec![response(id, result)],
+            None => vec![error_response(id, INVALID_PARAMS, "Invalid parameters")],
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let document = params.get("textDocument");
+        let uri = match document.get("uri").as_str() {
+            Some(uri) => uri.to_string(),
+            None => return Vec::new(),
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = document.get("text").as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), text.into());
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                match changes
+                    .last()
+                    .and_then(|change| change.get("text").as_str())
+                {
+                    Some(text) => self.documents.insert(uri.clone(), text.into()),
+                    None => return Vec::new(),
+                };
+            }
+            "textDocument/didSave" => {}
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![self.publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+
+        let text = match self.documents.get(&uri) {
+            Some(text) => text,
+            None => return Vec::new(),
+        };
+        let diagnostics = diagnostics(&uri_to_path(&uri), text, &self.include_paths)
+            .into_iter()
+            .map(|diagnostic| {
+                let severity = if diagnostic.warning {
+                    SEVERITY_WARNING
+                } else {
+                    SEVERITY_ERROR
+                };
+                let range = Json::object(vec![
+                    ("start", position(diagnostic.line, 0)),
+                    ("end", position(diagnostic.line + 1, 0)),
+                ]);
+                Json::object(vec![
+                    ("range", range),
+                    ("severity", severity.into()),
+                    ("source", "asm".into()),
+                    ("message", diagnostic.message.into()),
+                ])
+            })
+            .collect();
+
+        vec![self.publish_diagnostics(&uri, diagnostics)]
+    }
+
+    fn publish_diagnostics(&self, uri: &str, diagnostics: Vec<Json>) -> Json {
+        notification(
+            "textDocument/publishDiagnostics",
+            Json::object(vec![
+                ("uri", uri.into()),
+                ("diagnostics", diagnostics.into()),
+            ]),
+        )
+    }
+
+    fn initialize(&self) -> Json {
+        let capabilities = Json::object(vec![
+            ("textDocumentSync", FULL_SYNC.into()),
+            ("definitionProvider", true.into()),
+            ("referencesProvider", true.into()),
+            ("hoverProvider", true.into()),
+            ("completionProvider", Json::object(Vec::new())),
+        ]);
+
+        Json::object(vec![
+            ("capabilities", capabilities),
+            ("serverInfo", Json::object(vec![("name", "asm-lsp".into())])),
+        ])
+    }
+
+    // Calls the handler with the document and position of a request
+    fn with_position<F>(&self, params: &Json, handler: F) -> Option<Json>
+    where
+        F: Fn(&Server, &str, usize, usize) -> Json,
+    {
+        let uri = params.get("textDocument").get("uri").as_str()?;
+        let position = params.get("position");
+        let line = position.get("line").as_u64()? as usize;
+        let column = position.get("character").as_u64()? as usize;
+        Some(handler(self, uri, line, column))
+    }
+
+    fn analysis(&self, uri: &str) -> Analysis {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        Analysis::new(&uri_to_path(uri), text, &self.include_paths)
+    }
+
+    fn definition(&self, uri: &str, line: usize, column: usize) -> Json {
+        let analysis = self.analysis(uri);
+        analysis
+            .at(&uri_to_path(uri), line, column)
+            .and_then(|occurrence| analysis.definition(&occurrence.name))
+            .map_or(Json::Null, location)
+    }
+
+    fn references(&self, uri: &str, line: usize, column: usize, declaration: bool) -> Json {
+        let analysis = self.analysis(uri);
+        let occurrence = match analysis.at(&uri_to_path(uri), line, column) {
+            Some(occurrence) => occurrence,
+            None => return Json::Null,
+        };
+
+        analysis
+            .references(&occurrence.name)
+            .into_iter()
+            .filter(|reference| declaration || !reference.definition)
+            .map(location)
+            .collect::<Vec<Json>>()
+            .into()
+    }
+
+    fn hover(&self, uri: &str, line: usize, column: usize) -> Json {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let path = uri_to_path(uri);
+
+        let contents = word_at(text, line, column)
+            .and_then(describe_mnemonic)
+            .or_else(|| {
+                let analysis = self.analysis(uri);
+                let occurrence = analysis.at(&path, line, column)?;
+                let definition = analysis.definition(&occurrence.name)?;
+                Some(format!(
+                    "`{}` defined at {}:{}",
+                    definition.name,
+                    definition.path,
+                    definition.line + 1
+                ))
+            });
+
+        match contents {
+            Some(contents) => Json::object(vec![(
+                "contents",
+                Json::object(vec![
+                    ("kind", "markdown".into()),
+                    ("value", contents.into()),
+                ]),
+            )]),
+            None => Json::Null,
+        }
+    }
+
+    fn completion(&self, uri: &str, _line: usize, _column: usize) -> Json {
+        let mut items = Vec::new();
+
+        let mut keywords: Vec<&str> = MNEMONICS.iter().map(|m| m.keyword).collect();
+        keywords.sort_unstable();
+        keywords.dedup();
+        for keyword in keywords {
+            let description = MNEMONICS
+                .iter()
+                .find(|m| m.keyword == keyword)
+                .map_or("", |m| m.description);
+            items.push(completion_item(keyword, KIND_KEYWORD, description));
+        }
+
+        for register in REGISTER_NAMES.iter() {
+            items.push(completion_item(register, KIND_VARIABLE, "Register"));
+        }
+
+        for symbol in self.analysis(uri).symbols() {
+            items.push(completion_item(&symbol, KIND_REFERENCE, "Symbol"));
+        }
+
+        items.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: usize, method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn at(uri: &str, line: usize, character: usize) -> Json {
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", uri.into())])),
+            ("position", position(line, character)),
+        ])
+    }
+
+    fn open(server: &mut Server, uri: &str, text: &str) -> Vec<Json> {
+        let document = Json::object(vec![("uri", uri.into()), ("text", text.into())]);
+        server.handle(&notification(
+            "textDocument/didOpen",
+            Json::object(vec![("textDocument", document)]),
+        ))
+    }
+
+    #[test]
+    fn test_uri() {
+        assert_eq!(uri_to_path("file:///home/a%20b/x.asm"), "/home/a b/x.asm");
+        assert_eq!(path_to_uri("/home/a b/x.asm"), "file:///home/a%20b/x.asm");
+    }
+
+    #[test]
+    fn test_session() {
+        let mut server = Server::default();
+        let uri = "file:///project/main.asm";
+
+        let initialize = server.handle(&request(1, "initialize", Json::Null));
+        let capabilities = initialize[0].get("result").get("capabilities");
+        assert_eq!(capabilities.get("hoverProvider"), &Json::Bool(true));
+
+        let published = open(
+            &mut server,
+            uri,
+            "main:\n    jmp main\n    ldi r1, nothing\n",
+        );
+        let diagnostics = published[0].get("params").get("diagnostics");
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(
+            diagnostics.as_array().unwrap()[0]
+                .get("range")
+                .get("start")
+                .get("line")
+                .as_u64(),
+            Some(2)
+        );
+
+        let definition = server.handle(&request(2, "textDocument/definition", at(uri, 1, 9)));
+        let result = definition[0].get("result");
+        assert_eq!(result.get("uri").as_str(), Some(uri));
+        assert_eq!(result.get("range").get("start"), &position(0, 0));
+
+        let references = server.handle(&request(3, "textDocument/references", at(uri, 0, 1)));
+        assert_eq!(references[0].get("result").as_array().unwrap().len(), 2);
+
+        let hover = server.handle(&request(4, "textDocument/hover", at(uri, 1, 5)));
+        let contents = hover[0].get("result").get("contents").get("value");
+        assert!(contents.as_str().unwrap().contains("`jmp op`"));
+
+        let completion = server.handle(&request(5, "textDocument/completion", at(uri, 2, 0)));
+        let labels: Vec<&str> = completion[0]
+            .get("result")
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|item| item.get("label").as_str())
+            .collect();
+        assert!(labels.contains(&"ldi"));
+        assert!(labels.contains(&"sp"));
+        assert!(labels.contains(&"main"));
+
+        let unknown = server.handle(&request(6, "workspace/unknown", Json::Null));
+        assert_eq!(
+            unknown[0].get("error").get("code"),
+            &Json::Number(METHOD_NOT_FOUND as f64)
+        );
+
+        assert_eq!(server.exit_code(), 1);
+        server.handle(&request(7, "shutdown", Json::Null));
+        assert_eq!(server.exit_code(), 0);
+    }
+}
@@ -0,0 +1,52 @@
+extern crate mycpu;
+
+use std::env;
+use std::process;
+
+use mycpu::analysis::cfg::ControlFlowGraph;
+use mycpu::analysis::stack::StackReport;
+use mycpu::common::loader::load;
+
+const USAGE: &str = "Usage: stack-depth [--limit BYTES] FILE\n\
+    \x20 Prints the worst-case stack usage of every function and of the program.\n\
+    \x20 With --limit, fails if the usage is unbounded or exceeds the limit.";
+
+// Returns whether the program fits into the limit
+fn run(args: &[String]) -> Result<bool, String> {
+    let mut limit = None;
+    let mut input = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--limit" => {
+                let bytes = iter.next().ok_or(USAGE)?;
+                limit = Some(bytes.parse::<u32>().map_err(|_| USAGE)?);
+            }
+            _ if arg.starts_with('-') || input.is_some() => return Err(USAGE.into()),
+            _ => input = Some(arg),
+        }
+    }
+
+    let graph = ControlFlowGraph::build(&load(input.ok_or(USAGE)?)?);
+    let report = StackReport::analyze(&graph);
+    print!("{}", report);
+
+    Ok(match (limit, report.total()) {
+        (None, _) => true,
+        (Some(limit), Ok(total)) => total <= limit,
+        (Some(_), Err(_)) => false,
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}